@@ -13,10 +13,10 @@
     }
     globalThis.console={
         log(...args){
-            core.print(args_to_msg(args)+'\n');
+            core.print(args_to_msg(...args)+'\n',false);
         },
         error(...args){
-            core.print(args_to_msg(args)+'\n');
+            core.print(args_to_msg(...args)+'\n',true);
         }
     };
 
@@ -43,6 +43,9 @@
         },
         readLink:(path)=>{
             return core.ops.op_read_link(path);
+        },
+        tcpRequest:(address,data='')=>{
+            return core.ops.op_tcp_request(address,data);
         }
 
         
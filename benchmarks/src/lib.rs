@@ -24,10 +24,9 @@ pub fn vlm_config() -> VlmConfig {
 pub fn vlm_wasm() {
     let cfg: VlmConfig = vlm_config();
     // Optionally, precompile the regexes and return some meaningful result.
-    let compiled: Vec<String> = cfg.rules.into_iter().map(|rule| {
+    let compiled: Vec<String> = cfg.rules.into_iter().inspect(|rule| {
     // Compile to ensure validity.
-    Regex::new(&rule).expect("Invalid regex");
-    rule
+    Regex::new(rule).expect("Invalid regex");
 }).collect();
     let result = compiled.join(",");
     JsValue::from_str(&result);
@@ -1,4 +1,8 @@
 pub mod cli;
 pub mod vs_span;
 pub mod scopes;
+pub mod auth;
+pub mod scope_resolver;
+pub mod hierarchy;
+pub mod quotas;
 
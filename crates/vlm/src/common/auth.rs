@@ -0,0 +1,103 @@
+use std::{collections::HashMap, error::Error, fs, path::Path};
+
+use serde::Deserialize;
+use vlm_macro::web::auth::{VlmAuth, VlmAuthorizer, VlmCredential, VlmDenied};
+
+use super::scopes::Scope;
+
+/// Bearer tokens and API keys mapped to the scope they grant, e.g.
+/// `{"scope": "user", "tokens": {"s3cr3t": "admin"}, "api_keys": {"k-123": "user"}}`.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct ScopeConfig {
+    /// Scope of the process itself, see `ScopeResolver::config`.
+    pub scope: Option<String>,
+    pub tokens: HashMap<String, String>,
+    pub api_keys: HashMap<String, String>,
+}
+
+impl ScopeConfig {
+    /// Reads a JSON config.
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let config = fs::read_to_string(path).map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+        serde_json::from_str(&config).map_err(|e| format!("Invalid scope config {}: {}", path.display(), e))
+    }
+}
+
+/// Resolves callers to a [`Scope`] and validates it against the scope a route requires.
+#[derive(Debug, Clone, Default)]
+pub struct ScopeAuthorizer {
+    tokens: HashMap<String, Scope>,
+    api_keys: HashMap<String, Scope>,
+}
+
+impl ScopeAuthorizer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Reads a JSON [`ScopeConfig`].
+    pub fn load(path: &Path) -> Result<Self, Box<dyn Error>> {
+        let config = ScopeConfig::load(path)?;
+        Ok(config
+            .try_into()
+            .map_err(|e| format!("Invalid scope config {}: {}", path.display(), e))?)
+    }
+
+    pub fn token(mut self, token: impl Into<String>, scope: Scope) -> Self {
+        self.tokens.insert(token.into(), scope);
+        self
+    }
+
+    pub fn api_key(mut self, key: impl Into<String>, scope: Scope) -> Self {
+        self.api_keys.insert(key.into(), scope);
+        self
+    }
+
+    /// The scope granted to `credential`, `None` when it is not configured.
+    pub fn scope(&self, credential: &VlmCredential) -> Option<&Scope> {
+        match credential {
+            VlmCredential::Bearer(token) => self.tokens.get(token),
+            VlmCredential::ApiKey(key) => self.api_keys.get(key),
+        }
+    }
+
+    /// Route requirements checked by this authorizer, for `VlmOptions::auth`.
+    pub fn routes(self) -> VlmAuth<Self> {
+        VlmAuth::new(self)
+    }
+}
+
+impl TryFrom<ScopeConfig> for ScopeAuthorizer {
+    type Error = String;
+
+    /// Fails on the first token or key mapped to an unknown scope.
+    fn try_from(config: ScopeConfig) -> Result<Self, Self::Error> {
+        let scopes = |map: HashMap<String, String>| {
+            map.into_iter()
+                .map(|(secret, scope)| Ok((secret, Scope::try_from(scope)?)))
+                .collect::<Result<HashMap<_, _>, String>>()
+        };
+        Ok(Self {
+            tokens: scopes(config.tokens)?,
+            api_keys: scopes(config.api_keys)?,
+        })
+    }
+}
+
+impl VlmAuthorizer for ScopeAuthorizer {
+    type Requirement = Scope;
+
+    fn authorize(&self, required: &Scope, credential: Option<&VlmCredential>) -> Result<(), VlmDenied> {
+        let credential = credential.ok_or_else(|| {
+            VlmDenied::Unauthorized(format!(
+                "Authentication required: this route requires scope ({:?}).",
+                required
+            ))
+        })?;
+        let scope = self
+            .scope(credential)
+            .ok_or_else(|| VlmDenied::Unauthorized("Invalid bearer token or API key.".to_string()))?;
+        scope.validate(required).map_err(VlmDenied::Forbidden)
+    }
+}
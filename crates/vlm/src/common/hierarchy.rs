@@ -0,0 +1,279 @@
+use std::{
+    cmp::Ordering,
+    collections::{BTreeMap, BTreeSet},
+    fs,
+    path::Path,
+    sync::{Arc, RwLock},
+};
+
+use once_cell::sync::Lazy;
+use serde::Deserialize;
+
+use super::scopes::Scope;
+
+static INSTALLED: Lazy<RwLock<Arc<ScopeHierarchy>>> = Lazy::new(|| RwLock::new(Arc::new(ScopeHierarchy::builtin())));
+
+/// A role as declared in config.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(default)]
+pub struct ScopeRole {
+    /// Roles whose capabilities this one includes. A role ranks above everything it inherits.
+    pub inherits: Vec<String>,
+    pub capabilities: BTreeSet<String>,
+}
+
+/// Roles added to, or replacing, the built-in ones, e.g.
+/// `{"roles": {"auditor": {"inherits": ["user"], "capabilities": ["read-logs"]}}}`.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct ScopeHierarchyConfig {
+    pub roles: BTreeMap<String, ScopeRole>,
+}
+
+/// The roles scopes are checked against, with their inheritance and capabilities.
+///
+/// Roles form a partial order: one ranks above another when it inherits from
+/// it, directly or through any of its parents, and two roles with no such path
+/// are incomparable. [`Scope::validate`] consults the hierarchy installed with
+/// [`ScopeHierarchy::install`], the built-in `admin > developer > user` chain
+/// until one is.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ScopeHierarchy {
+    roles: BTreeMap<String, ScopeRole>,
+}
+
+impl Default for ScopeHierarchy {
+    fn default() -> Self {
+        Self::builtin()
+    }
+}
+
+impl ScopeHierarchy {
+    /// `admin` inherits `developer`, which inherits `user`.
+    pub fn builtin() -> Self {
+        let role = |inherits: &[&str], capabilities: &[&str]| ScopeRole {
+            inherits: inherits.iter().map(|name| name.to_string()).collect(),
+            capabilities: capabilities.iter().map(|name| name.to_string()).collect(),
+        };
+        Self {
+            roles: BTreeMap::from([
+                ("user".to_string(), role(&[], &["read"])),
+                ("developer".to_string(), role(&["user"], &["write"])),
+                ("admin".to_string(), role(&["developer"], &["manage"])),
+            ]),
+        }
+    }
+
+    /// The built-in roles extended with `config`. Fails on unknown parents and inheritance cycles.
+    pub fn from_config(config: ScopeHierarchyConfig) -> Result<Self, String> {
+        let mut hierarchy = Self::builtin();
+        for (name, role) in config.roles {
+            let role = ScopeRole {
+                inherits: role
+                    .inherits
+                    .iter()
+                    .map(|parent| parent.trim().to_ascii_lowercase())
+                    .collect(),
+                capabilities: role.capabilities,
+            };
+            hierarchy.roles.insert(name.trim().to_ascii_lowercase(), role);
+        }
+        hierarchy.check()?;
+        Ok(hierarchy)
+    }
+
+    /// Reads a JSON [`ScopeHierarchyConfig`].
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let config = fs::read_to_string(path).map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+        let config: ScopeHierarchyConfig =
+            serde_json::from_str(&config).map_err(|e| format!("Invalid scope hierarchy {}: {}", path.display(), e))?;
+        Self::from_config(config).map_err(|e| format!("Invalid scope hierarchy {}: {}", path.display(), e))
+    }
+
+    /// Adds or replaces one role.
+    pub fn role(mut self, name: &str, inherits: &[&str], capabilities: &[&str]) -> Result<Self, String> {
+        self.roles.insert(
+            name.trim().to_ascii_lowercase(),
+            ScopeRole {
+                inherits: inherits
+                    .iter()
+                    .map(|parent| parent.trim().to_ascii_lowercase())
+                    .collect(),
+                capabilities: capabilities.iter().map(|capability| capability.to_string()).collect(),
+            },
+        );
+        self.check()?;
+        Ok(self)
+    }
+
+    /// Makes this hierarchy the one [`Scope::validate`] and scope parsing use.
+    pub fn install(self) {
+        *INSTALLED.write().unwrap() = Arc::new(self);
+    }
+
+    /// The installed hierarchy.
+    pub fn current() -> Arc<ScopeHierarchy> {
+        Arc::clone(&INSTALLED.read().unwrap())
+    }
+
+    pub fn contains(&self, name: &str) -> bool {
+        self.roles.contains_key(&name.trim().to_ascii_lowercase())
+    }
+
+    /// Role names in alphabetical order.
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.roles.keys().map(String::as_str)
+    }
+
+    /// `scope` and every role it inherits from.
+    pub fn ancestors(&self, scope: &Scope) -> BTreeSet<String> {
+        let mut seen = BTreeSet::new();
+        let mut pending = vec![scope.name().to_string()];
+        while let Some(name) = pending.pop() {
+            if let Some(role) = self.roles.get(&name) {
+                pending.extend(role.inherits.iter().filter(|parent| !seen.contains(*parent)).cloned());
+            }
+            seen.insert(name);
+        }
+        seen
+    }
+
+    /// Capabilities of `scope`, including the inherited ones.
+    pub fn capabilities(&self, scope: &Scope) -> BTreeSet<String> {
+        self.ancestors(scope)
+            .iter()
+            .filter_map(|name| self.roles.get(name))
+            .flat_map(|role| role.capabilities.iter().cloned())
+            .collect()
+    }
+
+    /// Capabilities `required` has and `scope` lacks.
+    pub fn missing_capabilities(&self, scope: &Scope, required: &Scope) -> BTreeSet<String> {
+        let granted = self.capabilities(scope);
+        self.capabilities(required)
+            .into_iter()
+            .filter(|capability| !granted.contains(capability))
+            .collect()
+    }
+
+    /// `Greater` when `a` inherits from `b`, `Less` the other way round, `None` when unrelated.
+    pub fn compare(&self, a: &Scope, b: &Scope) -> Option<Ordering> {
+        match (
+            a == b,
+            self.ancestors(a).contains(b.name()),
+            self.ancestors(b).contains(a.name()),
+        ) {
+            (true, _, _) => Some(Ordering::Equal),
+            (_, true, _) => Some(Ordering::Greater),
+            (_, _, true) => Some(Ordering::Less),
+            _ => None,
+        }
+    }
+
+    /// Whether `scope` meets `required`: it ranks at or above it, or holds every
+    /// one of its capabilities when `required` declares any.
+    pub fn validate(&self, scope: &Scope, required: &Scope) -> Result<(), String> {
+        let missing = self.missing_capabilities(scope, required);
+        let ranks = matches!(self.compare(scope, required), Some(Ordering::Greater | Ordering::Equal));
+        let covers = missing.is_empty() && !self.capabilities(required).is_empty();
+        if ranks || covers {
+            return Ok(());
+        }
+        let denied = format!(
+            "Permission denied: Current scope ({}) does not meet required scope ({}).",
+            scope, required
+        );
+        match missing.is_empty() {
+            true => Err(denied),
+            false => Err(format!(
+                "{} Missing capability: {}.",
+                denied,
+                missing.into_iter().collect::<Vec<_>>().join(", ")
+            )),
+        }
+    }
+
+    /// Whether `scope` holds `capability`, directly or through inheritance.
+    pub fn require_capability(&self, scope: &Scope, capability: &str) -> Result<(), String> {
+        match self.capabilities(scope).contains(capability) {
+            true => Ok(()),
+            false => Err(format!(
+                "Permission denied: Current scope ({}) lacks capability ({}).",
+                scope, capability
+            )),
+        }
+    }
+
+    /// Every parent is declared and no role inherits from itself.
+    fn check(&self) -> Result<(), String> {
+        for (name, role) in &self.roles {
+            if let Some(parent) = role.inherits.iter().find(|parent| !self.roles.contains_key(*parent)) {
+                return Err(format!("Role {} inherits unknown role {}", name, parent));
+            }
+        }
+        // Every parent is known from here on.
+        for (name, role) in &self.roles {
+            let mut pending = role.inherits.clone();
+            let mut seen = BTreeSet::new();
+            while let Some(parent) = pending.pop() {
+                if parent == *name {
+                    return Err(format!("Role {} inherits from itself", name));
+                }
+                if seen.insert(parent.clone()) {
+                    pending.extend(self.roles[&parent].inherits.iter().cloned());
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(json: &str) -> Result<ScopeHierarchy, String> {
+        ScopeHierarchy::from_config(serde_json::from_str(json).unwrap())
+    }
+
+    #[test]
+    fn unknown_parents_are_rejected_at_any_depth() {
+        let error = config(r#"{"roles":{"a":{"inherits":["b"]},"b":{"inherits":["zzz"]}}}"#).unwrap_err();
+        assert_eq!(error, "Role b inherits unknown role zzz");
+        assert!(config(r#"{"roles":{"a":{"inherits":["zzz"]}}}"#).is_err());
+    }
+
+    #[test]
+    fn cycles_are_rejected() {
+        let error = config(r#"{"roles":{"a":{"inherits":["b"]},"b":{"inherits":["a"]}}}"#).unwrap_err();
+        assert!(error.ends_with("inherits from itself"), "{}", error);
+    }
+
+    #[test]
+    fn custom_roles_are_partially_ordered() {
+        let hierarchy = config(
+            r#"{"roles":{"auditor":{"inherits":["user"],"capabilities":["read-logs"]},"ops":{"inherits":["user"]}}}"#,
+        )
+        .unwrap();
+        let (auditor, ops) = (Scope::named("auditor"), Scope::named("ops"));
+        assert_eq!(hierarchy.compare(&auditor, &Scope::User), Some(Ordering::Greater));
+        assert_eq!(hierarchy.compare(&auditor, &ops), None);
+        assert!(hierarchy.validate(&auditor, &Scope::User).is_ok());
+        assert!(hierarchy.validate(&ops, &auditor).is_err());
+        assert!(hierarchy.require_capability(&auditor, "read").is_ok());
+    }
+
+    #[test]
+    fn built_in_names_are_the_built_in_scopes() {
+        let hierarchy = ScopeHierarchy::builtin();
+        assert_eq!(Scope::named(" Admin "), Scope::Admin);
+        assert_eq!(Scope::Custom("admin".to_string()), Scope::Admin);
+        assert_eq!(
+            hierarchy.compare(&Scope::Custom("admin".to_string()), &Scope::Admin),
+            Some(Ordering::Equal)
+        );
+        assert!(Scope::Admin > Scope::Developer && Scope::Developer > Scope::User);
+        assert_eq!(Scope::named("auditor").partial_cmp(&Scope::named("ops")), None);
+        assert_eq!(Scope::named("auditor").partial_cmp(&Scope::User), None);
+    }
+}
@@ -0,0 +1,427 @@
+use std::{
+    collections::HashMap,
+    fmt,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use sha2::{Digest, Sha256};
+use vlm_macro::{
+    common::tasks::VLMGenericTaskExecutor,
+    web::auth::{VlmAuth, VlmAuthorizer, VlmCredential, VlmDenied, VlmRequest},
+};
+
+use super::{
+    auth::ScopeAuthorizer,
+    scopes::{Scope, VLMScopeSpec},
+};
+
+/// Length of the window `VLMScopeSpec::Public` limits are counted over by default.
+pub const DEFAULT_WINDOW: Duration = Duration::from_secs(60);
+/// How long the lifetime counters of an anonymous `ip:` caller are kept after its last call by default.
+pub const DEFAULT_ANONYMOUS_IDLE: Duration = Duration::from_secs(60 * 60);
+
+/// Who a quota is charged to.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct VLMCaller {
+    pub principal: String,
+    pub scope: Scope,
+}
+
+impl VLMCaller {
+    pub fn new(principal: impl Into<String>, scope: Scope) -> Self {
+        Self {
+            principal: principal.into(),
+            scope,
+        }
+    }
+}
+
+/// Why a spec refused a call.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum VLMQuotaError {
+    /// A `Count` or `Private` quota is used up.
+    Exhausted { key: String, limit: i64 },
+    /// A `Public` rate limit is reached until the window reopens.
+    RateLimited {
+        key: String,
+        limit: i64,
+        retry_after: Duration,
+    },
+    /// The caller's scope does not pass a `Private` spec.
+    Denied(String),
+}
+
+impl fmt::Display for VLMQuotaError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            VLMQuotaError::Exhausted { key, limit } => write!(f, "Quota {} exhausted: {} calls allowed.", key, limit),
+            VLMQuotaError::RateLimited {
+                key,
+                limit,
+                retry_after,
+            } => write!(
+                f,
+                "Rate limit {} reached: {} calls per window, retry in {:.1}s.",
+                key,
+                limit,
+                retry_after.as_secs_f64()
+            ),
+            VLMQuotaError::Denied(message) => f.write_str(message),
+        }
+    }
+}
+
+impl std::error::Error for VLMQuotaError {}
+
+impl From<VLMQuotaError> for VlmDenied {
+    fn from(error: VLMQuotaError) -> Self {
+        match error {
+            VLMQuotaError::Denied(message) => VlmDenied::Forbidden(message),
+            VLMQuotaError::RateLimited { retry_after, .. } => VlmDenied::TooManyRequests {
+                message: error.to_string(),
+                retry_after: Some(retry_after),
+            },
+            VLMQuotaError::Exhausted { .. } => VlmDenied::TooManyRequests {
+                message: error.to_string(),
+                retry_after: None,
+            },
+        }
+    }
+}
+
+/// What is left of a quota after a check.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct VLMQuotaUsage {
+    pub used: i64,
+    /// `None` for unlimited specs.
+    pub remaining: Option<i64>,
+}
+
+#[derive(Debug)]
+struct Counter {
+    used: i64,
+    window_start: Instant,
+    /// Whether the counter restarts with each window, so it can be dropped once one passes.
+    windowed: bool,
+    last_used: Instant,
+}
+
+#[derive(Debug)]
+struct Counters {
+    entries: HashMap<(String, String), Counter>,
+    /// When expired windowed and idle anonymous counters were last dropped.
+    swept: Instant,
+}
+
+/// Thread-safe counters enforcing [`VLMScopeSpec`]s per principal.
+///
+/// Each `(principal, key)` pair has its own counter, so the same spec can guard
+/// several resources under different keys. `check` only looks, `consume`
+/// charges the call and, for `Private` specs, runs the callback. Counters of
+/// `Public` specs are dropped once their window passes, and every counter of an
+/// anonymous `ip:` principal once it has been idle for a while, so one-off callers
+/// do not accumulate.
+#[derive(Debug)]
+pub struct VLMQuotas {
+    window: Duration,
+    anonymous_idle: Duration,
+    private_scope: Scope,
+    counters: Mutex<Counters>,
+}
+
+impl Default for VLMQuotas {
+    fn default() -> Self {
+        Self {
+            window: DEFAULT_WINDOW,
+            anonymous_idle: DEFAULT_ANONYMOUS_IDLE,
+            private_scope: Scope::Admin,
+            counters: Mutex::new(Counters {
+                entries: HashMap::new(),
+                swept: Instant::now(),
+            }),
+        }
+    }
+}
+
+impl VLMQuotas {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The window `Public` limits are counted over.
+    pub fn with_window(mut self, window: Duration) -> Self {
+        self.window = window;
+        self
+    }
+
+    /// How long counters of anonymous `ip:` callers outlive their last call.
+    ///
+    /// Dropping them resets `Count` and `Private` quotas of the address, which is
+    /// the price of not keeping one counter per address ever seen.
+    pub fn with_anonymous_idle(mut self, idle: Duration) -> Self {
+        self.anonymous_idle = idle;
+        self
+    }
+
+    /// The scope callers need for `Private` specs, `Admin` by default.
+    pub fn with_private_scope(mut self, scope: Scope) -> Self {
+        self.private_scope = scope;
+        self
+    }
+
+    /// Whether `caller` may make a call under `spec` right now, without charging it.
+    pub fn check(&self, caller: &VLMCaller, key: &str, spec: &VLMScopeSpec) -> Result<VLMQuotaUsage, VLMQuotaError> {
+        self.apply(caller, key, spec, false)
+    }
+
+    /// Charges one call to `caller` under `spec`, running the callback of a `Private` spec.
+    pub fn consume(&self, caller: &VLMCaller, key: &str, spec: &VLMScopeSpec) -> Result<VLMQuotaUsage, VLMQuotaError> {
+        let usage = self.apply(caller, key, spec, true)?;
+        if let VLMScopeSpec::Private(callback, _) = spec {
+            callback();
+        }
+        Ok(usage)
+    }
+
+    /// Consumes `spec`, then runs `task` on `executor`.
+    pub fn execute<E, F, T>(
+        &self,
+        executor: &E,
+        caller: &VLMCaller,
+        key: &str,
+        spec: &VLMScopeSpec,
+        task: F,
+    ) -> Result<T, String>
+    where
+        E: VLMGenericTaskExecutor,
+        F: FnOnce() -> T,
+    {
+        self.consume(caller, key, spec).map_err(|e| e.to_string())?;
+        executor.execute_task(task)
+    }
+
+    /// Forgets the counters of `principal`, or of everyone.
+    pub fn reset(&self, principal: Option<&str>) {
+        let mut counters = self.counters.lock().unwrap();
+        match principal {
+            Some(principal) => counters.entries.retain(|(owner, _), _| owner != principal),
+            None => counters.entries.clear(),
+        }
+    }
+
+    /// A web guard charging routes declared with [`VlmAuth::require`] to the caller.
+    ///
+    /// Callers are identified by their bearer token or API key through `scopes`,
+    /// anonymous ones by remote address with the `User` scope. Principals hold
+    /// a SHA-256 digest of the secret, never the secret itself.
+    pub fn routes(self: Arc<Self>, scopes: ScopeAuthorizer) -> VlmAuth<VLMQuotaAuthorizer> {
+        VlmAuth::new(VLMQuotaAuthorizer { quotas: self, scopes })
+    }
+
+    fn apply(
+        &self,
+        caller: &VLMCaller,
+        key: &str,
+        spec: &VLMScopeSpec,
+        consume: bool,
+    ) -> Result<VLMQuotaUsage, VLMQuotaError> {
+        let (limit, windowed) = match spec {
+            VLMScopeSpec::Public(limit) => (*limit as i64, true),
+            VLMScopeSpec::Private(_, limit) => {
+                caller
+                    .scope
+                    .validate(&self.private_scope)
+                    .map_err(VLMQuotaError::Denied)?;
+                (*limit, false)
+            }
+            VLMScopeSpec::Count(limit) => (*limit as i64, false),
+        };
+        let now = Instant::now();
+        let mut counters = self.counters.lock().unwrap();
+        if now.duration_since(counters.swept) >= self.window.min(self.anonymous_idle) {
+            let (window, idle) = (self.window, self.anonymous_idle);
+            counters.entries.retain(|(principal, _), counter| {
+                let expired = counter.windowed && now.duration_since(counter.window_start) >= window;
+                let stale = principal.starts_with("ip:") && now.duration_since(counter.last_used) >= idle;
+                !expired && !stale
+            });
+            counters.swept = now;
+        }
+        let counter = counters
+            .entries
+            .entry((caller.principal.clone(), key.to_string()))
+            .or_insert(Counter {
+                used: 0,
+                window_start: now,
+                windowed,
+                last_used: now,
+            });
+        counter.last_used = now;
+        if windowed && now.duration_since(counter.window_start) >= self.window {
+            counter.used = 0;
+            counter.window_start = now;
+        }
+        if limit >= 0 && counter.used >= limit {
+            return Err(match windowed {
+                true => VLMQuotaError::RateLimited {
+                    key: key.to_string(),
+                    limit,
+                    retry_after: self.window.saturating_sub(now.duration_since(counter.window_start)),
+                },
+                false => VLMQuotaError::Exhausted {
+                    key: key.to_string(),
+                    limit,
+                },
+            });
+        }
+        if consume {
+            counter.used += 1;
+        }
+        Ok(VLMQuotaUsage {
+            used: counter.used,
+            remaining: (limit >= 0).then(|| limit - counter.used),
+        })
+    }
+}
+
+/// A quota a route charges, keyed so routes can share or split counters.
+#[derive(Debug, Clone)]
+pub struct VLMQuotaRoute {
+    pub key: String,
+    pub spec: VLMScopeSpec<'static>,
+}
+
+impl VLMQuotaRoute {
+    pub fn new(key: impl Into<String>, spec: VLMScopeSpec<'static>) -> Self {
+        Self { key: key.into(), spec }
+    }
+}
+
+/// Charges web requests to [`VLMQuotas`], see [`VLMQuotas::routes`].
+#[derive(Debug)]
+pub struct VLMQuotaAuthorizer {
+    quotas: Arc<VLMQuotas>,
+    scopes: ScopeAuthorizer,
+}
+
+impl VlmAuthorizer for VLMQuotaAuthorizer {
+    type Requirement = VLMQuotaRoute;
+
+    fn authorize(&self, required: &VLMQuotaRoute, credential: Option<&VlmCredential>) -> Result<(), VlmDenied> {
+        let caller = match credential {
+            Some(credential) => self.caller(credential)?,
+            None => VLMCaller::new("anonymous", Scope::User),
+        };
+        self.charge(&caller, required)
+    }
+
+    fn authorize_request(&self, required: &VLMQuotaRoute, request: &VlmRequest) -> Result<(), VlmDenied> {
+        let caller = match (&request.credential, request.remote) {
+            (Some(credential), _) => self.caller(credential)?,
+            (None, Some(remote)) => VLMCaller::new(format!("ip:{}", remote.ip()), Scope::User),
+            (None, None) => VLMCaller::new("anonymous", Scope::User),
+        };
+        self.charge(&caller, required)
+    }
+}
+
+impl VLMQuotaAuthorizer {
+    fn caller(&self, credential: &VlmCredential) -> Result<VLMCaller, VlmDenied> {
+        let scope = self
+            .scopes
+            .scope(credential)
+            .ok_or_else(|| VlmDenied::Unauthorized("Invalid bearer token or API key.".to_string()))?;
+        let principal = match credential {
+            VlmCredential::Bearer(token) => format!("bearer:{}", digest(token)),
+            VlmCredential::ApiKey(key) => format!("key:{}", digest(key)),
+        };
+        Ok(VLMCaller::new(principal, scope.clone()))
+    }
+
+    fn charge(&self, caller: &VLMCaller, route: &VLMQuotaRoute) -> Result<(), VlmDenied> {
+        self.quotas.consume(caller, &route.key, &route.spec)?;
+        Ok(())
+    }
+}
+
+/// Hex SHA-256 of a secret, standing in for it in principals.
+fn digest(secret: &str) -> String {
+    Sha256::digest(secret.as_bytes())
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread;
+
+    use super::*;
+
+    #[test]
+    fn public_limits_reopen_with_the_window() {
+        let quotas = VLMQuotas::new().with_window(Duration::from_millis(50));
+        let caller = VLMCaller::new("ip:127.0.0.1", Scope::User);
+        let spec = VLMScopeSpec::Public(1);
+        assert!(quotas.consume(&caller, "api", &spec).is_ok());
+        assert!(matches!(
+            quotas.consume(&caller, "api", &spec),
+            Err(VLMQuotaError::RateLimited { .. })
+        ));
+        thread::sleep(Duration::from_millis(60));
+        assert!(quotas.consume(&caller, "api", &spec).is_ok());
+    }
+
+    #[test]
+    fn expired_windows_are_evicted() {
+        let quotas = VLMQuotas::new().with_window(Duration::from_millis(50));
+        for i in 0..100 {
+            let caller = VLMCaller::new(format!("ip:10.0.0.{}", i), Scope::User);
+            quotas.consume(&caller, "api", &VLMScopeSpec::Public(10)).unwrap();
+        }
+        let admin = VLMCaller::new("key:admin", Scope::Admin);
+        quotas.consume(&admin, "total", &VLMScopeSpec::Count(1)).unwrap();
+        assert_eq!(quotas.counters.lock().unwrap().entries.len(), 101);
+        thread::sleep(Duration::from_millis(60));
+        let caller = VLMCaller::new("ip:10.0.1.1", Scope::User);
+        quotas.consume(&caller, "api", &VLMScopeSpec::Public(10)).unwrap();
+        // Lifetime quotas survive the sweep.
+        assert_eq!(quotas.counters.lock().unwrap().entries.len(), 2);
+        assert!(quotas.consume(&admin, "total", &VLMScopeSpec::Count(1)).is_err());
+    }
+
+    #[test]
+    fn idle_anonymous_counters_are_evicted() {
+        let quotas = VLMQuotas::new().with_anonymous_idle(Duration::from_millis(50));
+        for i in 0..100 {
+            let caller = VLMCaller::new(format!("ip:10.0.0.{}", i), Scope::User);
+            quotas.consume(&caller, "total", &VLMScopeSpec::Count(1)).unwrap();
+        }
+        let key = VLMCaller::new("key:someone", Scope::User);
+        quotas.consume(&key, "total", &VLMScopeSpec::Count(1)).unwrap();
+        assert_eq!(quotas.counters.lock().unwrap().entries.len(), 101);
+        thread::sleep(Duration::from_millis(60));
+        let caller = VLMCaller::new("ip:10.0.1.1", Scope::User);
+        quotas.consume(&caller, "total", &VLMScopeSpec::Count(1)).unwrap();
+        assert_eq!(quotas.counters.lock().unwrap().entries.len(), 2);
+        // Callers with a credential keep their lifetime quota.
+        assert!(quotas.consume(&key, "total", &VLMScopeSpec::Count(1)).is_err());
+    }
+
+    #[test]
+    fn principals_do_not_hold_secrets() {
+        let quotas = Arc::new(VLMQuotas::new());
+        let guard = quotas
+            .clone()
+            .routes(ScopeAuthorizer::new().token("s3cr3t-token", Scope::User))
+            .require("/api/*rest", VLMQuotaRoute::new("api", VLMScopeSpec::Count(5)))
+            .into_guard();
+        let mut headers = warp::http::HeaderMap::new();
+        headers.insert("Authorization", "Bearer s3cr3t-token".parse().unwrap());
+        guard.check(&VlmRequest::new("/api/x", &headers, None)).unwrap();
+        let debug = format!("{:?}", quotas);
+        assert!(debug.contains("bearer:"), "{}", debug);
+        assert!(!debug.contains("s3cr3t-token"), "{}", debug);
+    }
+}
@@ -0,0 +1,248 @@
+use std::{env, fmt, path::PathBuf};
+
+use vlm_macro::web::auth::VlmCredential;
+
+use super::{
+    auth::{ScopeAuthorizer, ScopeConfig},
+    scopes::Scope,
+};
+
+/// Environment variable naming the scope of the process.
+pub const SCOPE_ENV: &str = "VLM_SCOPE";
+/// Environment variable pointing at a JSON [`ScopeConfig`] whose `scope` applies.
+pub const SCOPE_CONFIG_ENV: &str = "VLM_SCOPE_CONFIG";
+/// Command-line flag naming the scope, as `--scope admin` or `--scope=admin`.
+pub const SCOPE_FLAG: &str = "--scope";
+
+/// Where a resolved scope came from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ScopeSource {
+    Cli,
+    Token,
+    Env,
+    Config,
+    /// No source named a scope.
+    Default,
+}
+
+impl fmt::Display for ScopeSource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            ScopeSource::Cli => "command-line flag",
+            ScopeSource::Token => "request token",
+            ScopeSource::Env => "environment variable",
+            ScopeSource::Config => "config file",
+            ScopeSource::Default => "default",
+        })
+    }
+}
+
+/// A scope together with the source that decided it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ResolvedScope {
+    pub scope: Scope,
+    pub source: ScopeSource,
+}
+
+impl fmt::Display for ResolvedScope {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} (from {})", self.scope.name(), self.source)
+    }
+}
+
+/// Resolves a [`Scope`] from several sources in a fixed precedence.
+///
+/// Sources are consulted in [`ScopeResolver::DEFAULT_PRECEDENCE`] order unless
+/// [`ScopeResolver::precedence`] says otherwise; the first one naming a scope
+/// decides. A request token always comes first: the flag, environment and config
+/// file set the scope of the process, and must not hand it to every caller. A source naming an unknown scope, or an unknown token, fails the
+/// resolution instead of falling through. Without any source the scope is
+/// [`Scope::User`], the least privileged one.
+#[derive(Debug, Clone)]
+pub struct ScopeResolver {
+    precedence: Vec<ScopeSource>,
+    cli: Option<String>,
+    token: Option<Result<Scope, String>>,
+    env_var: Option<String>,
+    config: Option<PathBuf>,
+    default: Scope,
+}
+
+impl Default for ScopeResolver {
+    fn default() -> Self {
+        Self {
+            precedence: Self::DEFAULT_PRECEDENCE.to_vec(),
+            cli: None,
+            token: None,
+            env_var: None,
+            config: None,
+            default: Scope::User,
+        }
+    }
+}
+
+impl ScopeResolver {
+    /// The caller's token wins, then an explicit flag, then the environment, then the config file.
+    pub const DEFAULT_PRECEDENCE: [ScopeSource; 4] = [
+        ScopeSource::Token,
+        ScopeSource::Cli,
+        ScopeSource::Env,
+        ScopeSource::Config,
+    ];
+
+    /// A resolver with no sources, always resolving to the default scope.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// A resolver reading the process arguments, [`SCOPE_ENV`] and the config
+    /// file named by [`SCOPE_CONFIG_ENV`].
+    pub fn from_env() -> Self {
+        let resolver = Self::new().cli_args(env::args().skip(1)).env_var(SCOPE_ENV);
+        match env::var_os(SCOPE_CONFIG_ENV) {
+            Some(path) if !path.is_empty() => resolver.config(path.into()),
+            _ => resolver,
+        }
+    }
+
+    /// The order sources are consulted in. Sources left out are ignored, and
+    /// [`ScopeSource::Token`] moves ahead of the others.
+    pub fn precedence(mut self, precedence: &[ScopeSource]) -> Self {
+        self.precedence = precedence.to_vec();
+        self.precedence.sort_by_key(|source| *source != ScopeSource::Token);
+        self
+    }
+
+    /// The scope given on the command line.
+    pub fn cli(mut self, scope: impl Into<String>) -> Self {
+        self.cli = Some(scope.into());
+        self
+    }
+
+    /// Picks the scope flag out of command-line arguments.
+    pub fn cli_args<I: IntoIterator<Item = String>>(mut self, args: I) -> Self {
+        let mut args = args.into_iter();
+        while let Some(arg) = args.next() {
+            if arg == SCOPE_FLAG {
+                self.cli = args.next();
+            } else if let Some(scope) = arg.strip_prefix(SCOPE_FLAG).and_then(|rest| rest.strip_prefix('=')) {
+                self.cli = Some(scope.to_string());
+            }
+        }
+        self
+    }
+
+    /// The scope `authorizer` grants to the caller's bearer token or API key.
+    pub fn token(mut self, credential: &VlmCredential, authorizer: &ScopeAuthorizer) -> Self {
+        self.token = Some(
+            authorizer
+                .scope(credential)
+                .cloned()
+                .ok_or_else(|| "Invalid bearer token or API key.".to_string()),
+        );
+        self
+    }
+
+    /// Reads the scope from environment variable `name`. Unset or empty variables are skipped.
+    pub fn env_var(mut self, name: impl Into<String>) -> Self {
+        self.env_var = Some(name.into());
+        self
+    }
+
+    /// Reads the `scope` of the JSON [`ScopeConfig`] at `path`.
+    pub fn config(mut self, path: PathBuf) -> Self {
+        self.config = Some(path);
+        self
+    }
+
+    /// The scope used when no source names one.
+    pub fn default_scope(mut self, scope: Scope) -> Self {
+        self.default = scope;
+        self
+    }
+
+    pub fn resolve(&self) -> Result<ResolvedScope, String> {
+        for source in &self.precedence {
+            if let Some(scope) = self.lookup(*source)? {
+                return Ok(ResolvedScope { scope, source: *source });
+            }
+        }
+        Ok(ResolvedScope {
+            scope: self.default.clone(),
+            source: ScopeSource::Default,
+        })
+    }
+
+    /// The scope `source` names, if any.
+    fn lookup(&self, source: ScopeSource) -> Result<Option<Scope>, String> {
+        match source {
+            ScopeSource::Cli => self
+                .cli
+                .as_deref()
+                .map(|scope| Scope::try_from(scope).map_err(|e| format!("Invalid {} flag: {}", SCOPE_FLAG, e)))
+                .transpose(),
+            ScopeSource::Token => self.token.clone().transpose(),
+            ScopeSource::Env => {
+                let name = match &self.env_var {
+                    Some(name) => name,
+                    None => return Ok(None),
+                };
+                match env::var(name) {
+                    Ok(scope) if !scope.trim().is_empty() => Scope::try_from(scope)
+                        .map(Some)
+                        .map_err(|e| format!("Invalid {}: {}", name, e)),
+                    _ => Ok(None),
+                }
+            }
+            ScopeSource::Config => {
+                let path = match &self.config {
+                    Some(path) => path,
+                    None => return Ok(None),
+                };
+                ScopeConfig::load(path)?
+                    .scope
+                    .map(|scope| {
+                        Scope::try_from(scope).map_err(|e| format!("Invalid scope in {}: {}", path.display(), e))
+                    })
+                    .transpose()
+            }
+            ScopeSource::Default => Ok(Some(self.default.clone())),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn authorizer() -> ScopeAuthorizer {
+        ScopeAuthorizer::new().token("t-user", Scope::User)
+    }
+
+    #[test]
+    fn request_tokens_win_over_the_process_scope() {
+        let credential = VlmCredential::Bearer("t-user".to_string());
+        let resolver = ScopeResolver::new().cli("admin").token(&credential, &authorizer());
+        let resolved = resolver.resolve().unwrap();
+        assert_eq!((resolved.scope, resolved.source), (Scope::User, ScopeSource::Token));
+        let reordered = resolver.precedence(&[ScopeSource::Cli, ScopeSource::Token]);
+        assert_eq!(reordered.resolve().unwrap().source, ScopeSource::Token);
+    }
+
+    #[test]
+    fn unknown_tokens_fail_instead_of_falling_back() {
+        let credential = VlmCredential::ApiKey("nope".to_string());
+        let resolver = ScopeResolver::new().cli("admin").token(&credential, &authorizer());
+        assert!(resolver.resolve().is_err());
+    }
+
+    #[test]
+    fn process_sources_apply_without_a_token() {
+        let args = ["--scope=admin".to_string()];
+        let resolved = ScopeResolver::new().cli_args(args).resolve().unwrap();
+        assert_eq!((resolved.scope, resolved.source), (Scope::Admin, ScopeSource::Cli));
+        let resolved = ScopeResolver::new().resolve().unwrap();
+        assert_eq!((resolved.scope, resolved.source), (Scope::User, ScopeSource::Default));
+        assert!(ScopeResolver::new().cli("root").resolve().is_err());
+    }
+}
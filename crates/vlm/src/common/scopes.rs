@@ -1,46 +1,102 @@
+use std::fmt;
+
+use super::{
+    hierarchy::ScopeHierarchy,
+    scope_resolver::{ResolvedScope, ScopeResolver},
+};
 
 #[derive(Debug, Clone)]
 pub enum Scope {
     Admin,
     Developer,
     User,
+    /// A role declared in the installed [`ScopeHierarchy`], by lowercase name.
+    Custom(String),
 }
 
+/// A quota enforced by [`VLMQuotas`](super::quotas::VLMQuotas). Negative limits mean unlimited.
 #[derive(Clone)]
 pub enum VLMScopeSpec<'a> {
+    /// At most this many calls per principal and rate-limit window.
     Public(i32),
-    Private(&'a dyn Fn(),i64),
+    /// A callback run only for callers whose scope passes, at most this many times per principal.
+    Private(&'a (dyn Fn() + Send + Sync),i64),
+    /// At most this many calls per principal in total.
     Count(isize),
 }
 
 
 
-impl From<String> for Scope {
-    fn from(scope: String) -> Self {
-        match scope.as_str() {
+impl TryFrom<&str> for Scope {
+    type Error = String;
+
+    fn try_from(scope: &str) -> Result<Self, Self::Error> {
+        let hierarchy = ScopeHierarchy::current();
+        match Scope::named(scope) {
+            Scope::Custom(name) if !hierarchy.contains(&name) => Err(format!(
+                "Unknown scope ({}): expected one of {}.",
+                scope,
+                hierarchy.names().collect::<Vec<_>>().join(", ")
+            )),
+            scope => Ok(scope),
+        }
+    }
+}
+
+impl TryFrom<String> for Scope {
+    type Error = String;
+
+    fn try_from(scope: String) -> Result<Self, Self::Error> {
+        Scope::try_from(scope.as_str())
+    }
+}
+
+impl Scope {
+    /// Resolves the scope of this process from the CLI flag, environment and
+    /// config file, see [`ScopeResolver::from_env`].
+    pub fn current() -> Result<ResolvedScope, String> {
+        ScopeResolver::from_env().resolve()
+    }
+
+    /// The scope for a role name, built-in roles included, whether or not the
+    /// installed hierarchy declares it.
+    pub fn named(name: &str) -> Scope {
+        let name = name.trim().to_ascii_lowercase();
+        match name.as_str() {
             "admin" => Scope::Admin,
             "developer" => Scope::Developer,
             "user" => Scope::User,
-            _ => Scope::User, // Default case if scope is invalid
+            _ => Scope::Custom(name),
         }
     }
-}
 
-impl Scope {
-    // A method to simulate checking the current scope
-    pub fn current() -> Self {
-        Scope::Developer // Example, assuming the current scope is Developer
+    pub fn name(&self) -> &str {
+        match self {
+            Scope::Admin => "admin",
+            Scope::Developer => "developer",
+            Scope::User => "user",
+            Scope::Custom(name) => name,
+        }
     }
 
-    // Validation function for checking the scope
+    // Validation function for checking the scope against the installed hierarchy
     pub fn validate(&self, required: &Scope) -> Result<(), String> {
-        if *self >= *required {
-            Ok(())
-        } else {
-            Err(format!(
-                "Permission denied: Current scope ({:?}) does not meet required scope ({:?}).",
-                self, required
-            ))
+        ScopeHierarchy::current().validate(self, required)
+    }
+
+    /// Checks `capability` is granted to this scope by the installed hierarchy.
+    pub fn require_capability(&self, capability: &str) -> Result<(), String> {
+        ScopeHierarchy::current().require_capability(self, capability)
+    }
+}
+
+impl fmt::Display for Scope {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Scope::Admin => f.write_str("Admin"),
+            Scope::Developer => f.write_str("Developer"),
+            Scope::User => f.write_str("User"),
+            Scope::Custom(name) => f.write_str(name),
         }
     }
 }
@@ -50,6 +106,16 @@ impl Eq for Scope {}
 
 // impliment vlm scope spec
 
+impl<'a> fmt::Debug for VLMScopeSpec<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Public(limit) => f.debug_tuple("Public").field(limit).finish(),
+            Self::Private(_, limit) => f.debug_tuple("Private").field(&"..").field(limit).finish(),
+            Self::Count(limit) => f.debug_tuple("Count").field(limit).finish(),
+        }
+    }
+}
+
 impl<'a> PartialEq for VLMScopeSpec<'a> {
     fn eq(&self, other: &Self) -> bool {
         match (self, other) {
@@ -59,10 +125,6 @@ impl<'a> PartialEq for VLMScopeSpec<'a> {
             _ => false,
         }
     }
-    
-    fn ne(&self, other: &Self) -> bool {
-        !self.eq(other)
-    }
 }
 
 
@@ -93,61 +155,17 @@ impl<'a> PartialOrd for VLMScopeSpec<'a> {
     }
 }
 
+// Scopes are their role: `Custom("admin")` is `Admin`.
 impl PartialEq for Scope {
     fn eq(&self, other: &Self) -> bool {
-        use Scope::*;
-        matches!((self, other), 
-            (Admin, Admin) | 
-            (Developer, Developer) | 
-            (User, User)
-        )
+        self.name() == other.name()
     }
 }
 
+// Roles are ordered by inheritance in the installed hierarchy: a role ranks above
+// every role it inherits from, and unrelated roles are incomparable.
 impl PartialOrd for Scope {
     fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
-        use Scope::*;
-        Some(match (self, other) {
-            (Admin, Admin) | (Developer, Developer) | (User, User) => std::cmp::Ordering::Equal,
-            (Admin, _) => std::cmp::Ordering::Greater,
-            (_, Admin) => std::cmp::Ordering::Less,
-            (Developer, _) => std::cmp::Ordering::Greater,
-            (_, Developer) => std::cmp::Ordering::Less,
-        })
-    }
-    
-}
-
-impl Ord for Scope {
-    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
-        self.partial_cmp(other).unwrap()
-    }
-    
-    fn max(self, other: Self) -> Self
-    where
-        Self: Sized,
-    {
-        std::cmp::max_by(self, other, Ord::cmp)
-    }
-    
-    fn min(self, other: Self) -> Self
-    where
-        Self: Sized,
-    {
-        std::cmp::min_by(self, other, Ord::cmp)
-    }
-    
-    fn clamp(self, min: Self, max: Self) -> Self
-    where
-        Self: Sized,
-    {
-        assert!(min <= max);
-        if self < min {
-            min
-        } else if self > max {
-            max
-        } else {
-            self
-        }
+        ScopeHierarchy::current().compare(self, other)
     }
 }
@@ -4,7 +4,15 @@ pub mod common;
 use std::marker::PhantomData;
 
 use vlm_macro_derive::VLM;
-pub use vlm_macro::web::{VlmHost,VlmPort,VirtualEnv,EJS,HTML,VlmContentType,XML};
+pub use vlm_macro::web::api::{VlmApi,VlmApiError,VlmParams};
+pub use vlm_macro::web::auth::{VlmAuth,VlmAuthorizer,VlmCredential,VlmDenied,VlmGuard,VlmGuards,VlmRequest};
+pub use common::auth::{ScopeAuthorizer,ScopeConfig};
+pub use common::scope_resolver::{ResolvedScope,ScopeResolver,ScopeSource};
+pub use common::hierarchy::{ScopeHierarchy,ScopeHierarchyConfig,ScopeRole};
+pub use common::quotas::{VLMCaller,VLMQuotaAuthorizer,VLMQuotaError,VLMQuotaRoute,VLMQuotaUsage,VLMQuotas};
+pub use vlm_macro::errors::{VLMPermissions,permissions::{VLMPermissionFormat,VLMPermissionGraph,VLMPermissionRule}};
+pub use vlm_macro::web::ws::{VlmBroadcast,VlmLagPolicy,VlmWebSockets,VlmWsConnection,VlmWsMessage,VlmWsOptions};
+pub use vlm_macro::web::{VlmBoundAddr,VlmHost,VlmPort,VirtualEnv,VlmExecution,VlmExitStatus,VlmRunError,EJS,HTML,VlmContentType,VlmContentTypes,XML,JSON,NDJSON,PlainText,CSS,JavaScript,SVG,WASM,PNG,OctetStream};
 
 
 
@@ -19,6 +27,12 @@ impl<V, U> Vlm<V, U> {
     }
 }
 
+impl<V, U> Default for Vlm<V, U> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 
 
 
@@ -1,3 +1,4 @@
+pub mod capabilities;
 pub mod tasks;
 pub mod vlm;
 pub mod span;
@@ -0,0 +1,354 @@
+use std::{
+    collections::VecDeque,
+    env,
+    error::Error,
+    fmt,
+    path::{Component, Path, PathBuf},
+    sync::Mutex,
+    time::SystemTime,
+};
+
+use crate::errors::{VLMPermissions, permissions::VLMPermissionGraph};
+
+/// Audit entries kept by default before the oldest are dropped.
+pub const DEFAULT_AUDIT_CAPACITY: usize = 1024;
+
+/// What an op needs, after Deno's `--allow-read`, `--allow-write` and `--allow-net`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum VlmCapability {
+    Read,
+    Write,
+    Net,
+}
+
+impl VlmCapability {
+    /// The name rules use as their `from` side.
+    pub fn name(&self) -> &'static str {
+        match self {
+            VlmCapability::Read => "read",
+            VlmCapability::Write => "write",
+            VlmCapability::Net => "net",
+        }
+    }
+}
+
+impl fmt::Display for VlmCapability {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+/// Checks the ops script runtimes expose go through before touching a path or host.
+///
+/// Errors are messages meant to be thrown into the script, where it can catch them.
+pub trait VlmCapabilityCheck: Send + Sync {
+    /// Resolves `path` against the working directory and checks `capability`
+    /// covers it once symlinks and `..` are resolved. With `follow_links` false
+    /// the last component is not resolved, for ops acting on a link itself.
+    fn check_path(
+        &self,
+        runtime: &str,
+        op: &str,
+        capability: VlmCapability,
+        path: &str,
+        follow_links: bool,
+    ) -> Result<PathBuf, String>;
+
+    /// Checks `host`, with or without a `:port`, may be connected to. IPv6
+    /// addresses with a port are bracketed, as in `[::1]:80`.
+    fn check_host(&self, runtime: &str, op: &str, host: &str) -> Result<(), String>;
+}
+
+/// One checked access.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct VlmAuditEntry {
+    pub time: SystemTime,
+    pub runtime: String,
+    pub op: String,
+    pub capability: VlmCapability,
+    /// The resolved path or the host.
+    pub resource: String,
+    pub allowed: bool,
+}
+
+/// [`VlmCapabilityCheck`] backed by a [`VLMPermissions`] rule set, with an audit log.
+///
+/// Rules go from a capability name (`read`, `write` or `net`) to a resolved
+/// absolute path or a host, so a rule file loaded with
+/// [`VLMPermissionGraph::load`] can grant or deny access with regex rules like
+/// `read -> /srv/data(/.*)?`. Nothing is allowed until a rule says so, and
+/// with a [`VLMPermissionGraph`] a deny wins over any allow. Every check, allowed
+/// or not, is logged under the `vlm::audit` target and kept in memory.
+pub struct VlmCapabilities {
+    rules: Box<dyn VLMPermissions + Send + Sync>,
+    audit: Mutex<VecDeque<VlmAuditEntry>>,
+    audit_capacity: usize,
+}
+
+impl Default for VlmCapabilities {
+    fn default() -> Self {
+        Self::with_rules(VLMPermissionGraph::new())
+    }
+}
+
+impl fmt::Debug for VlmCapabilities {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("VlmCapabilities")
+            .field("audit_capacity", &self.audit_capacity)
+            .finish_non_exhaustive()
+    }
+}
+
+impl VlmCapabilities {
+    /// Capabilities allowing nothing.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_rules(rules: impl VLMPermissions + Send + Sync + 'static) -> Self {
+        Self {
+            rules: Box::new(rules),
+            audit: Mutex::new(VecDeque::new()),
+            audit_capacity: DEFAULT_AUDIT_CAPACITY,
+        }
+    }
+
+    /// Reads rules from a `.toml`, `.yaml`/`.yml` or `.json` permission graph.
+    pub fn load(path: &Path) -> Result<Self, Box<dyn Error>> {
+        VLMPermissionGraph::load(path).map(Self::with_rules)
+    }
+
+    /// How many audit entries are kept in memory.
+    pub fn audit_capacity(mut self, capacity: usize) -> Self {
+        self.audit_capacity = capacity;
+        self
+    }
+
+    /// Allows reading `dir` and everything below it.
+    pub fn allow_read(self, dir: impl AsRef<Path>) -> Self {
+        self.path_rule(VlmCapability::Read, dir.as_ref(), true)
+    }
+
+    /// Allows writing, creating and removing `dir` and everything below it.
+    pub fn allow_write(self, dir: impl AsRef<Path>) -> Self {
+        self.path_rule(VlmCapability::Write, dir.as_ref(), true)
+    }
+
+    /// Denies `capability` on `path` and everything below it, whatever else allows it.
+    pub fn deny_path(self, capability: VlmCapability, path: impl AsRef<Path>) -> Self {
+        self.path_rule(capability, path.as_ref(), false)
+    }
+
+    /// Allows connecting to `host`, on any port unless it names one.
+    pub fn allow_net(self, host: &str) -> Self {
+        self.host_rule(host, true)
+    }
+
+    /// Denies connecting to `host`, on any port unless it names one, whatever else allows it.
+    pub fn deny_net(self, host: &str) -> Self {
+        self.host_rule(host, false)
+    }
+
+    /// The most recent checks, oldest first.
+    pub fn audit(&self) -> Vec<VlmAuditEntry> {
+        self.audit.lock().unwrap().iter().cloned().collect()
+    }
+
+    pub fn clear_audit(&self) {
+        self.audit.lock().unwrap().clear();
+    }
+
+    fn path_rule(mut self, capability: VlmCapability, path: &Path, allow: bool) -> Self {
+        let path = env::current_dir()
+            .ok()
+            .and_then(|cwd| resolve(&cwd.join(path), true).ok())
+            .unwrap_or_else(|| path.to_path_buf());
+        let dir = path.to_string_lossy();
+        let pattern = format!("{}(?:/.*)?", regex::escape(dir.trim_end_matches('/')));
+        self.rules.set_permission_with_regex(capability.name(), &pattern, allow);
+        self
+    }
+
+    fn host_rule(mut self, host: &str, allow: bool) -> Self {
+        let pattern = match (has_port(host), host.contains(':') && !host.starts_with('[')) {
+            (true, _) => regex::escape(host),
+            // A bare IPv6 address, written bracketed once a port follows.
+            (false, true) => format!("(?:{}|\\[{}\\](?::[0-9]+)?)", regex::escape(host), regex::escape(host)),
+            (false, false) => format!("{}(?::[0-9]+)?", regex::escape(host)),
+        };
+        self.rules
+            .set_permission_with_regex(VlmCapability::Net.name(), &pattern, allow);
+        self
+    }
+
+    fn record(&self, runtime: &str, op: &str, capability: VlmCapability, resource: &str, allowed: bool) {
+        match allowed {
+            true => log::info!(target: "vlm::audit", "{} {} allowed {} {}", runtime, op, capability, resource),
+            false => log::warn!(target: "vlm::audit", "{} {} denied {} {}", runtime, op, capability, resource),
+        }
+        let mut audit = self.audit.lock().unwrap();
+        if audit.len() >= self.audit_capacity {
+            audit.pop_front();
+        }
+        if self.audit_capacity > 0 {
+            audit.push_back(VlmAuditEntry {
+                time: SystemTime::now(),
+                runtime: runtime.to_string(),
+                op: op.to_string(),
+                capability,
+                resource: resource.to_string(),
+                allowed,
+            });
+        }
+    }
+}
+
+impl VlmCapabilityCheck for VlmCapabilities {
+    fn check_path(
+        &self,
+        runtime: &str,
+        op: &str,
+        capability: VlmCapability,
+        path: &str,
+        follow_links: bool,
+    ) -> Result<PathBuf, String> {
+        let cwd = env::current_dir().map_err(|e| format!("Failed to read the working directory: {}", e))?;
+        let resolved = match resolve(&cwd.join(path), follow_links) {
+            Ok(resolved) => resolved,
+            Err(e) => {
+                self.record(runtime, op, capability, path, false);
+                return Err(e);
+            }
+        };
+        let allowed = self
+            .rules
+            .has_permission(capability.name(), &resolved.to_string_lossy());
+        self.record(runtime, op, capability, &resolved.to_string_lossy(), allowed);
+        match allowed {
+            true => Ok(resolved),
+            false => Err(format!("Requires {} access to \"{}\"", capability, path)),
+        }
+    }
+
+    fn check_host(&self, runtime: &str, op: &str, host: &str) -> Result<(), String> {
+        let allowed = self.rules.has_permission(VlmCapability::Net.name(), host);
+        self.record(runtime, op, VlmCapability::Net, host, allowed);
+        match allowed {
+            true => Ok(()),
+            false => Err(format!("Requires net access to \"{}\"", host)),
+        }
+    }
+}
+
+/// Whether `host` ends in a port, telling `[::1]:80` from `::1`.
+fn has_port(host: &str) -> bool {
+    host.rsplit_once(':')
+        .is_some_and(|(name, port)| port.parse::<u16>().is_ok() && (!name.contains(':') || name.ends_with(']')))
+}
+
+/// `path` with its existing part canonicalized and the rest appended.
+fn resolve(path: &Path, follow_links: bool) -> Result<PathBuf, String> {
+    if !follow_links && let (Some(parent), Some(name)) = (path.parent(), path.file_name()) {
+        return Ok(resolve(parent, true)?.join(name));
+    }
+    let mut existing = path;
+    let mut missing = Vec::new();
+    loop {
+        if let Ok(canonical) = existing.canonicalize() {
+            return missing
+                .iter()
+                .rev()
+                .try_fold(canonical, |resolved, component: &Component| match component {
+                    Component::Normal(name) => Ok(resolved.join(name)),
+                    Component::CurDir => Ok(resolved),
+                    _ => Err(format!("Invalid path {}", path.display())),
+                });
+        }
+        missing.extend(existing.components().next_back());
+        existing = existing
+            .parent()
+            .ok_or_else(|| format!("Invalid path {}", path.display()))?;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+    use VlmCapability::{Read, Write};
+
+    /// A fresh `allowed` directory with a file outside of it.
+    fn sandbox(name: &str) -> (PathBuf, PathBuf) {
+        let root = env::temp_dir().join(format!("vlm-capabilities-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&root);
+        fs::create_dir_all(root.join("allowed/sub")).unwrap();
+        fs::write(root.join("secret"), "secret").unwrap();
+        (root.canonicalize().unwrap(), root.join("allowed"))
+    }
+
+    fn check(capabilities: &VlmCapabilities, capability: VlmCapability, path: &Path, follow: bool) -> bool {
+        capabilities
+            .check_path("test", "op", capability, &path.to_string_lossy(), follow)
+            .is_ok()
+    }
+
+    #[test]
+    fn dot_dot_cannot_escape_an_allowed_directory() {
+        let (root, allowed) = sandbox("dotdot");
+        let capabilities = VlmCapabilities::new().allow_read(&allowed);
+        assert!(check(&capabilities, Read, &allowed.join("sub/../file"), true));
+        assert!(!check(&capabilities, Read, &allowed.join("../secret"), true));
+        assert!(!check(&capabilities, Read, &allowed.join("missing/../../secret"), true));
+        assert!(!check(&capabilities, Write, &allowed.join("file"), true));
+        assert!(!check(&capabilities, Read, &root.join("allowedx"), true));
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn symlinks_resolve_before_checking() {
+        let (root, allowed) = sandbox("symlink");
+        std::os::unix::fs::symlink(root.join("secret"), allowed.join("link")).unwrap();
+        std::os::unix::fs::symlink(&root, allowed.join("up")).unwrap();
+        let capabilities = VlmCapabilities::new().allow_read(&allowed).allow_write(&allowed);
+        assert!(!check(&capabilities, Read, &allowed.join("link"), true));
+        assert!(!check(&capabilities, Read, &allowed.join("up/secret"), true));
+        assert!(!check(&capabilities, Write, &allowed.join("up/new"), true));
+        // Acting on the link itself stays inside.
+        assert!(check(&capabilities, Write, &allowed.join("link"), false));
+        assert!(!check(&capabilities, Write, &allowed.join("up/secret"), false));
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn deny_wins_and_every_check_is_audited() {
+        let (root, allowed) = sandbox("deny");
+        let capabilities = VlmCapabilities::new()
+            .deny_path(Read, allowed.join("sub"))
+            .allow_read(&allowed)
+            .audit_capacity(2);
+        assert!(check(&capabilities, Read, &allowed.join("file"), true));
+        assert!(!check(&capabilities, Read, &allowed.join("sub/file"), true));
+        assert!(!check(&capabilities, Read, &allowed.join("sub"), true));
+        let audit = capabilities.audit();
+        assert_eq!(audit.len(), 2);
+        assert!(audit.iter().all(|entry| !entry.allowed));
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn hosts_match_with_and_without_ports() {
+        let capabilities = VlmCapabilities::new()
+            .allow_net("example.com")
+            .deny_net("example.com:80")
+            .allow_net("::1")
+            .allow_net("127.0.0.1:8080");
+        let allowed = |host: &str| capabilities.check_host("test", "op", host).is_ok();
+        assert!(allowed("example.com") && allowed("example.com:443"));
+        assert!(!allowed("example.com:80"));
+        assert!(!allowed("example.com.evil:443") && !allowed("sub.example.com"));
+        assert!(allowed("::1") && allowed("[::1]:80"));
+        assert!(allowed("127.0.0.1:8080") && !allowed("127.0.0.1:8081") && !allowed("127.0.0.1"));
+    }
+}
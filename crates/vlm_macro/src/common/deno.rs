@@ -1,3 +0,0 @@
-pub trait VlmDeno{
-    
-}
\ No newline at end of file
@@ -1,14 +1,22 @@
 use std::collections::HashSet;
 
 pub mod error;
+pub mod permissions;
 
 // 
 
 /// Define the `VLMPermissions` trait.
+///
+/// Exact rules name roles literally, regex rules match them with anchored
+/// patterns. A deny rule always wins over an allow rule matching the same pair.
 pub trait VLMPermissions {
-    fn has_permission(&self, from: &'static str, to: &'static str) -> bool;
-    fn set_permission(&mut self, from: &'static str, to: &'static str, can_interact: bool);
-    fn get_permissions(&self, role: &'static str) -> Option<HashSet<&'static str>>;
+    /// Whether `from` may interact with `to`, considering every rule.
+    fn has_permission(&self, from: &str, to: &str) -> bool;
+    fn set_permission(&mut self, from: &str, to: &str, can_interact: bool);
+    /// The roles `from` may interact with through exact rules and regex rules naming
+    /// a single role, `None` for an unknown role.
+    fn get_permissions(&self, role: &str) -> Option<HashSet<String>>;
     fn set_permission_with_regex(&mut self, from: &str, to: &str, can_interact: bool);
+    /// Whether `from` may interact with `to`, considering only regex rules.
     fn check_permission_with_regex(&self, from: &str, to: &str) -> bool;
-}
\ No newline at end of file
+}
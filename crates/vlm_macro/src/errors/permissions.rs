@@ -0,0 +1,379 @@
+use std::{
+    collections::{BTreeMap, BTreeSet, HashSet, VecDeque},
+    error::Error,
+    fs,
+    path::Path,
+};
+
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+
+use super::VLMPermissions;
+
+/// File formats a permission graph is loaded from and saved to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VLMPermissionFormat {
+    Toml,
+    Yaml,
+    Json,
+}
+
+impl VLMPermissionFormat {
+    /// The format matching the file extension of `path`.
+    pub fn from_path(path: &Path) -> Option<Self> {
+        match path.extension()?.to_str()?.to_ascii_lowercase().as_str() {
+            "toml" => Some(VLMPermissionFormat::Toml),
+            "yaml" | "yml" => Some(VLMPermissionFormat::Yaml),
+            "json" => Some(VLMPermissionFormat::Json),
+            _ => None,
+        }
+    }
+}
+
+/// One `from → to` rule as stored on disk.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct VLMPermissionRule {
+    pub from: String,
+    pub to: String,
+    pub allow: bool,
+    /// `from` and `to` are regular expressions matched against whole role names.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub regex: bool,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct PermissionsFile {
+    #[serde(default)]
+    inherits: BTreeMap<String, Vec<String>>,
+    #[serde(default)]
+    rules: Vec<VLMPermissionRule>,
+}
+
+#[derive(Debug, Clone)]
+struct RegexRule {
+    from: Regex,
+    to: Regex,
+    allow: bool,
+}
+
+impl RegexRule {
+    fn new(from: &str, to: &str, allow: bool) -> Result<Self, regex::Error> {
+        let anchored = |pattern: &str| Regex::new(&format!("^(?:{})$", pattern));
+        Ok(Self {
+            from: anchored(from)?,
+            to: anchored(to)?,
+            allow,
+        })
+    }
+
+    /// The pattern as written, without the anchors added by [`RegexRule::new`].
+    fn source(regex: &Regex) -> &str {
+        let pattern = regex.as_str();
+        &pattern[4..pattern.len() - 2]
+    }
+
+    /// The one role `regex` matches when the pattern is a plain name, such as `admin` or `ops-team`.
+    fn literal(regex: &Regex) -> Option<&str> {
+        let source = Self::source(regex);
+        let plain = |c: char| c.is_alphanumeric() || c == '_' || c == '-';
+        (!source.is_empty() && source.chars().all(plain)).then_some(source)
+    }
+}
+
+/// In-memory role graph implementing [`VLMPermissions`].
+///
+/// Roles inherit the rules of their parents. When several rules match a pair,
+/// a deny wins over any allow, wherever in the inheritance chain it comes from;
+/// pairs no rule matches are denied.
+#[derive(Debug, Clone, Default)]
+pub struct VLMPermissionGraph {
+    inherits: BTreeMap<String, BTreeSet<String>>,
+    exact: BTreeMap<(String, String), bool>,
+    regex: Vec<RegexRule>,
+}
+
+impl VLMPermissionGraph {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Makes `role` inherit every rule of `parent`.
+    pub fn inherit(&mut self, role: &str, parent: &str) -> &mut Self {
+        self.inherits
+            .entry(role.to_string())
+            .or_default()
+            .insert(parent.to_string());
+        self
+    }
+
+    /// Adds a regex rule, replacing one with the same patterns.
+    pub fn add_regex_rule(&mut self, from: &str, to: &str, allow: bool) -> Result<&mut Self, regex::Error> {
+        let rule = RegexRule::new(from, to, allow)?;
+        self.regex.retain(|existing| {
+            existing.from.as_str() != rule.from.as_str() || existing.to.as_str() != rule.to.as_str()
+        });
+        self.regex.push(rule);
+        Ok(self)
+    }
+
+    /// Every role named by an inheritance link, an exact rule or a regex rule whose
+    /// pattern is a plain role name. Roles only a wider pattern such as `dev.*`
+    /// matches cannot be listed and are not included.
+    pub fn roles(&self) -> BTreeSet<String> {
+        let mut roles = BTreeSet::new();
+        for (role, parents) in &self.inherits {
+            roles.insert(role.clone());
+            roles.extend(parents.iter().cloned());
+        }
+        for (from, to) in self.exact.keys() {
+            roles.insert(from.clone());
+            roles.insert(to.clone());
+        }
+        for rule in &self.regex {
+            roles.extend(
+                [&rule.from, &rule.to]
+                    .into_iter()
+                    .filter_map(RegexRule::literal)
+                    .map(str::to_string),
+            );
+        }
+        roles
+    }
+
+    /// All rules, exact ones first, in the shape they are saved in.
+    pub fn rules(&self) -> Vec<VLMPermissionRule> {
+        let exact = self.exact.iter().map(|((from, to), allow)| VLMPermissionRule {
+            from: from.clone(),
+            to: to.clone(),
+            allow: *allow,
+            regex: false,
+        });
+        let regex = self.regex.iter().map(|rule| VLMPermissionRule {
+            from: RegexRule::source(&rule.from).to_string(),
+            to: RegexRule::source(&rule.to).to_string(),
+            allow: rule.allow,
+            regex: true,
+        });
+        exact.chain(regex).collect()
+    }
+
+    /// Reads a graph from a `.toml`, `.yaml`/`.yml` or `.json` file.
+    pub fn load(path: &Path) -> Result<Self, Box<dyn Error>> {
+        let format = VLMPermissionFormat::from_path(path)
+            .ok_or_else(|| format!("Unknown permissions format for {}", path.display()))?;
+        let text = fs::read_to_string(path).map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+        Self::parse(&text, format).map_err(|e| format!("Invalid permissions in {}: {}", path.display(), e).into())
+    }
+
+    /// Writes the graph in the format matching the extension of `path`.
+    pub fn save(&self, path: &Path) -> Result<(), Box<dyn Error>> {
+        let format = VLMPermissionFormat::from_path(path)
+            .ok_or_else(|| format!("Unknown permissions format for {}", path.display()))?;
+        fs::write(path, self.serialize(format)?)
+            .map_err(|e| format!("Failed to write {}: {}", path.display(), e).into())
+    }
+
+    pub fn parse(text: &str, format: VLMPermissionFormat) -> Result<Self, Box<dyn Error>> {
+        let file: PermissionsFile = match format {
+            VLMPermissionFormat::Toml => toml::from_str(text)?,
+            VLMPermissionFormat::Yaml => serde_yaml::from_str(text)?,
+            VLMPermissionFormat::Json => serde_json::from_str(text)?,
+        };
+        let mut graph = Self::new();
+        for (role, parents) in &file.inherits {
+            for parent in parents {
+                graph.inherit(role, parent);
+            }
+        }
+        for rule in &file.rules {
+            match rule.regex {
+                true => {
+                    graph
+                        .add_regex_rule(&rule.from, &rule.to, rule.allow)
+                        .map_err(|e| format!("Invalid rule {} -> {}: {}", rule.from, rule.to, e))?;
+                }
+                false => graph.set_permission(&rule.from, &rule.to, rule.allow),
+            }
+        }
+        Ok(graph)
+    }
+
+    pub fn serialize(&self, format: VLMPermissionFormat) -> Result<String, Box<dyn Error>> {
+        let file = PermissionsFile {
+            inherits: self
+                .inherits
+                .iter()
+                .map(|(role, parents)| (role.clone(), parents.iter().cloned().collect()))
+                .collect(),
+            rules: self.rules(),
+        };
+        Ok(match format {
+            VLMPermissionFormat::Toml => toml::to_string(&file)?,
+            VLMPermissionFormat::Yaml => serde_yaml::to_string(&file)?,
+            VLMPermissionFormat::Json => serde_json::to_string_pretty(&file)?,
+        })
+    }
+
+    /// `role` followed by every role it inherits from, each once.
+    fn lineage(&self, role: &str) -> Vec<String> {
+        let mut seen = vec![role.to_string()];
+        let mut queue = VecDeque::from([role.to_string()]);
+        while let Some(role) = queue.pop_front() {
+            for parent in self.inherits.get(&role).into_iter().flatten() {
+                if !seen.contains(parent) {
+                    seen.push(parent.clone());
+                    queue.push_back(parent.clone());
+                }
+            }
+        }
+        seen
+    }
+
+    fn decide(&self, from: &str, to: &str, exact: bool, regex: bool) -> bool {
+        let mut allowed = false;
+        for role in self.lineage(from) {
+            let exact = match exact {
+                true => self.exact.get(&(role.clone(), to.to_string())).copied(),
+                false => None,
+            };
+            let regex = self
+                .regex
+                .iter()
+                .filter(|rule| regex && rule.from.is_match(&role) && rule.to.is_match(to))
+                .map(|rule| rule.allow);
+            for allow in exact.into_iter().chain(regex) {
+                if !allow {
+                    return false;
+                }
+                allowed = true;
+            }
+        }
+        allowed
+    }
+}
+
+impl VLMPermissions for VLMPermissionGraph {
+    fn has_permission(&self, from: &str, to: &str) -> bool {
+        self.decide(from, to, true, true)
+    }
+
+    fn set_permission(&mut self, from: &str, to: &str, can_interact: bool) {
+        self.exact.insert((from.to_string(), to.to_string()), can_interact);
+    }
+
+    fn get_permissions(&self, role: &str) -> Option<HashSet<String>> {
+        if !self.roles().contains(role) {
+            return None;
+        }
+        let lineage = self.lineage(role);
+        let exact = self
+            .exact
+            .iter()
+            .filter(|((from, _), allow)| **allow && lineage.contains(from))
+            .map(|((_, to), _)| to.as_str());
+        let regex = self
+            .regex
+            .iter()
+            .filter(|rule| rule.allow && lineage.iter().any(|from| rule.from.is_match(from)))
+            .filter_map(|rule| RegexRule::literal(&rule.to));
+        Some(
+            exact
+                .chain(regex)
+                .filter(|to| self.has_permission(role, to))
+                .map(str::to_string)
+                .collect(),
+        )
+    }
+
+    /// Invalid patterns are logged and ignored; use
+    /// [`VLMPermissionGraph::add_regex_rule`] to handle the error.
+    fn set_permission_with_regex(&mut self, from: &str, to: &str, can_interact: bool) {
+        if let Err(e) = self.add_regex_rule(from, to, can_interact) {
+            log::warn!("Ignoring permission rule {} -> {}: {}", from, to, e);
+        }
+    }
+
+    fn check_permission_with_regex(&self, from: &str, to: &str) -> bool {
+        self.decide(from, to, false, true)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn graph() -> VLMPermissionGraph {
+        let mut graph = VLMPermissionGraph::new();
+        graph.inherit("admin", "dev").inherit("dev", "user");
+        graph.set_permission("user", "docs", true);
+        graph.set_permission("dev", "deploy", true);
+        graph.set_permission("admin", "docs", false);
+        graph.add_regex_rule("dev|admin", "logs-.*", true).unwrap();
+        graph.add_regex_rule("admin", "logs-audit", false).unwrap();
+        graph.add_regex_rule("auditor", "audit", true).unwrap();
+        graph
+    }
+
+    #[test]
+    fn deny_wins_wherever_it_comes_from() {
+        let graph = graph();
+        assert!(graph.has_permission("dev", "docs"));
+        assert!(!graph.has_permission("admin", "docs"));
+        assert!(graph.has_permission("admin", "deploy"));
+        assert!(graph.has_permission("dev", "logs-audit"));
+        assert!(!graph.has_permission("admin", "logs-audit"));
+        assert!(!graph.has_permission("user", "deploy"));
+        assert!(!graph.has_permission("stranger", "docs"));
+
+        let mut graph = graph;
+        graph.set_permission("user", "logs-app", false);
+        assert!(!graph.has_permission("admin", "logs-app"));
+        assert!(graph.check_permission_with_regex("admin", "logs-app"));
+    }
+
+    #[test]
+    fn roles_named_only_by_regex_rules_are_known() {
+        let graph = graph();
+        assert!(graph.roles().contains("auditor"));
+        assert!(
+            !graph
+                .roles()
+                .iter()
+                .any(|role| role.contains('*') || role.contains('|'))
+        );
+        let auditor = graph.get_permissions("auditor").unwrap();
+        assert_eq!(auditor, HashSet::from(["audit".to_string()]));
+        let admin = graph.get_permissions("admin").unwrap();
+        assert_eq!(admin, HashSet::from(["deploy".to_string()]));
+        assert_eq!(graph.get_permissions("stranger"), None);
+    }
+
+    #[test]
+    fn every_format_round_trips() {
+        let graph = graph();
+        for format in [
+            VLMPermissionFormat::Toml,
+            VLMPermissionFormat::Yaml,
+            VLMPermissionFormat::Json,
+        ] {
+            let text = graph.serialize(format).unwrap();
+            let parsed = VLMPermissionGraph::parse(&text, format).unwrap();
+            assert_eq!(parsed.inherits, graph.inherits, "{:?}", format);
+            assert_eq!(parsed.rules(), graph.rules(), "{:?}", format);
+            assert!(!parsed.has_permission("admin", "logs-audit"));
+        }
+    }
+
+    #[test]
+    fn save_picks_the_format_from_the_extension() {
+        let dir = std::env::temp_dir().join(format!("vlm-permissions-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        for name in ["roles.toml", "roles.yml", "roles.json"] {
+            let path = dir.join(name);
+            graph().save(&path).unwrap();
+            assert_eq!(VLMPermissionGraph::load(&path).unwrap().rules(), graph().rules());
+        }
+        assert!(graph().save(&dir.join("roles.ini")).is_err());
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
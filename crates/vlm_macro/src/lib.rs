@@ -1,6 +1,10 @@
 use std::{path::PathBuf, sync::Arc};
 
-use web::{VlmContentType, VlmHost, VlmPort};
+use web::{
+    api::{VlmApi, VlmApiOutput},
+    server::{VlmOptions, VlmServer},
+    VlmContentType, VlmHost, VlmPort, VlmPortPolicy,
+};
 
 pub mod cli;
 pub mod common;
@@ -11,7 +15,29 @@ pub mod errors;
 
 #[derive(Debug)]
 pub enum V {
-    Go(PathBuf),
+    /// A Go program, served on the port picked by its policy.
+    Go(PathBuf, VlmPortPolicy),
+}
+
+impl V {
+    pub fn port_policy(&self) -> VlmPortPolicy {
+        match self {
+            V::Go(_, policy) => *policy,
+        }
+    }
+
+    /// Applies the mode's port policy to the requested address.
+    ///
+    /// The port is not probed here: a conflict fails `VlmServer::spawn` when it binds,
+    /// before anything is served, and the server logs the address it actually got.
+    pub fn bind_address(
+        &self,
+        addr: (VlmHost, VlmPort),
+    ) -> Result<(VlmHost, VlmPort), Box<dyn ::std::error::Error>> {
+        let port = self.port_policy().apply(addr.1)?;
+        log::debug!("{:?} mode asks for port {} (requested port {})", self, port, addr.1);
+        Ok((addr.0, port))
+    }
 }
 
 
@@ -22,6 +48,48 @@ pub trait VLM<T> {
         content: std::path::PathBuf,
         content_type: std::sync::Arc<Option<Vec<Arc<dyn VlmContentType>>>>,
         mode: Arc<Option<V>>,
+    ) -> Result<Option<T>, Box<dyn ::std::error::Error>> {
+        self.vlm_with_context(addr, content, serde_json::Value::Null, content_type, mode)
+    }
+    /// Like `vlm`, rendering `.ejs` templates under `content` with `context`.
+    fn vlm_with_context(
+        &self,
+        addr: (VlmHost, VlmPort),
+        content: std::path::PathBuf,
+        context: serde_json::Value,
+        content_type: std::sync::Arc<Option<Vec<Arc<dyn VlmContentType>>>>,
+        mode: Arc<Option<V>>,
     ) -> Result<Option<T>, Box<dyn ::std::error::Error>>;
+    /// Starts the server in the background and returns a handle to stop or await it.
+    fn serve(
+        &self,
+        addr: (VlmHost, VlmPort),
+        content: std::path::PathBuf,
+        content_type: std::sync::Arc<Option<Vec<Arc<dyn VlmContentType>>>>,
+        mode: Arc<Option<V>>,
+    ) -> Result<VlmServer, Box<dyn ::std::error::Error>> {
+        self.serve_with(addr, content, content_type, mode, VlmOptions::default())
+    }
+    /// Like `serve`, with explicit server options.
+    fn serve_with(
+        &self,
+        addr: (VlmHost, VlmPort),
+        content: std::path::PathBuf,
+        content_type: std::sync::Arc<Option<Vec<Arc<dyn VlmContentType>>>>,
+        mode: Arc<Option<V>>,
+        options: VlmOptions,
+    ) -> Result<VlmServer, Box<dyn ::std::error::Error>>;
+    /// Like `serve_with`, answering the typed JSON routes of `api` ahead of the static content.
+    fn serve_api(
+        &self,
+        addr: (VlmHost, VlmPort),
+        content: std::path::PathBuf,
+        content_type: std::sync::Arc<Option<Vec<Arc<dyn VlmContentType>>>>,
+        mode: Arc<Option<V>>,
+        options: VlmOptions,
+        api: VlmApi<T>,
+    ) -> Result<VlmServer, Box<dyn ::std::error::Error>>
+    where
+        T: VlmApiOutput;
     fn type_id(&self) -> ::std::any::TypeId;
 }
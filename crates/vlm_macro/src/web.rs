@@ -1,9 +1,32 @@
+pub mod access;
+pub mod api;
+pub mod auth;
+pub mod compress;
+pub mod conditional;
+pub mod ejs;
+pub mod execution;
+pub mod files;
+mod host;
+pub mod http_date;
+pub mod metrics;
+pub mod negotiate;
+pub mod proxy;
+pub mod range;
+mod registry;
+pub mod server;
+pub mod watch;
+pub mod ws;
 mod web_1;
 
 use std::error::Error;
 
-pub use web_1::{VlmContentType, EJS, HTML, XML};
-pub type VlmHost=[u8; 4];
+pub use execution::{VlmExecution, VlmExitStatus, VlmRunError};
+pub use registry::VlmContentTypes;
+pub use web_1::{
+    OctetStream, PlainText, VlmContentType, CSS, EJS, HTML, JSON, NDJSON, PNG, SVG, WASM, XML,
+    JavaScript,
+};
+pub use host::{VlmBoundAddr, VlmHost, VlmPortPolicy};
 pub type VlmPort=u16;
 
 // --- Trait definition for a Virtual Environment for a language ---
@@ -19,70 +42,8 @@ pub trait VirtualEnv {
     fn init(&self) -> Result<(), Box<dyn Error>>;
     
     /// Execute a given code snippet within the environment.
-    fn run_code(&self, code: &str) -> Result<(), Box<dyn Error>>;
-}
-
-
-// 
-
-impl XML {
-    pub fn new(content_type: &[u8], charset: &[u8]) -> Self {
-        Self {
-            content_type: content_type.to_vec(),
-            charset: charset.to_vec(),
-        }
-    }
-}
-
-impl VlmContentType for XML {
-    fn content_type_header(&self) -> &[u8] {
-        &self.content_type
-    }
-
-    fn charset(&self) -> &[u8] {
-        &self.charset
-    }
-}
-
-// 
-
-impl HTML {
-    pub fn new(content_type: &[u8], charset: &[u8]) -> Self {
-        Self {
-            content_type: content_type.to_vec(),
-            charset: charset.to_vec(),
-        }
-    }
-}
-
-impl VlmContentType for HTML {
-    fn content_type_header(&self) -> &[u8] {
-        &self.content_type
-    }
-
-    fn charset(&self) -> &[u8] {
-        &self.charset
-    }
-}
-
-// 
-
-
-impl EJS {
-    pub fn new(content_type: &[u8], charset: &[u8]) -> Self {
-        Self {
-            content_type: content_type.to_vec(),
-            charset: charset.to_vec(),
-        }
-    }
-}
-
-impl VlmContentType for EJS {
-    fn content_type_header(&self) -> &[u8] {
-        &self.content_type
-    }
-
-    fn charset(&self) -> &[u8] {
-        &self.charset
-    }
+    ///
+    /// Failures of the snippet itself are reported in the returned
+    /// [`VlmExecution`]; `Err` means the environment could not run it at all.
+    fn run_code(&self, code: &str) -> Result<VlmExecution, Box<dyn Error>>;
 }
@@ -0,0 +1,220 @@
+use std::{
+    convert::Infallible,
+    net::SocketAddr,
+    sync::Arc,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+use serde_json::json;
+use warp::{
+    filters::BoxedFilter,
+    http::{Method, Response, StatusCode},
+    hyper::{body::HttpBody, Body},
+    path::FullPath,
+    reject, reply, Filter, Rejection,
+};
+
+use super::{
+    http_date::{Civil, MONTHS},
+    metrics::VlmMetrics,
+};
+
+/// Format of the per-request access log written to the `vlm::access` log target.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VlmAccessLog {
+    /// Common Log Format followed by the latency in milliseconds and the content type:
+    /// `127.0.0.1 - - [18/Oct/2026:09:12:03 +0000] "GET /docs/" 200 512 0.412 "text/html; charset=utf-8"`.
+    Common,
+    /// One JSON object per request.
+    Json,
+}
+
+/// One served request, as seen by the access log and metrics.
+#[derive(Debug, Clone)]
+pub struct VlmAccess {
+    pub remote: Option<SocketAddr>,
+    pub method: Method,
+    pub path: String,
+    pub status: StatusCode,
+    /// Body length, `None` for streamed bodies of unknown length.
+    pub bytes: Option<u64>,
+    pub latency: Duration,
+    pub content_type: Option<String>,
+    pub time: SystemTime,
+}
+
+impl VlmAccess {
+    /// Formats the request as a single log line.
+    pub fn format(&self, format: VlmAccessLog) -> String {
+        let remote = self.remote.map_or("-".to_string(), |addr| addr.ip().to_string());
+        let latency_ms = self.latency.as_secs_f64() * 1000.0;
+        match format {
+            VlmAccessLog::Common => format!(
+                "{} - - [{}] \"{} {}\" {} {} {:.3} \"{}\"",
+                remote,
+                common_time(self.time),
+                self.method,
+                self.path,
+                self.status.as_u16(),
+                self.bytes.map_or("-".to_string(), |bytes| bytes.to_string()),
+                latency_ms,
+                self.content_type.as_deref().unwrap_or("-"),
+            ),
+            VlmAccessLog::Json => json!({
+                "time": self.time.duration_since(UNIX_EPOCH).unwrap_or_default().as_secs_f64(),
+                "remote": self.remote.map(|addr| addr.ip().to_string()),
+                "method": self.method.as_str(),
+                "path": self.path,
+                "status": self.status.as_u16(),
+                "bytes": self.bytes,
+                "latency_ms": latency_ms,
+                "content_type": self.content_type,
+            })
+            .to_string(),
+        }
+    }
+}
+
+/// Wraps `route` so every request, including rejected ones, is logged and counted.
+///
+/// Rejections are turned into plain-text responses here so their status shows up
+/// in the log and metrics instead of being decided after the route returns.
+pub fn instrument(
+    route: BoxedFilter<(reply::Response,)>,
+    log: Option<VlmAccessLog>,
+    metrics: Option<Arc<VlmMetrics>>,
+) -> BoxedFilter<(reply::Response,)> {
+    warp::any()
+        .map(|| (Instant::now(), SystemTime::now()))
+        .and(warp::addr::remote())
+        .and(warp::method())
+        .and(warp::path::full())
+        .and(warp::query::raw().or(warp::any().map(String::new)).unify())
+        .and(route.recover(rejection_response).unify())
+        .map(
+            move |(started, time): (Instant, SystemTime),
+                  remote,
+                  method,
+                  path: FullPath,
+                  query: String,
+                  response: reply::Response| {
+                let access = VlmAccess {
+                    remote,
+                    method,
+                    path: match query.is_empty() {
+                        true => path.as_str().to_string(),
+                        false => format!("{}?{}", path.as_str(), query),
+                    },
+                    status: response.status(),
+                    bytes: response.body().size_hint().exact().or_else(|| {
+                        response
+                            .headers()
+                            .get("Content-Length")
+                            .and_then(|value| value.to_str().ok()?.parse().ok())
+                    }),
+                    latency: started.elapsed(),
+                    content_type: response
+                        .headers()
+                        .get("Content-Type")
+                        .and_then(|value| value.to_str().ok())
+                        .map(str::to_string),
+                    time,
+                };
+                if let Some(format) = log {
+                    log::info!(target: "vlm::access", "{}", access.format(format));
+                }
+                if let Some(metrics) = &metrics {
+                    metrics.record(&access.method, access.status, access.bytes, access.latency);
+                }
+                response
+            },
+        )
+        .boxed()
+}
+
+/// Mirrors warp's default rejection responses for the rejections our routes produce.
+async fn rejection_response(rejection: Rejection) -> Result<reply::Response, Infallible> {
+    let status = if rejection.is_not_found() {
+        StatusCode::NOT_FOUND
+    } else if rejection.find::<reject::MethodNotAllowed>().is_some() {
+        StatusCode::METHOD_NOT_ALLOWED
+    } else if rejection.find::<reject::PayloadTooLarge>().is_some() {
+        StatusCode::PAYLOAD_TOO_LARGE
+    } else if rejection.find::<reject::LengthRequired>().is_some() {
+        StatusCode::LENGTH_REQUIRED
+    } else if rejection.find::<reject::UnsupportedMediaType>().is_some() {
+        StatusCode::UNSUPPORTED_MEDIA_TYPE
+    } else if rejection.find::<reject::InvalidQuery>().is_some()
+        || rejection.find::<reject::InvalidHeader>().is_some()
+        || rejection.find::<reject::MissingHeader>().is_some()
+        || rejection.find::<reject::MissingCookie>().is_some()
+        || rejection.find::<warp::body::BodyDeserializeError>().is_some()
+        || rejection.find::<warp::ws::MissingConnectionUpgrade>().is_some()
+    {
+        StatusCode::BAD_REQUEST
+    } else if rejection.find::<warp::cors::CorsForbidden>().is_some() {
+        StatusCode::FORBIDDEN
+    } else {
+        log::error!(target: "vlm::access", "Unhandled rejection: {:?}", rejection);
+        StatusCode::INTERNAL_SERVER_ERROR
+    };
+    let reason = status.canonical_reason().unwrap_or_default();
+    Ok(Response::builder()
+        .status(status)
+        .header("Content-Type", "text/plain; charset=utf-8")
+        .body(Body::from(format!("{} {}", status.as_u16(), reason)))
+        .unwrap())
+}
+
+/// Formats `time` as `18/Oct/2026:09:12:03 +0000`.
+fn common_time(time: SystemTime) -> String {
+    let c = Civil::from_time(time);
+    format!(
+        "{:02}/{}/{}:{:02}:{:02}:{:02} +0000",
+        c.day,
+        MONTHS[(c.month - 1) as usize],
+        c.year,
+        c.hour,
+        c.minute,
+        c.second
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use warp::Reply;
+
+    async fn status(route: BoxedFilter<(reply::Response,)>, request: warp::test::RequestBuilder) -> StatusCode {
+        request.reply(&instrument(route, None, None)).await.status()
+    }
+
+    #[tokio::test]
+    async fn known_rejections_keep_their_status() {
+        let get = warp::get().map(|| Reply::into_response("ok")).boxed();
+        let post = warp::test::request().method("POST").path("/");
+        assert_eq!(status(get.clone(), post).await, StatusCode::METHOD_NOT_ALLOWED);
+        let json = warp::post()
+            .and(warp::body::json())
+            .map(|body: serde_json::Value| Reply::into_response(reply::json(&body)))
+            .boxed();
+        let request = warp::test::request().method("POST").path("/");
+        let text = request.header("Content-Type", "text/plain").body("{}");
+        assert_eq!(status(json.clone(), text).await, StatusCode::UNSUPPORTED_MEDIA_TYPE);
+        let request = warp::test::request().method("POST").path("/");
+        let broken = request.header("Content-Type", "application/json").body("{");
+        assert_eq!(status(json, broken).await, StatusCode::BAD_REQUEST);
+        let socket = warp::ws()
+            .map(|ws: warp::ws::Ws| ws.on_upgrade(|_| async {}).into_response())
+            .boxed();
+        let plain = warp::test::request().path("/");
+        assert_eq!(status(socket, plain).await, StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn unmatched_paths_are_not_found() {
+        let route = warp::path("a").map(|| Reply::into_response("ok")).boxed();
+        let request = warp::test::request().path("/b");
+        assert_eq!(status(route, request).await, StatusCode::NOT_FOUND);
+    }
+}
@@ -0,0 +1,346 @@
+use std::{collections::HashMap, fmt, str::FromStr, sync::Arc};
+
+use futures_util::{Stream, StreamExt};
+use serde::{de::DeserializeOwned, Serialize};
+use serde_json::json;
+use warp::{
+    filters::BoxedFilter,
+    http::{Method, Response, StatusCode},
+    hyper::{
+        body::{Buf, Bytes},
+        Body,
+    },
+    path::FullPath,
+    reply, Filter,
+};
+
+use super::files::percent_decode;
+
+/// Largest request body an API handler accepts.
+pub const MAX_BODY_LEN: u64 = 2 * 1024 * 1024;
+
+/// What API handlers return, i.e. the `U` of `Vlm<V, U>`.
+pub trait VlmApiOutput: Serialize + Send + Sync + 'static {}
+
+impl<T: Serialize + Send + Sync + 'static> VlmApiOutput for T {}
+
+/// An API failure, sent as `{"error": {"status": 404, "message": "..."}}`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct VlmApiError {
+    pub status: StatusCode,
+    pub message: String,
+}
+
+impl VlmApiError {
+    pub fn new(status: StatusCode, message: impl Into<String>) -> Self {
+        Self {
+            status,
+            message: message.into(),
+        }
+    }
+
+    pub fn bad_request(message: impl Into<String>) -> Self {
+        Self::new(StatusCode::BAD_REQUEST, message)
+    }
+
+    pub fn not_found(message: impl Into<String>) -> Self {
+        Self::new(StatusCode::NOT_FOUND, message)
+    }
+
+    pub fn internal(message: impl Into<String>) -> Self {
+        Self::new(StatusCode::INTERNAL_SERVER_ERROR, message)
+    }
+
+    /// The JSON error response.
+    pub fn into_response(self) -> reply::Response {
+        let body = json!({ "error": { "status": self.status.as_u16(), "message": self.message } });
+        json_response(self.status, body.to_string().into())
+    }
+}
+
+impl fmt::Display for VlmApiError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} {}", self.status.as_u16(), self.message)
+    }
+}
+
+impl std::error::Error for VlmApiError {}
+
+/// Path parameters captured by `:name` and `*name` segments of a route pattern.
+///
+/// Captures are percent-decoded; a `*name` capture joins its decoded segments with `/`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct VlmParams {
+    values: HashMap<String, String>,
+}
+
+impl VlmParams {
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.values.get(name).map(String::as_str)
+    }
+
+    /// Parses a parameter, answering `400 Bad Request` when it is missing or malformed.
+    pub fn parse<T: FromStr>(&self, name: &str) -> Result<T, VlmApiError> {
+        let value = self
+            .get(name)
+            .ok_or_else(|| VlmApiError::bad_request(format!("Missing path parameter {}", name)))?;
+        value
+            .parse()
+            .map_err(|_| VlmApiError::bad_request(format!("Invalid path parameter {}: {}", name, value)))
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum Segment {
+    Literal(String),
+    Param(String),
+    /// `*name`, capturing the rest of the path.
+    Rest(String),
+}
+
+type Handler<U> = dyn Fn(&VlmParams, &str, &Bytes) -> Result<U, VlmApiError> + Send + Sync;
+
+struct Route<U> {
+    method: Method,
+    pattern: Vec<Segment>,
+    handler: Arc<Handler<U>>,
+}
+
+/// Typed JSON handlers served ahead of the static content by `VLM::serve_api`.
+///
+/// Patterns are paths such as `/api/users/:id` or `/api/files/*path`. Requests
+/// are deserialized from the JSON body, or from the query string when the body
+/// is empty, and the handler's `U` is sent back as JSON.
+///
+/// A `*path` rest segment does not match when one of its segments decodes to a
+/// `/`, which the joined capture could not tell apart from a separator.
+pub struct VlmApi<U> {
+    routes: Vec<Route<U>>,
+}
+
+impl<U> Default for VlmApi<U> {
+    fn default() -> Self {
+        Self { routes: Vec::new() }
+    }
+}
+
+impl<U: VlmApiOutput> VlmApi<U> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Registers `handler` for `method` requests matching `pattern`.
+    pub fn route<Req, F>(mut self, method: Method, pattern: &str, handler: F) -> Self
+    where
+        Req: DeserializeOwned,
+        F: Fn(&VlmParams, Req) -> Result<U, VlmApiError> + Send + Sync + 'static,
+    {
+        let handler = move |params: &VlmParams, query: &str, body: &Bytes| {
+            let request = match body.iter().all(u8::is_ascii_whitespace) {
+                true => serde_urlencoded::from_str::<Req>(query)
+                    .map_err(|e| VlmApiError::bad_request(format!("Invalid query: {}", e)))?,
+                false => serde_json::from_slice::<Req>(body)
+                    .map_err(|e| VlmApiError::bad_request(format!("Invalid JSON body: {}", e)))?,
+            };
+            handler(params, request)
+        };
+        self.routes.push(Route {
+            method,
+            pattern: parse_pattern(pattern),
+            handler: Arc::new(handler),
+        });
+        self
+    }
+
+    pub fn get<Req: DeserializeOwned, F>(self, pattern: &str, handler: F) -> Self
+    where
+        F: Fn(&VlmParams, Req) -> Result<U, VlmApiError> + Send + Sync + 'static,
+    {
+        self.route(Method::GET, pattern, handler)
+    }
+
+    pub fn post<Req: DeserializeOwned, F>(self, pattern: &str, handler: F) -> Self
+    where
+        F: Fn(&VlmParams, Req) -> Result<U, VlmApiError> + Send + Sync + 'static,
+    {
+        self.route(Method::POST, pattern, handler)
+    }
+
+    pub fn put<Req: DeserializeOwned, F>(self, pattern: &str, handler: F) -> Self
+    where
+        F: Fn(&VlmParams, Req) -> Result<U, VlmApiError> + Send + Sync + 'static,
+    {
+        self.route(Method::PUT, pattern, handler)
+    }
+
+    pub fn delete<Req: DeserializeOwned, F>(self, pattern: &str, handler: F) -> Self
+    where
+        F: Fn(&VlmParams, Req) -> Result<U, VlmApiError> + Send + Sync + 'static,
+    {
+        self.route(Method::DELETE, pattern, handler)
+    }
+
+    /// Runs the handler for a request in-process and returns its typed output.
+    ///
+    /// Returns `None` when no route matches the path, so callers can fall back to other content.
+    pub fn dispatch(&self, method: &Method, path: &str, query: &str, body: &Bytes) -> Option<Result<U, VlmApiError>> {
+        let mut path_matched = false;
+        for route in &self.routes {
+            let params = match match_pattern(&route.pattern, path) {
+                Some(params) => params,
+                None => continue,
+            };
+            path_matched = true;
+            if route.method == *method {
+                return Some((route.handler)(&params, query, body));
+            }
+        }
+        match path_matched {
+            true => Some(Err(VlmApiError::new(
+                StatusCode::METHOD_NOT_ALLOWED,
+                format!("{} is not allowed on {}", method, path),
+            ))),
+            false => None,
+        }
+    }
+
+    /// Whether any route matches `path`, whatever its method.
+    pub fn matches(&self, path: &str) -> bool {
+        self.routes.iter().any(|route| match_pattern(&route.pattern, path).is_some())
+    }
+
+    /// Warp filter serving the routes. Paths no route matches are rejected as not
+    /// found before the body is read, so later filters can still consume it.
+    pub fn into_filter(self) -> BoxedFilter<(reply::Response,)> {
+        let api = Arc::new(self);
+        warp::path::full()
+            .and_then(move |path: FullPath| {
+                let api = Arc::clone(&api);
+                async move {
+                    match api.matches(path.as_str()) {
+                        true => Ok((api, path)),
+                        false => Err(warp::reject::not_found()),
+                    }
+                }
+            })
+            .and(warp::method())
+            .and(warp::query::raw().or(warp::any().map(String::new)).unify())
+            .and(warp::body::stream())
+            .then(|(api, path): (Arc<Self>, FullPath), method: Method, query: String, body| async move {
+                let body = match read_body(body).await {
+                    Ok(body) => body,
+                    Err(e) => return e.into_response(),
+                };
+                let result = api
+                    .dispatch(&method, path.as_str(), &query, &body)
+                    .unwrap_or_else(|| Err(VlmApiError::not_found(format!("No route for {}", path.as_str()))));
+                match result.and_then(|output| {
+                    serde_json::to_vec(&output)
+                        .map_err(|e| VlmApiError::internal(format!("Failed to serialize response: {}", e)))
+                }) {
+                    Ok(body) => json_response(StatusCode::OK, body.into()),
+                    Err(e) => e.into_response(),
+                }
+            })
+            .boxed()
+    }
+}
+
+/// Collects a request body, refusing anything over [`MAX_BODY_LEN`].
+async fn read_body<B: Buf>(body: impl Stream<Item = Result<B, warp::Error>>) -> Result<Bytes, VlmApiError> {
+    let mut body = std::pin::pin!(body);
+    let mut collected = Vec::new();
+    while let Some(chunk) = body.next().await {
+        let chunk = chunk.map_err(|e| VlmApiError::bad_request(format!("Failed to read body: {}", e)))?;
+        if collected.len() as u64 + chunk.remaining() as u64 > MAX_BODY_LEN {
+            return Err(VlmApiError::new(
+                StatusCode::PAYLOAD_TOO_LARGE,
+                format!("Body exceeds {} bytes", MAX_BODY_LEN),
+            ));
+        }
+        collected.extend_from_slice(chunk.chunk());
+    }
+    Ok(collected.into())
+}
+
+fn json_response(status: StatusCode, body: Body) -> reply::Response {
+    Response::builder()
+        .status(status)
+        .header("Content-Type", "application/json")
+        .body(body)
+        .unwrap()
+}
+
+pub(crate) fn parse_pattern(pattern: &str) -> Vec<Segment> {
+    pattern
+        .split('/')
+        .filter(|segment| !segment.is_empty())
+        .map(|segment| match (segment.strip_prefix(':'), segment.strip_prefix('*')) {
+            (Some(name), _) => Segment::Param(name.to_string()),
+            (_, Some(name)) => Segment::Rest(name.to_string()),
+            _ => Segment::Literal(segment.to_string()),
+        })
+        .collect()
+}
+
+pub(crate) fn match_pattern(pattern: &[Segment], path: &str) -> Option<VlmParams> {
+    let segments: Vec<&str> = path.split('/').filter(|segment| !segment.is_empty()).collect();
+    let mut params = VlmParams::default();
+    for (i, segment) in pattern.iter().enumerate() {
+        match segment {
+            Segment::Rest(name) => {
+                let rest = segments.get(i..)?.iter().map(|segment| percent_decode(segment));
+                let rest = rest.collect::<Option<Vec<_>>>()?;
+                if rest.iter().any(|segment| segment.contains('/')) {
+                    return None;
+                }
+                params.values.insert(name.clone(), rest.join("/"));
+                return Some(params);
+            }
+            Segment::Literal(literal) if segments.get(i) == Some(&literal.as_str()) => {}
+            Segment::Literal(_) => return None,
+            Segment::Param(name) => {
+                params.values.insert(name.clone(), percent_decode(segments.get(i)?)?);
+            }
+        }
+    }
+    (segments.len() == pattern.len()).then_some(params)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn params(pattern: &str, path: &str) -> Option<Vec<(String, String)>> {
+        let params = match_pattern(&parse_pattern(pattern), path)?;
+        let mut values: Vec<_> = params.values.into_iter().collect();
+        values.sort();
+        Some(values)
+    }
+
+    fn one(name: &str, value: &str) -> Option<Vec<(String, String)>> {
+        Some(vec![(name.to_string(), value.to_string())])
+    }
+
+    #[test]
+    fn literals_and_params() {
+        assert_eq!(params("/api/users", "/api/users/"), Some(vec![]));
+        assert_eq!(params("/api/users/:id", "/api/users/42"), one("id", "42"));
+        assert_eq!(params("/api/users/:id", "/api/users"), None);
+        assert_eq!(params("/api/users/:id", "/api/users/42/posts"), None);
+        assert_eq!(params("/api/users/:id", "/api/groups/42"), None);
+        assert_eq!(params("/api/:name", "/api/a%20b%2Fc"), one("name", "a b/c"));
+        assert_eq!(params("/api/:name", "/api/%zz"), None);
+    }
+
+    #[test]
+    fn rest_captures_are_decoded_like_params() {
+        assert_eq!(params("/files/*path", "/files/a%20b/c%25d"), one("path", "a b/c%d"));
+        assert_eq!(params("/files/*path", "/files//a///b"), one("path", "a/b"));
+        assert_eq!(params("/files/*path", "/files"), one("path", ""));
+        assert_eq!(params("/files/*path", "/files/a%2Fb"), None);
+        assert_eq!(params("/files/*path", "/files/%FF"), None);
+        let both = params("/u/:id/*rest", "/u/7/x%20y");
+        assert_eq!(both, Some(vec![("id".into(), "7".into()), ("rest".into(), "x y".into())]));
+    }
+}
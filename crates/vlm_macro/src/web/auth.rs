@@ -0,0 +1,332 @@
+use std::{fmt, net::SocketAddr, sync::Arc, time::Duration};
+
+use warp::{
+    filters::BoxedFilter,
+    http::{HeaderMap, StatusCode},
+    path::FullPath,
+    reply, Filter,
+};
+
+use super::{
+    api::{match_pattern, parse_pattern, Segment, VlmApiError},
+    files::normalize_path,
+};
+
+/// Header carrying an API key, as an alternative to `Authorization: Bearer`.
+pub const API_KEY_HEADER: &str = "X-API-Key";
+
+/// What a client presented to identify itself.
+#[derive(Clone, PartialEq, Eq)]
+pub enum VlmCredential {
+    Bearer(String),
+    ApiKey(String),
+}
+
+impl VlmCredential {
+    /// The bearer token from `Authorization`, or else the key from [`API_KEY_HEADER`].
+    pub fn from_headers(headers: &HeaderMap) -> Option<Self> {
+        let header = |name: &str| headers.get(name).and_then(|value| value.to_str().ok()).map(str::trim);
+        let bearer = header("Authorization").and_then(|value| {
+            let (scheme, token) = value.split_once(' ')?;
+            scheme.eq_ignore_ascii_case("bearer").then(|| token.trim())
+        });
+        match (bearer, header(API_KEY_HEADER)) {
+            (Some(token), _) if !token.is_empty() => Some(VlmCredential::Bearer(token.to_string())),
+            (_, Some(key)) if !key.is_empty() => Some(VlmCredential::ApiKey(key.to_string())),
+            _ => None,
+        }
+    }
+
+    pub fn secret(&self) -> &str {
+        match self {
+            VlmCredential::Bearer(secret) | VlmCredential::ApiKey(secret) => secret,
+        }
+    }
+}
+
+// Secrets stay out of logs.
+impl fmt::Debug for VlmCredential {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            VlmCredential::Bearer(_) => f.write_str("Bearer(..)"),
+            VlmCredential::ApiKey(_) => f.write_str("ApiKey(..)"),
+        }
+    }
+}
+
+/// The parts of a request guards decide on.
+#[derive(Debug, Clone)]
+pub struct VlmRequest<'a> {
+    pub path: &'a str,
+    pub remote: Option<SocketAddr>,
+    pub credential: Option<VlmCredential>,
+}
+
+impl<'a> VlmRequest<'a> {
+    pub fn new(path: &'a str, headers: &HeaderMap, remote: Option<SocketAddr>) -> Self {
+        Self {
+            path,
+            remote,
+            credential: VlmCredential::from_headers(headers),
+        }
+    }
+}
+
+/// Why a request was refused.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum VlmDenied {
+    /// No credential, or one that maps to nobody: `401 Unauthorized`.
+    Unauthorized(String),
+    /// A known caller without the access the route requires: `403 Forbidden`.
+    Forbidden(String),
+    /// The caller used up a quota or rate limit: `429 Too Many Requests`.
+    TooManyRequests {
+        message: String,
+        retry_after: Option<Duration>,
+    },
+}
+
+impl VlmDenied {
+    /// The JSON error response, with a `WWW-Authenticate` challenge for `401`
+    /// and `Retry-After` for `429` when known.
+    pub fn into_response(self) -> reply::Response {
+        match self {
+            VlmDenied::Unauthorized(message) => {
+                let mut response = VlmApiError::new(StatusCode::UNAUTHORIZED, message).into_response();
+                response
+                    .headers_mut()
+                    .insert("WWW-Authenticate", "Bearer".parse().unwrap());
+                response
+            }
+            VlmDenied::Forbidden(message) => VlmApiError::new(StatusCode::FORBIDDEN, message).into_response(),
+            VlmDenied::TooManyRequests { message, retry_after } => {
+                let mut response = VlmApiError::new(StatusCode::TOO_MANY_REQUESTS, message).into_response();
+                if let Some(retry_after) = retry_after {
+                    // Round up so clients never retry before the window reopens.
+                    let seconds = retry_after.as_secs() + u64::from(retry_after.subsec_nanos() > 0);
+                    response.headers_mut().insert("Retry-After", seconds.into());
+                }
+                response
+            }
+        }
+    }
+}
+
+impl fmt::Display for VlmDenied {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            VlmDenied::Unauthorized(message)
+            | VlmDenied::Forbidden(message)
+            | VlmDenied::TooManyRequests { message, .. } => f.write_str(message),
+        }
+    }
+}
+
+impl std::error::Error for VlmDenied {}
+
+/// Decides whether a caller meets the requirement a route declares.
+pub trait VlmAuthorizer: Send + Sync + 'static {
+    /// What routes require, e.g. a minimum scope.
+    type Requirement: fmt::Debug + Send + Sync + 'static;
+
+    fn authorize(&self, required: &Self::Requirement, credential: Option<&VlmCredential>) -> Result<(), VlmDenied>;
+
+    /// Like [`VlmAuthorizer::authorize`], for authorizers that also need the path or the remote address.
+    fn authorize_request(&self, required: &Self::Requirement, request: &VlmRequest) -> Result<(), VlmDenied> {
+        self.authorize(required, request.credential.as_ref())
+    }
+}
+
+/// Checks a request before it is served, see [`VlmAuth`].
+pub trait VlmGuard: fmt::Debug + Send + Sync {
+    fn check(&self, request: &VlmRequest) -> Result<(), VlmDenied>;
+}
+
+/// Guards checked in order, the first refusal winning.
+#[derive(Debug, Clone, Default)]
+pub struct VlmGuards(pub Vec<Arc<dyn VlmGuard>>);
+
+impl VlmGuards {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with(mut self, guard: Arc<dyn VlmGuard>) -> Self {
+        self.0.push(guard);
+        self
+    }
+
+    pub fn into_guard(self) -> Arc<dyn VlmGuard> {
+        Arc::new(self)
+    }
+}
+
+impl VlmGuard for VlmGuards {
+    fn check(&self, request: &VlmRequest) -> Result<(), VlmDenied> {
+        self.0.iter().try_for_each(|guard| guard.check(request))
+    }
+}
+
+/// Route requirements enforced by an authorizer, configured through `VlmOptions::auth`.
+///
+/// Patterns use the `VlmApi` syntax (`/admin/*rest`, `/api/users/:id`), so they
+/// guard API, WebSocket, static and proxied paths alike. The first matching
+/// pattern applies; paths no pattern matches are public.
+pub struct VlmAuth<A: VlmAuthorizer> {
+    authorizer: A,
+    routes: Vec<(Vec<Segment>, A::Requirement)>,
+}
+
+impl<A: VlmAuthorizer> VlmAuth<A> {
+    pub fn new(authorizer: A) -> Self {
+        Self {
+            authorizer,
+            routes: Vec::new(),
+        }
+    }
+
+    /// Requires `requirement` on paths matching `pattern`.
+    pub fn require(mut self, pattern: &str, requirement: A::Requirement) -> Self {
+        self.routes.push((parse_pattern(pattern), requirement));
+        self
+    }
+
+    /// The requirement declared for `path`, if any.
+    ///
+    /// `path` is matched in the form static files are resolved from, so encoded,
+    /// `.` and doubled-slash spellings of a guarded path are guarded too.
+    pub fn requirement(&self, path: &str) -> Option<&A::Requirement> {
+        let normalized = normalize_path(path);
+        let path = normalized.as_deref().unwrap_or(path);
+        self.routes
+            .iter()
+            .find(|(pattern, _)| match_pattern(pattern, path).is_some())
+            .map(|(_, requirement)| requirement)
+    }
+
+    pub fn into_guard(self) -> Arc<dyn VlmGuard> {
+        Arc::new(self)
+    }
+}
+
+impl<A: VlmAuthorizer> VlmGuard for VlmAuth<A> {
+    fn check(&self, request: &VlmRequest) -> Result<(), VlmDenied> {
+        match self.requirement(request.path) {
+            Some(required) => self.authorizer.authorize_request(required, request),
+            None => Ok(()),
+        }
+    }
+}
+
+impl<A: VlmAuthorizer> fmt::Debug for VlmAuth<A> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("VlmAuth")
+            .field(
+                "routes",
+                &self.routes.iter().map(|(_, required)| required).collect::<Vec<_>>(),
+            )
+            .finish()
+    }
+}
+
+/// Runs `guard` ahead of `route`, answering `401`/`403`/`429` for refused requests.
+///
+/// Guards see the normalized path (see [`VlmAuth::requirement`]); paths that cannot
+/// be normalized, such as those with `..` segments, are answered `400 Bad Request`.
+pub fn protect(
+    route: BoxedFilter<(reply::Response,)>,
+    guard: Option<Arc<dyn VlmGuard>>,
+) -> BoxedFilter<(reply::Response,)> {
+    let guard = match guard {
+        Some(guard) => guard,
+        None => return route,
+    };
+    warp::path::full()
+        .and(warp::header::headers_cloned())
+        .and(warp::addr::remote())
+        .and_then(move |path: FullPath, headers: HeaderMap, remote: Option<SocketAddr>| {
+            let refused = match normalize_path(path.as_str()) {
+                Some(normalized) => guard
+                    .check(&VlmRequest::new(&normalized, &headers, remote))
+                    .err()
+                    .map(|denied| {
+                        log::info!(target: "vlm::auth", "Refused {}: {}", path.as_str(), denied);
+                        denied.into_response()
+                    }),
+                None => Some(VlmApiError::bad_request(format!("Invalid path {}", path.as_str())).into_response()),
+            };
+            async move { refused.ok_or_else(warp::reject::not_found) }
+        })
+        .or(route)
+        .unify()
+        .boxed()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct Token;
+
+    impl VlmAuthorizer for Token {
+        type Requirement = &'static str;
+
+        fn authorize(&self, required: &&'static str, credential: Option<&VlmCredential>) -> Result<(), VlmDenied> {
+            match credential {
+                Some(credential) if credential.secret() == *required => Ok(()),
+                _ => Err(VlmDenied::Unauthorized("Missing token".to_string())),
+            }
+        }
+    }
+
+    fn auth() -> VlmAuth<Token> {
+        VlmAuth::new(Token).require("/admin/*rest", "secret")
+    }
+
+    #[test]
+    fn requirement_matches_spellings_of_guarded_paths() {
+        let auth = auth();
+        for path in [
+            "/admin/secret.html",
+            "/%61dmin/secret.html",
+            "/%61%64%6D%69%6E/secret.html",
+            "/./admin/secret.html",
+            "/admin/./secret.html",
+            "//admin/secret.html",
+            "/admin//secret.html",
+            "/admin/%2e/secret.html",
+        ] {
+            assert_eq!(auth.requirement(path), Some(&"secret"), "{}", path);
+        }
+        assert_eq!(auth.requirement("/public/admin"), None);
+        assert_eq!(auth.requirement("/administrator"), None);
+    }
+
+    #[test]
+    fn normalized_percent_stays_encoded() {
+        assert_eq!(normalize_path("/a/100%25").as_deref(), Some("/a/100%25"));
+        assert_eq!(normalize_path("/%61//./b/").as_deref(), Some("/a/b/"));
+        assert_eq!(normalize_path("/a/%2e%2e/b"), None);
+        assert_eq!(normalize_path("/a/%2Fb"), None);
+    }
+
+    #[tokio::test]
+    async fn protect_refuses_encoded_and_dotted_paths() {
+        let route = warp::any().map(|| reply::Response::new("served".into())).boxed();
+        let filter = protect(route, Some(auth().into_guard()));
+        for path in ["/admin/x", "/%61dmin/x", "/./admin/x", "//admin/x"] {
+            let response = warp::test::request().path(path).reply(&filter).await;
+            assert_eq!(response.status(), StatusCode::UNAUTHORIZED, "{}", path);
+        }
+        let response = warp::test::request()
+            .path("/%61dmin/x")
+            .header("Authorization", "Bearer secret")
+            .reply(&filter)
+            .await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let response = warp::test::request().path("/admin/../x").reply(&filter).await;
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        let response = warp::test::request().path("/public").reply(&filter).await;
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+}
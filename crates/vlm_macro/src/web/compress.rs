@@ -0,0 +1,232 @@
+use std::{
+    collections::HashMap,
+    io::{self, Write},
+    path::PathBuf,
+    sync::Mutex,
+};
+
+use flate2::{
+    write::{DeflateEncoder, GzEncoder},
+    Compression,
+};
+use warp::hyper::body::Bytes;
+
+use super::range::VlmSource;
+
+/// Bodies smaller than this are sent as they are; compression would not pay off.
+pub const MIN_COMPRESS_LEN: usize = 256;
+/// Larger bodies are streamed from disk as they are instead of being compressed in memory.
+pub const MAX_COMPRESS_LEN: u64 = 8 * 1024 * 1024;
+/// Upper bound on the bytes held by a [`VlmCompressor`] cache before it is emptied.
+pub const CACHE_LIMIT: usize = 64 * 1024 * 1024;
+
+/// A `Content-Encoding` the server can produce.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum VlmEncoding {
+    Identity,
+    Brotli,
+    Gzip,
+    Deflate,
+}
+
+impl VlmEncoding {
+    /// Encodings in server preference order, used to break ties between equal q-values.
+    pub const PREFERRED: [VlmEncoding; 4] = [
+        VlmEncoding::Brotli,
+        VlmEncoding::Gzip,
+        VlmEncoding::Deflate,
+        VlmEncoding::Identity,
+    ];
+
+    /// The `Content-Encoding` token.
+    pub fn token(&self) -> &'static str {
+        match self {
+            VlmEncoding::Identity => "identity",
+            VlmEncoding::Brotli => "br",
+            VlmEncoding::Gzip => "gzip",
+            VlmEncoding::Deflate => "deflate",
+        }
+    }
+
+    /// Suffix distinguishing the strong ETag of this encoding from the identity one.
+    pub fn etag_suffix(&self) -> &'static str {
+        match self {
+            VlmEncoding::Identity => "",
+            VlmEncoding::Brotli => "-br",
+            VlmEncoding::Gzip => "-gz",
+            VlmEncoding::Deflate => "-df",
+        }
+    }
+
+    /// Compresses `data` with this encoding.
+    pub fn encode(&self, data: &[u8]) -> io::Result<Vec<u8>> {
+        match self {
+            VlmEncoding::Identity => Ok(data.to_vec()),
+            VlmEncoding::Gzip => {
+                let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+                encoder.write_all(data)?;
+                encoder.finish()
+            }
+            VlmEncoding::Deflate => {
+                let mut encoder = DeflateEncoder::new(Vec::new(), Compression::default());
+                encoder.write_all(data)?;
+                encoder.finish()
+            }
+            VlmEncoding::Brotli => {
+                let mut out = Vec::new();
+                {
+                    let mut encoder = brotli::CompressorWriter::new(&mut out, 4096, 5, 22);
+                    encoder.write_all(data)?;
+                }
+                Ok(out)
+            }
+        }
+    }
+}
+
+/// Picks the encoding the client prefers from an `Accept-Encoding` header.
+///
+/// Without a header, or when every listed coding is refused, the body is sent as is.
+pub fn negotiate_encoding(accept_encoding: Option<&str>) -> VlmEncoding {
+    let header = match accept_encoding {
+        Some(header) if !header.trim().is_empty() => header,
+        _ => return VlmEncoding::Identity,
+    };
+    let codings: Vec<(String, f32)> = header
+        .split(',')
+        .filter_map(|coding| {
+            let mut parts = coding.split(';');
+            let name = parts.next()?.trim().to_ascii_lowercase();
+            let q = parts
+                .filter_map(|param| param.split_once('='))
+                .find(|(name, _)| name.trim().eq_ignore_ascii_case("q"))
+                .map_or(Some(1.0), |(_, q)| q.trim().parse::<f32>().ok())?;
+            Some((name, q.clamp(0.0, 1.0)))
+        })
+        .collect();
+    let quality = |encoding: VlmEncoding| {
+        let named = codings.iter().find(|(name, _)| {
+            name == encoding.token() || (encoding == VlmEncoding::Gzip && name == "x-gzip")
+        });
+        match (named, encoding) {
+            (Some((_, q)), _) => *q,
+            (None, VlmEncoding::Identity) => 1.0,
+            (None, _) => codings.iter().find(|(name, _)| name == "*").map_or(0.0, |(_, q)| *q),
+        }
+    };
+
+    let mut best = (VlmEncoding::Identity, 0.0);
+    for encoding in VlmEncoding::PREFERRED {
+        let q = quality(encoding);
+        if q > best.1 {
+            best = (encoding, q);
+        }
+    }
+    best.0
+}
+
+/// Whether bodies of `content_type` are worth compressing.
+pub fn compressible(content_type: &str) -> bool {
+    let media = content_type.split(';').next().unwrap_or_default().trim().to_ascii_lowercase();
+    media.starts_with("text/")
+        || media.ends_with("+xml")
+        || media.ends_with("+json")
+        || matches!(
+            media.as_str(),
+            "application/xml"
+                | "application/json"
+                | "application/javascript"
+                | "application/x-ndjson"
+                | "application/wasm"
+                | "image/svg+xml"
+        )
+}
+
+/// Encoded response bodies kept by file, ETag and encoding so repeated requests skip the work.
+#[derive(Debug, Default)]
+pub struct VlmCompressor {
+    cache: Mutex<EncodedCache>,
+}
+
+#[derive(Debug, Default)]
+struct EncodedCache {
+    bodies: HashMap<(Option<PathBuf>, String, VlmEncoding), Bytes>,
+    len: usize,
+}
+
+impl VlmCompressor {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The body for the representation identified by `etag` in `encoding`,
+    /// reading and encoding `source` only on the first request.
+    ///
+    /// File tags only tell versions of one file apart, so files are also keyed by path.
+    pub async fn get(&self, etag: &str, encoding: VlmEncoding, source: &VlmSource) -> io::Result<Bytes> {
+        let path = match source {
+            VlmSource::File { path, .. } => Some(path.clone()),
+            VlmSource::Memory(_) => None,
+        };
+        let key = (path, etag.to_string(), encoding);
+        if let Some(body) = self.cache.lock().unwrap().bodies.get(&key) {
+            return Ok(body.clone());
+        }
+        let data = source.read().await?;
+        let body = tokio::task::spawn_blocking(move || encoding.encode(&data)).await.map_err(io::Error::other)??;
+        let body = Bytes::from(body);
+        let mut cache = self.cache.lock().unwrap();
+        if cache.len + body.len() > CACHE_LIMIT {
+            cache.bodies.clear();
+            cache.len = 0;
+        }
+        cache.len += body.len();
+        cache.bodies.insert(key, body.clone());
+        Ok(body)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{env, fs};
+
+    use flate2::read::GzDecoder;
+
+    use super::*;
+
+    #[test]
+    fn negotiate_encoding_follows_q_values() {
+        assert_eq!(negotiate_encoding(None), VlmEncoding::Identity);
+        assert_eq!(negotiate_encoding(Some(" ")), VlmEncoding::Identity);
+        assert_eq!(negotiate_encoding(Some("gzip, deflate, br")), VlmEncoding::Brotli);
+        assert_eq!(negotiate_encoding(Some("gzip;q=1, br;q=0.5")), VlmEncoding::Gzip);
+        assert_eq!(negotiate_encoding(Some("X-GZIP")), VlmEncoding::Gzip);
+        assert_eq!(negotiate_encoding(Some("deflate;Q=0.8, identity;q=0.5")), VlmEncoding::Deflate);
+        assert_eq!(negotiate_encoding(Some("*")), VlmEncoding::Brotli);
+        assert_eq!(negotiate_encoding(Some("*;q=0.5, gzip")), VlmEncoding::Gzip);
+        assert_eq!(negotiate_encoding(Some("br;q=0, gzip;q=0, deflate;q=0")), VlmEncoding::Identity);
+        assert_eq!(negotiate_encoding(Some("gzip;q=abc")), VlmEncoding::Identity);
+        assert_eq!(negotiate_encoding(Some("compress")), VlmEncoding::Identity);
+    }
+
+    #[tokio::test]
+    async fn files_with_equal_tags_are_cached_apart() {
+        let dir = env::temp_dir().join(format!("vlm-compress-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let (a, b) = (dir.join("a.txt"), dir.join("b.txt"));
+        fs::write(&a, "a".repeat(1000)).unwrap();
+        fs::write(&b, "b".repeat(1000)).unwrap();
+
+        let compressor = VlmCompressor::new();
+        let mut bodies = Vec::new();
+        for path in [a, b] {
+            let source = VlmSource::File { path, len: 1000 };
+            let body = compressor.get("\"same-gz\"", VlmEncoding::Gzip, &source).await.unwrap();
+            let mut decoded = String::new();
+            io::Read::read_to_string(&mut GzDecoder::new(&body[..]), &mut decoded).unwrap();
+            bodies.push(decoded);
+        }
+        fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(bodies, ["a".repeat(1000), "b".repeat(1000)]);
+    }
+}
@@ -0,0 +1,102 @@
+use std::{
+    collections::hash_map::DefaultHasher,
+    fs::Metadata,
+    hash::Hasher,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use warp::http::HeaderMap;
+
+use super::{compress::VlmEncoding, http_date};
+
+/// Opaque validator for content produced in memory, hashed from its bytes.
+pub fn content_tag(data: &[u8]) -> String {
+    let mut hasher = DefaultHasher::new();
+    hasher.write(data);
+    format!("{:016x}-{:x}", hasher.finish(), data.len())
+}
+
+/// Opaque validator for a file on disk, taken from its size and modification time
+/// so the file does not have to be read.
+pub fn file_tag(meta: &Metadata) -> String {
+    let modified = meta
+        .modified()
+        .ok()
+        .and_then(|modified| modified.duration_since(UNIX_EPOCH).ok())
+        .unwrap_or_default();
+    format!("{:x}.{:x}-{:x}", modified.as_secs(), modified.subsec_nanos(), meta.len())
+}
+
+/// Strong ETag for the representation of `tag` sent with `encoding`, including the quotes.
+///
+/// Each encoding gets its own tag since the encoded bytes differ.
+pub fn etag(tag: &str, encoding: VlmEncoding) -> String {
+    format!("\"{}{}\"", tag, encoding.etag_suffix())
+}
+
+/// Whether the client's cached copy is still current, i.e. the response should be `304 Not Modified`.
+///
+/// `If-None-Match` wins over `If-Modified-Since` when both are sent.
+pub fn not_modified(headers: &HeaderMap, etag: &str, modified: Option<SystemTime>) -> bool {
+    if let Some(if_none_match) = headers.get("If-None-Match").and_then(|v| v.to_str().ok()) {
+        let etag = etag.trim_start_matches("W/");
+        return if_none_match
+            .split(',')
+            .map(|tag| tag.trim().trim_start_matches("W/"))
+            .any(|tag| tag == "*" || tag == etag);
+    }
+    let since = headers
+        .get("If-Modified-Since")
+        .and_then(|v| v.to_str().ok())
+        .and_then(http_date::parse);
+    match (since, modified) {
+        (Some(since), Some(modified)) => whole_seconds(modified) <= whole_seconds(since),
+        _ => false,
+    }
+}
+
+fn whole_seconds(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH).unwrap_or_default().as_secs()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use warp::http::HeaderValue;
+
+    use super::*;
+
+    fn headers(pairs: &[(&'static str, &'static str)]) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        for (name, value) in pairs {
+            headers.insert(*name, HeaderValue::from_static(value));
+        }
+        headers
+    }
+
+    #[test]
+    fn if_none_match_compares_tags_weakly() {
+        let tag = etag("1a.0-2f", VlmEncoding::Gzip);
+        assert_eq!(tag, "\"1a.0-2f-gz\"");
+        assert!(not_modified(&headers(&[("If-None-Match", "\"1a.0-2f-gz\"")]), &tag, None));
+        assert!(not_modified(&headers(&[("If-None-Match", "\"x\", W/\"1a.0-2f-gz\"")]), &tag, None));
+        assert!(not_modified(&headers(&[("If-None-Match", "*")]), &tag, None));
+        assert!(!not_modified(&headers(&[("If-None-Match", "\"1a.0-2f\"")]), &tag, None));
+        assert!(!not_modified(&headers(&[]), &tag, None));
+    }
+
+    #[test]
+    fn if_modified_since_compares_whole_seconds() {
+        let modified = UNIX_EPOCH + Duration::from_millis(784_111_777_500);
+        let date = "Sun, 06 Nov 1994 08:49:37 GMT";
+        assert!(not_modified(&headers(&[("If-Modified-Since", date)]), "\"a\"", Some(modified)));
+        let earlier = "Sun, 06 Nov 1994 08:49:36 GMT";
+        assert!(!not_modified(&headers(&[("If-Modified-Since", earlier)]), "\"a\"", Some(modified)));
+        assert!(!not_modified(&headers(&[("If-Modified-Since", date)]), "\"a\"", None));
+        assert!(!not_modified(&headers(&[("If-Modified-Since", "yesterday")]), "\"a\"", Some(modified)));
+        // A changed tag is sent again even when the date says it has not changed.
+        let both = headers(&[("If-None-Match", "\"b\""), ("If-Modified-Since", date)]);
+        assert!(!not_modified(&both, "\"a\"", Some(modified)));
+    }
+}
@@ -0,0 +1,1277 @@
+//! Server-side rendering for EJS templates.
+//!
+//! Supports `<%= %>` escaped output, `<%- %>` raw output, `<% %>` control flow,
+//! `<%# %>` comments, the `<%_`/`_%>`/`-%>` whitespace trimming tags and
+//! `include(path, data)`. Scriptlets are a subset of JavaScript: `if`/`else`,
+//! `for ... of`, `for ... in`, `array.forEach(function (item, i) { ... })`,
+//! `const`/`let`/`var` bindings and expressions over the JSON context.
+
+use std::{
+    fmt, io,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use serde_json::{Map, Number, Value};
+
+/// Nesting limit for `include`, guarding against templates that include themselves.
+const MAX_INCLUDE_DEPTH: usize = 32;
+
+/// Loads the source of a template by its resolved path.
+pub type EjsLoader<'a> = dyn Fn(&Path) -> io::Result<Arc<String>> + 'a;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct EjsError {
+    pub message: String,
+    pub file: Option<PathBuf>,
+    pub line: Option<usize>,
+}
+
+impl EjsError {
+    fn new(message: impl Into<String>) -> Self {
+        Self {
+            message: message.into(),
+            file: None,
+            line: None,
+        }
+    }
+
+    fn at(mut self, line: usize) -> Self {
+        self.line.get_or_insert(line);
+        self
+    }
+
+    fn in_file(mut self, file: &Path) -> Self {
+        if self.file.is_none() {
+            self.file = Some(file.to_path_buf());
+        }
+        self
+    }
+}
+
+impl fmt::Display for EjsError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match (&self.file, self.line) {
+            (Some(file), Some(line)) => write!(f, "{}:{}: {}", file.display(), line, self.message),
+            (Some(file), None) => write!(f, "{}: {}", file.display(), self.message),
+            (None, Some(line)) => write!(f, "line {}: {}", line, self.message),
+            (None, None) => f.write_str(&self.message),
+        }
+    }
+}
+
+impl std::error::Error for EjsError {}
+
+/// Renders a template held in memory. `include` is not available.
+pub fn render(source: &str, context: &Value) -> Result<String, EjsError> {
+    let renderer = Renderer {
+        root: None,
+        load: None,
+        depth: 0,
+    };
+    renderer.render_source(source, None, context)
+}
+
+/// Renders the template at `path`, resolving includes relative to the including
+/// file. Includes may not escape `root`.
+pub fn render_file(
+    path: &Path,
+    root: &Path,
+    context: &Value,
+    load: &EjsLoader<'_>,
+) -> Result<String, EjsError> {
+    let renderer = Renderer {
+        root: Some(root),
+        load: Some(load),
+        depth: 0,
+    };
+    renderer.render_path(path, context)
+}
+
+/// HTML-escapes a value the way EJS does for `<%= %>`.
+pub fn escape_html(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&#34;"),
+            '\'' => escaped.push_str("&#39;"),
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+// --- Template compilation ---
+
+#[derive(Debug)]
+enum Node {
+    Text(String),
+    Output { expr: Expr, escape: bool, line: usize },
+    If { branches: Vec<(Expr, Vec<Node>)>, otherwise: Vec<Node>, line: usize },
+    For { binding: Binding, iter: Expr, body: Vec<Node>, line: usize },
+    Let { name: String, expr: Expr, line: usize },
+    Eval { expr: Expr, line: usize },
+}
+
+#[derive(Debug, Clone)]
+enum Binding {
+    /// `for (const x of xs)` or `xs.forEach(function (x, i) { ... })`.
+    Of { item: String, index: Option<String> },
+    /// `for (const key in obj)`.
+    In { key: String },
+}
+
+enum Frame {
+    If { branches: Vec<(Expr, Vec<Node>)>, otherwise: Option<Vec<Node>>, line: usize },
+    For { binding: Binding, iter: Expr, body: Vec<Node>, line: usize, callback: bool },
+}
+
+/// Statement fragments found in a `<% %>` scriptlet. Blocks may span several tags.
+#[derive(Debug)]
+enum Fragment {
+    If(Expr),
+    ElseIf(Expr),
+    Else,
+    Close,
+    CloseCallback,
+    For { binding: Binding, iter: Expr, callback: bool },
+    Let(String, Expr),
+    Eval(Expr),
+}
+
+enum Segment {
+    Text(String),
+    Output { code: String, escape: bool, line: usize },
+    Code { code: String, line: usize },
+}
+
+fn split_segments(source: &str) -> Result<Vec<Segment>, EjsError> {
+    let mut segments = Vec::new();
+    let mut text = String::new();
+    let mut rest = source;
+    let mut line = 1;
+    let mut trim_next_newline = false;
+    let mut trim_next_space = false;
+
+    while !rest.is_empty() {
+        if trim_next_space {
+            let trimmed = rest.trim_start_matches([' ', '\t']);
+            rest = trimmed;
+            trim_next_space = false;
+            trim_next_newline = true;
+        }
+        if trim_next_newline {
+            if let Some(r) = rest.strip_prefix("\r\n").or_else(|| rest.strip_prefix('\n')) {
+                rest = r;
+                line += 1;
+            }
+            trim_next_newline = false;
+        }
+
+        let Some(start) = rest.find("<%") else {
+            text.push_str(&rest.replace("%%>", "%>"));
+            break;
+        };
+        let before = &rest[..start];
+        line += before.matches('\n').count();
+        text.push_str(&before.replace("%%>", "%>"));
+        rest = &rest[start + 2..];
+
+        if let Some(r) = rest.strip_prefix('%') {
+            text.push_str("<%");
+            rest = r;
+            continue;
+        }
+
+        let tag_line = line;
+        let kind = rest.chars().next().filter(|c| matches!(c, '=' | '-' | '#' | '_'));
+        if let Some(c) = kind {
+            rest = &rest[c.len_utf8()..];
+        }
+        if kind == Some('_') {
+            let kept = text.trim_end_matches([' ', '\t']).len();
+            text.truncate(kept);
+        }
+
+        let end = rest
+            .find("%>")
+            .ok_or_else(|| EjsError::new("Could not find matching close tag for \"<%\"").at(tag_line))?;
+        let mut code = &rest[..end];
+        line += code.matches('\n').count();
+        rest = &rest[end + 2..];
+        if let Some(c) = code.strip_suffix('-') {
+            code = c;
+            trim_next_newline = true;
+        } else if let Some(c) = code.strip_suffix('_') {
+            code = c;
+            trim_next_space = true;
+        }
+
+        if !text.is_empty() {
+            segments.push(Segment::Text(std::mem::take(&mut text)));
+        }
+        match kind {
+            Some('#') => {}
+            Some('=') => segments.push(Segment::Output { code: code.to_string(), escape: true, line: tag_line }),
+            Some('-') => segments.push(Segment::Output { code: code.to_string(), escape: false, line: tag_line }),
+            _ => segments.push(Segment::Code { code: code.to_string(), line: tag_line }),
+        }
+    }
+    if !text.is_empty() {
+        segments.push(Segment::Text(text));
+    }
+    Ok(segments)
+}
+
+fn compile(source: &str) -> Result<Vec<Node>, EjsError> {
+    let mut root: Vec<Node> = Vec::new();
+    let mut stack: Vec<Frame> = Vec::new();
+
+    fn current<'a>(root: &'a mut Vec<Node>, stack: &'a mut [Frame]) -> &'a mut Vec<Node> {
+        match stack.last_mut() {
+            None => root,
+            Some(Frame::If { otherwise: Some(body), .. }) => body,
+            Some(Frame::If { branches, .. }) => &mut branches.last_mut().expect("if frame has a branch").1,
+            Some(Frame::For { body, .. }) => body,
+        }
+    }
+
+    for segment in split_segments(source)? {
+        match segment {
+            Segment::Text(text) => current(&mut root, &mut stack).push(Node::Text(text)),
+            Segment::Output { code, escape, line } => {
+                let expr = Parser::new(&code).and_then(|mut p| p.expression_only()).map_err(|e| e.at(line))?;
+                current(&mut root, &mut stack).push(Node::Output { expr, escape, line });
+            }
+            Segment::Code { code, line } => {
+                for fragment in Parser::new(&code).and_then(|mut p| p.fragments()).map_err(|e| e.at(line))? {
+                    match fragment {
+                        Fragment::If(cond) => stack.push(Frame::If {
+                            branches: vec![(cond, Vec::new())],
+                            otherwise: None,
+                            line,
+                        }),
+                        Fragment::ElseIf(cond) => match stack.last_mut() {
+                            Some(Frame::If { branches, otherwise: None, .. }) => branches.push((cond, Vec::new())),
+                            _ => return Err(EjsError::new("Unexpected \"else if\"").at(line)),
+                        },
+                        Fragment::Else => match stack.last_mut() {
+                            Some(Frame::If { otherwise: otherwise @ None, .. }) => *otherwise = Some(Vec::new()),
+                            _ => return Err(EjsError::new("Unexpected \"else\"").at(line)),
+                        },
+                        Fragment::For { binding, iter, callback } => stack.push(Frame::For {
+                            binding,
+                            iter,
+                            body: Vec::new(),
+                            line,
+                            callback,
+                        }),
+                        Fragment::Close | Fragment::CloseCallback => {
+                            let closes_callback = matches!(fragment, Fragment::CloseCallback);
+                            let node = match stack.pop() {
+                                Some(Frame::If { branches, otherwise, line }) if !closes_callback => Node::If {
+                                    branches,
+                                    otherwise: otherwise.unwrap_or_default(),
+                                    line,
+                                },
+                                Some(Frame::For { binding, iter, body, line, callback }) if callback == closes_callback => {
+                                    Node::For { binding, iter, body, line }
+                                }
+                                _ => return Err(EjsError::new("Unexpected \"}\"").at(line)),
+                            };
+                            current(&mut root, &mut stack).push(node);
+                        }
+                        Fragment::Let(name, expr) => current(&mut root, &mut stack).push(Node::Let { name, expr, line }),
+                        Fragment::Eval(expr) => current(&mut root, &mut stack).push(Node::Eval { expr, line }),
+                    }
+                }
+            }
+        }
+    }
+
+    match stack.last() {
+        None => Ok(root),
+        Some(Frame::If { line, .. } | Frame::For { line, .. }) => {
+            Err(EjsError::new("Unclosed block, missing \"}\"").at(*line))
+        }
+    }
+}
+
+// --- Scriptlet tokenizer and parser ---
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Ident(String),
+    Num(f64),
+    Str(String),
+    Punct(&'static str),
+}
+
+const PUNCTUATION: &[&str] = &[
+    "===", "!==", "=>", "==", "!=", "<=", ">=", "&&", "||", "??", "(", ")", "[", "]", "{", "}", ".", ",", ":",
+    ";", "?", "!", "+", "-", "*", "/", "%", "<", ">", "=",
+];
+
+fn tokenize(code: &str) -> Result<Vec<Token>, EjsError> {
+    let mut tokens = Vec::new();
+    let chars: Vec<char> = code.chars().collect();
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        if c.is_whitespace() {
+            i += 1;
+        } else if c.is_ascii_alphabetic() || c == '_' || c == '$' {
+            let start = i;
+            while i < chars.len() && (chars[i].is_ascii_alphanumeric() || chars[i] == '_' || chars[i] == '$') {
+                i += 1;
+            }
+            tokens.push(Token::Ident(chars[start..i].iter().collect()));
+        } else if c.is_ascii_digit() || (c == '.' && chars.get(i + 1).is_some_and(char::is_ascii_digit)) {
+            let start = i;
+            while i < chars.len() && (chars[i].is_ascii_digit() || chars[i] == '.') {
+                i += 1;
+            }
+            if i < chars.len() && (chars[i] == 'e' || chars[i] == 'E') {
+                i += 1;
+                if i < chars.len() && (chars[i] == '+' || chars[i] == '-') {
+                    i += 1;
+                }
+                while i < chars.len() && chars[i].is_ascii_digit() {
+                    i += 1;
+                }
+            }
+            let literal: String = chars[start..i].iter().collect();
+            let number = literal
+                .parse::<f64>()
+                .map_err(|_| EjsError::new(format!("Invalid number \"{}\"", literal)))?;
+            tokens.push(Token::Num(number));
+        } else if c == '\'' || c == '"' {
+            let mut value = String::new();
+            i += 1;
+            loop {
+                match chars.get(i) {
+                    None => return Err(EjsError::new("Unterminated string literal")),
+                    Some(&q) if q == c => break,
+                    Some('\\') => {
+                        i += 1;
+                        match chars.get(i) {
+                            Some('n') => value.push('\n'),
+                            Some('t') => value.push('\t'),
+                            Some('r') => value.push('\r'),
+                            Some(&other) => value.push(other),
+                            None => return Err(EjsError::new("Unterminated string literal")),
+                        }
+                    }
+                    Some(&other) => value.push(other),
+                }
+                i += 1;
+            }
+            i += 1;
+            tokens.push(Token::Str(value));
+        } else {
+            let rest: String = chars[i..chars.len().min(i + 3)].iter().collect();
+            let punct = PUNCTUATION
+                .iter()
+                .find(|p| rest.starts_with(**p))
+                .ok_or_else(|| EjsError::new(format!("Unexpected character '{}'", c)))?;
+            i += punct.chars().count();
+            tokens.push(Token::Punct(punct));
+        }
+    }
+    Ok(tokens)
+}
+
+#[derive(Debug, Clone)]
+enum Expr {
+    Lit(Value),
+    Ident(String),
+    Member(Box<Expr>, String),
+    Index(Box<Expr>, Box<Expr>),
+    Call(Box<Expr>, Vec<Expr>),
+    Unary(&'static str, Box<Expr>),
+    Binary(&'static str, Box<Expr>, Box<Expr>),
+    Cond(Box<Expr>, Box<Expr>, Box<Expr>),
+    Array(Vec<Expr>),
+    Object(Vec<(String, Expr)>),
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    pos: usize,
+}
+
+impl Parser {
+    fn new(code: &str) -> Result<Self, EjsError> {
+        Ok(Self { tokens: tokenize(code)?, pos: 0 })
+    }
+
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos)
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.pos).cloned();
+        self.pos += 1;
+        token
+    }
+
+    fn is_punct(&self, punct: &str) -> bool {
+        matches!(self.peek(), Some(Token::Punct(p)) if *p == punct)
+    }
+
+    fn is_ident(&self, ident: &str) -> bool {
+        matches!(self.peek(), Some(Token::Ident(i)) if i == ident)
+    }
+
+    fn eat_punct(&mut self, punct: &str) -> bool {
+        let found = self.is_punct(punct);
+        if found {
+            self.pos += 1;
+        }
+        found
+    }
+
+    fn eat_ident(&mut self, ident: &str) -> bool {
+        let found = self.is_ident(ident);
+        if found {
+            self.pos += 1;
+        }
+        found
+    }
+
+    fn expect_punct(&mut self, punct: &str) -> Result<(), EjsError> {
+        if self.eat_punct(punct) {
+            Ok(())
+        } else {
+            Err(EjsError::new(format!("Expected \"{}\"", punct)))
+        }
+    }
+
+    fn expect_ident(&mut self) -> Result<String, EjsError> {
+        match self.next() {
+            Some(Token::Ident(name)) => Ok(name),
+            _ => Err(EjsError::new("Expected an identifier")),
+        }
+    }
+
+    fn expression_only(&mut self) -> Result<Expr, EjsError> {
+        let expr = self.expression()?;
+        self.eat_punct(";");
+        if self.peek().is_some() {
+            return Err(EjsError::new("Unexpected token after expression"));
+        }
+        Ok(expr)
+    }
+
+    fn fragments(&mut self) -> Result<Vec<Fragment>, EjsError> {
+        let mut fragments = Vec::new();
+        while self.peek().is_some() {
+            if self.eat_punct(";") {
+                continue;
+            }
+            if self.eat_punct("}") {
+                if self.eat_punct(")") {
+                    fragments.push(Fragment::CloseCallback);
+                } else if self.eat_ident("else") {
+                    if self.eat_ident("if") {
+                        let cond = self.condition()?;
+                        self.expect_punct("{")?;
+                        fragments.push(Fragment::ElseIf(cond));
+                    } else {
+                        self.expect_punct("{")?;
+                        fragments.push(Fragment::Else);
+                    }
+                } else {
+                    fragments.push(Fragment::Close);
+                }
+            } else if self.eat_ident("if") {
+                let cond = self.condition()?;
+                self.expect_punct("{")?;
+                fragments.push(Fragment::If(cond));
+            } else if self.eat_ident("for") {
+                self.expect_punct("(")?;
+                for decl in ["const", "let", "var"] {
+                    if self.eat_ident(decl) {
+                        break;
+                    }
+                }
+                let name = self.expect_ident()?;
+                let binding = if self.eat_ident("of") {
+                    Binding::Of { item: name, index: None }
+                } else if self.eat_ident("in") {
+                    Binding::In { key: name }
+                } else {
+                    return Err(EjsError::new("Only \"for ... of\" and \"for ... in\" loops are supported"));
+                };
+                let iter = self.expression()?;
+                self.expect_punct(")")?;
+                self.expect_punct("{")?;
+                fragments.push(Fragment::For { binding, iter, callback: false });
+            } else if self.is_ident("const") || self.is_ident("let") || self.is_ident("var") {
+                self.pos += 1;
+                let name = self.expect_ident()?;
+                self.expect_punct("=")?;
+                fragments.push(Fragment::Let(name, self.expression()?));
+            } else if matches!(self.peek(), Some(Token::Punct("!" | "-" | "+"))) || self.is_ident("typeof") {
+                fragments.push(Fragment::Eval(self.expression()?));
+            } else {
+                let expr = self.postfix(true)?;
+                if let Some(fragment) = self.for_each(&expr)? {
+                    fragments.push(fragment);
+                    continue;
+                }
+                let expr = self.binary_rest(expr, 0)?;
+                fragments.push(Fragment::Eval(self.conditional_rest(expr)?));
+            }
+        }
+        Ok(fragments)
+    }
+
+    /// Recognizes `xs.forEach(function (x, i) {` and `xs.forEach((x, i) => {`.
+    fn for_each(&mut self, callee: &Expr) -> Result<Option<Fragment>, EjsError> {
+        let Expr::Member(target, method) = callee else {
+            return Ok(None);
+        };
+        if method != "forEach" || !self.eat_punct("(") {
+            return Ok(None);
+        }
+        let function = self.eat_ident("function");
+        let mut params = Vec::new();
+        if self.eat_punct("(") {
+            while !self.eat_punct(")") {
+                params.push(self.expect_ident()?);
+                self.eat_punct(",");
+            }
+        } else {
+            params.push(self.expect_ident()?);
+        }
+        if !function {
+            self.expect_punct("=>")?;
+        }
+        self.expect_punct("{")?;
+        let mut params = params.into_iter();
+        let item = params.next().ok_or_else(|| EjsError::new("forEach callback needs a parameter"))?;
+        Ok(Some(Fragment::For {
+            binding: Binding::Of { item, index: params.next() },
+            iter: (**target).clone(),
+            callback: true,
+        }))
+    }
+
+    fn condition(&mut self) -> Result<Expr, EjsError> {
+        self.expect_punct("(")?;
+        let cond = self.expression()?;
+        self.expect_punct(")")?;
+        Ok(cond)
+    }
+
+    fn expression(&mut self) -> Result<Expr, EjsError> {
+        let lhs = self.unary()?;
+        let expr = self.binary_rest(lhs, 0)?;
+        self.conditional_rest(expr)
+    }
+
+    fn conditional_rest(&mut self, cond: Expr) -> Result<Expr, EjsError> {
+        if !self.eat_punct("?") {
+            return Ok(cond);
+        }
+        let then = self.expression()?;
+        self.expect_punct(":")?;
+        let otherwise = self.expression()?;
+        Ok(Expr::Cond(Box::new(cond), Box::new(then), Box::new(otherwise)))
+    }
+
+    fn binary_rest(&mut self, mut lhs: Expr, min_prec: u8) -> Result<Expr, EjsError> {
+        loop {
+            let (op, prec) = match self.peek() {
+                Some(Token::Punct(op)) => match binary_precedence(op) {
+                    Some(prec) if prec >= min_prec => (*op, prec),
+                    _ => return Ok(lhs),
+                },
+                _ => return Ok(lhs),
+            };
+            self.pos += 1;
+            let mut rhs = self.unary()?;
+            while let Some(Token::Punct(next)) = self.peek() {
+                match binary_precedence(next) {
+                    Some(next_prec) if next_prec > prec => rhs = self.binary_rest(rhs, next_prec)?,
+                    _ => break,
+                }
+            }
+            lhs = Expr::Binary(op, Box::new(lhs), Box::new(rhs));
+        }
+    }
+
+    fn unary(&mut self) -> Result<Expr, EjsError> {
+        for op in ["!", "-", "+"] {
+            if self.eat_punct(op) {
+                return Ok(Expr::Unary(op, Box::new(self.unary()?)));
+            }
+        }
+        if self.eat_ident("typeof") {
+            return Ok(Expr::Unary("typeof", Box::new(self.unary()?)));
+        }
+        self.postfix(false)
+    }
+
+    /// Parses a primary expression with member accesses and calls. When `stop_at_for_each`
+    /// is set, a trailing `.forEach` is left unapplied so the caller can open a loop.
+    fn postfix(&mut self, stop_at_for_each: bool) -> Result<Expr, EjsError> {
+        let mut expr = self.primary()?;
+        loop {
+            if self.eat_punct(".") {
+                let name = self.expect_ident()?;
+                expr = Expr::Member(Box::new(expr), name);
+                if stop_at_for_each
+                    && matches!(&expr, Expr::Member(_, m) if m == "forEach")
+                    && self.is_punct("(")
+                {
+                    return Ok(expr);
+                }
+            } else if self.eat_punct("[") {
+                let index = self.expression()?;
+                self.expect_punct("]")?;
+                expr = Expr::Index(Box::new(expr), Box::new(index));
+            } else if self.eat_punct("(") {
+                let mut args = Vec::new();
+                while !self.eat_punct(")") {
+                    args.push(self.expression()?);
+                    if !self.eat_punct(",") && !self.is_punct(")") {
+                        return Err(EjsError::new("Expected \",\" or \")\" in call"));
+                    }
+                }
+                expr = Expr::Call(Box::new(expr), args);
+            } else {
+                return Ok(expr);
+            }
+        }
+    }
+
+    fn primary(&mut self) -> Result<Expr, EjsError> {
+        match self.next() {
+            Some(Token::Num(n)) => Ok(Expr::Lit(number(n))),
+            Some(Token::Str(s)) => Ok(Expr::Lit(Value::String(s))),
+            Some(Token::Ident(name)) => Ok(match name.as_str() {
+                "true" => Expr::Lit(Value::Bool(true)),
+                "false" => Expr::Lit(Value::Bool(false)),
+                "null" | "undefined" => Expr::Lit(Value::Null),
+                _ => Expr::Ident(name),
+            }),
+            Some(Token::Punct("(")) => {
+                let expr = self.expression()?;
+                self.expect_punct(")")?;
+                Ok(expr)
+            }
+            Some(Token::Punct("[")) => {
+                let mut items = Vec::new();
+                while !self.eat_punct("]") {
+                    items.push(self.expression()?);
+                    if !self.eat_punct(",") && !self.is_punct("]") {
+                        return Err(EjsError::new("Expected \",\" or \"]\" in array"));
+                    }
+                }
+                Ok(Expr::Array(items))
+            }
+            Some(Token::Punct("{")) => {
+                let mut entries = Vec::new();
+                while !self.eat_punct("}") {
+                    let key = match self.next() {
+                        Some(Token::Ident(key) | Token::Str(key)) => key,
+                        _ => return Err(EjsError::new("Expected a property name")),
+                    };
+                    let value = if self.eat_punct(":") {
+                        self.expression()?
+                    } else {
+                        Expr::Ident(key.clone())
+                    };
+                    entries.push((key, value));
+                    if !self.eat_punct(",") && !self.is_punct("}") {
+                        return Err(EjsError::new("Expected \",\" or \"}\" in object"));
+                    }
+                }
+                Ok(Expr::Object(entries))
+            }
+            Some(token) => Err(EjsError::new(format!("Unexpected token {:?}", token))),
+            None => Err(EjsError::new("Unexpected end of expression")),
+        }
+    }
+}
+
+fn binary_precedence(op: &str) -> Option<u8> {
+    Some(match op {
+        "??" => 1,
+        "||" => 2,
+        "&&" => 3,
+        "==" | "!=" | "===" | "!==" => 4,
+        "<" | ">" | "<=" | ">=" => 5,
+        "+" | "-" => 6,
+        "*" | "/" | "%" => 7,
+        _ => return None,
+    })
+}
+
+// --- Evaluation ---
+
+struct Renderer<'r> {
+    root: Option<&'r Path>,
+    load: Option<&'r EjsLoader<'r>>,
+    depth: usize,
+}
+
+struct Scope<'c> {
+    context: &'c Value,
+    locals: Vec<(String, Value)>,
+    file: Option<&'c Path>,
+}
+
+impl Scope<'_> {
+    fn lookup(&self, name: &str) -> Result<Value, EjsError> {
+        if let Some((_, value)) = self.locals.iter().rev().find(|(n, _)| n == name) {
+            return Ok(value.clone());
+        }
+        if name == "locals" {
+            return Ok(self.context.clone());
+        }
+        self.context
+            .get(name)
+            .cloned()
+            .ok_or_else(|| EjsError::new(format!("{} is not defined", name)))
+    }
+}
+
+impl Renderer<'_> {
+    fn render_path(&self, path: &Path, context: &Value) -> Result<String, EjsError> {
+        let load = self.load.ok_or_else(|| EjsError::new("include is not available here"))?;
+        let source = load(path).map_err(|e| EjsError::new(format!("Failed to read template: {}", e)).in_file(path))?;
+        self.render_source(&source, Some(path), context)
+            .map_err(|e| e.in_file(path))
+    }
+
+    fn render_source(&self, source: &str, file: Option<&Path>, context: &Value) -> Result<String, EjsError> {
+        let nodes = compile(source)?;
+        let mut scope = Scope {
+            context,
+            locals: Vec::new(),
+            file,
+        };
+        let mut out = String::with_capacity(source.len());
+        self.render_nodes(&nodes, &mut scope, &mut out)?;
+        Ok(out)
+    }
+
+    fn render_nodes(&self, nodes: &[Node], scope: &mut Scope<'_>, out: &mut String) -> Result<(), EjsError> {
+        for node in nodes {
+            match node {
+                Node::Text(text) => out.push_str(text),
+                Node::Output { expr, escape, line } => {
+                    let value = self.eval(expr, scope).map_err(|e| e.at(*line))?;
+                    let text = to_output(&value);
+                    if *escape {
+                        out.push_str(&escape_html(&text));
+                    } else {
+                        out.push_str(&text);
+                    }
+                }
+                Node::If { branches, otherwise, line } => {
+                    let mut taken = None;
+                    for (cond, body) in branches {
+                        if truthy(&self.eval(cond, scope).map_err(|e| e.at(*line))?) {
+                            taken = Some(body);
+                            break;
+                        }
+                    }
+                    let body = taken.unwrap_or(otherwise);
+                    let depth = scope.locals.len();
+                    self.render_nodes(body, scope, out)?;
+                    scope.locals.truncate(depth);
+                }
+                Node::For { binding, iter, body, line } => {
+                    let iterable = self.eval(iter, scope).map_err(|e| e.at(*line))?;
+                    let items: Vec<(Value, Value)> = match (binding, iterable) {
+                        (Binding::Of { .. }, Value::Array(items)) => items
+                            .into_iter()
+                            .enumerate()
+                            .map(|(i, item)| (item, Value::from(i)))
+                            .collect(),
+                        (Binding::Of { .. }, Value::String(s)) => s
+                            .chars()
+                            .enumerate()
+                            .map(|(i, c)| (Value::String(c.to_string()), Value::from(i)))
+                            .collect(),
+                        (Binding::In { .. }, Value::Object(map)) => {
+                            map.into_iter().map(|(k, _)| (Value::String(k), Value::Null)).collect()
+                        }
+                        (Binding::In { .. }, Value::Array(items)) => (0..items.len())
+                            .map(|i| (Value::String(i.to_string()), Value::Null))
+                            .collect(),
+                        (Binding::In { .. }, Value::Null) => Vec::new(),
+                        (_, other) => {
+                            return Err(EjsError::new(format!("{} is not iterable", type_of(&other))).at(*line));
+                        }
+                    };
+                    for (item, index) in items {
+                        let depth = scope.locals.len();
+                        match binding {
+                            Binding::Of { item: name, index: index_name } => {
+                                scope.locals.push((name.clone(), item));
+                                if let Some(index_name) = index_name {
+                                    scope.locals.push((index_name.clone(), index));
+                                }
+                            }
+                            Binding::In { key } => scope.locals.push((key.clone(), item)),
+                        }
+                        self.render_nodes(body, scope, out)?;
+                        scope.locals.truncate(depth);
+                    }
+                }
+                Node::Let { name, expr, line } => {
+                    let value = self.eval(expr, scope).map_err(|e| e.at(*line))?;
+                    scope.locals.push((name.clone(), value));
+                }
+                Node::Eval { expr, line } => {
+                    self.eval(expr, scope).map_err(|e| e.at(*line))?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn eval(&self, expr: &Expr, scope: &Scope<'_>) -> Result<Value, EjsError> {
+        match expr {
+            Expr::Lit(value) => Ok(value.clone()),
+            Expr::Ident(name) => scope.lookup(name),
+            Expr::Member(target, name) => {
+                let target = self.eval(target, scope)?;
+                property(&target, name)
+            }
+            Expr::Index(target, index) => {
+                let target = self.eval(target, scope)?;
+                let index = self.eval(index, scope)?;
+                property(&target, &to_output(&index))
+            }
+            Expr::Call(callee, args) => self.call(callee, args, scope),
+            Expr::Unary(op, operand) => {
+                let value = self.eval(operand, scope)?;
+                Ok(match *op {
+                    "!" => Value::Bool(!truthy(&value)),
+                    "-" => number(-to_number(&value)),
+                    "+" => number(to_number(&value)),
+                    _ => Value::String(type_of(&value).to_string()),
+                })
+            }
+            Expr::Binary(op, lhs, rhs) => {
+                let lhs = self.eval(lhs, scope)?;
+                match *op {
+                    "&&" => return if truthy(&lhs) { self.eval(rhs, scope) } else { Ok(lhs) },
+                    "||" => return if truthy(&lhs) { Ok(lhs) } else { self.eval(rhs, scope) },
+                    "??" => return if lhs.is_null() { self.eval(rhs, scope) } else { Ok(lhs) },
+                    _ => {}
+                }
+                let rhs = self.eval(rhs, scope)?;
+                Ok(binary(op, &lhs, &rhs))
+            }
+            Expr::Cond(cond, then, otherwise) => {
+                if truthy(&self.eval(cond, scope)?) {
+                    self.eval(then, scope)
+                } else {
+                    self.eval(otherwise, scope)
+                }
+            }
+            Expr::Array(items) => items
+                .iter()
+                .map(|item| self.eval(item, scope))
+                .collect::<Result<Vec<_>, _>>()
+                .map(Value::Array),
+            Expr::Object(entries) => {
+                let mut map = Map::new();
+                for (key, value) in entries {
+                    map.insert(key.clone(), self.eval(value, scope)?);
+                }
+                Ok(Value::Object(map))
+            }
+        }
+    }
+
+    fn call(&self, callee: &Expr, args: &[Expr], scope: &Scope<'_>) -> Result<Value, EjsError> {
+        let args = args
+            .iter()
+            .map(|arg| self.eval(arg, scope))
+            .collect::<Result<Vec<_>, _>>()?;
+        let arg = |i: usize| args.get(i).cloned().unwrap_or(Value::Null);
+
+        match callee {
+            Expr::Ident(name) if name == "include" => self.include(&arg(0), &arg(1), scope),
+            Expr::Ident(name) if name == "String" => Ok(Value::String(to_output(&arg(0)))),
+            Expr::Ident(name) if name == "Number" => Ok(number(to_number(&arg(0)))),
+            Expr::Ident(name) if name == "Boolean" => Ok(Value::Bool(truthy(&arg(0)))),
+            Expr::Member(object, method) => match (object.as_ref(), method.as_str()) {
+                (Expr::Ident(o), "stringify") if o == "JSON" => serde_json::to_string(&arg(0))
+                    .map(Value::String)
+                    .map_err(|e| EjsError::new(e.to_string())),
+                (Expr::Ident(o), "keys" | "values" | "entries") if o == "Object" => {
+                    let map = match arg(0) {
+                        Value::Object(map) => map,
+                        _ => Map::new(),
+                    };
+                    Ok(Value::Array(
+                        map.into_iter()
+                            .map(|(k, v)| match method.as_str() {
+                                "keys" => Value::String(k),
+                                "values" => v,
+                                _ => Value::Array(vec![Value::String(k), v]),
+                            })
+                            .collect(),
+                    ))
+                }
+                (Expr::Ident(o), _) if o == "Math" => {
+                    let x = to_number(&arg(0));
+                    Ok(number(match method.as_str() {
+                        "round" => x.round(),
+                        "floor" => x.floor(),
+                        "ceil" => x.ceil(),
+                        "abs" => x.abs(),
+                        "min" => args.iter().map(to_number).fold(f64::INFINITY, f64::min),
+                        "max" => args.iter().map(to_number).fold(f64::NEG_INFINITY, f64::max),
+                        _ => return Err(EjsError::new(format!("Math.{} is not a function", method))),
+                    }))
+                }
+                _ => {
+                    let target = self.eval(object, scope)?;
+                    method_call(&target, method, &args)
+                }
+            },
+            _ => Err(EjsError::new("Expression is not a function")),
+        }
+    }
+
+    fn include(&self, path: &Value, data: &Value, scope: &Scope<'_>) -> Result<Value, EjsError> {
+        let (Some(root), Some(file)) = (self.root, scope.file) else {
+            return Err(EjsError::new("include is not available for in-memory templates"));
+        };
+        if self.depth >= MAX_INCLUDE_DEPTH {
+            return Err(EjsError::new("Maximum include depth exceeded"));
+        }
+        let Value::String(name) = path else {
+            return Err(EjsError::new("include expects a path string"));
+        };
+
+        let mut target = file.parent().unwrap_or(root).join(name);
+        if target.extension().is_none() {
+            target.set_extension("ejs");
+        }
+        let target = target
+            .canonicalize()
+            .map_err(|e| EjsError::new(format!("Could not find include \"{}\": {}", name, e)))?;
+        if !target.starts_with(root) {
+            return Err(EjsError::new(format!("include \"{}\" is outside of the content root", name)));
+        }
+
+        let mut context = match scope.context {
+            Value::Object(map) => map.clone(),
+            _ => Map::new(),
+        };
+        if let Value::Object(extra) = data {
+            context.extend(extra.clone());
+        }
+        let nested = Renderer {
+            root: self.root,
+            load: self.load,
+            depth: self.depth + 1,
+        };
+        nested.render_path(&target, &Value::Object(context)).map(Value::String)
+    }
+}
+
+fn method_call(target: &Value, method: &str, args: &[Value]) -> Result<Value, EjsError> {
+    let arg = |i: usize| args.get(i).cloned().unwrap_or(Value::Null);
+    let index_arg = |i: usize, len: usize, default: usize| match args.get(i) {
+        None | Some(Value::Null) => default,
+        Some(v) => {
+            let n = to_number(v) as i64;
+            if n < 0 { len.saturating_sub(n.unsigned_abs() as usize) } else { (n as usize).min(len) }
+        }
+    };
+    match (target, method) {
+        (Value::String(s), "toUpperCase") => Ok(Value::String(s.to_uppercase())),
+        (Value::String(s), "toLowerCase") => Ok(Value::String(s.to_lowercase())),
+        (Value::String(s), "trim") => Ok(Value::String(s.trim().to_string())),
+        (Value::String(s), "includes") => Ok(Value::Bool(s.contains(&to_output(&arg(0))))),
+        (Value::String(s), "startsWith") => Ok(Value::Bool(s.starts_with(&to_output(&arg(0))))),
+        (Value::String(s), "endsWith") => Ok(Value::Bool(s.ends_with(&to_output(&arg(0))))),
+        (Value::String(s), "replace") => Ok(Value::String(s.replacen(&to_output(&arg(0)), &to_output(&arg(1)), 1))),
+        (Value::String(s), "split") => Ok(Value::Array(
+            s.split(to_output(&arg(0)).as_str())
+                .map(|part| Value::String(part.to_string()))
+                .collect(),
+        )),
+        (Value::String(s), "slice" | "substring") => {
+            let chars: Vec<char> = s.chars().collect();
+            let start = index_arg(0, chars.len(), 0);
+            let end = index_arg(1, chars.len(), chars.len());
+            Ok(Value::String(chars[start..end.max(start)].iter().collect()))
+        }
+        (Value::Array(items), "join") => {
+            let sep = match args.first() {
+                None | Some(Value::Null) => ",".to_string(),
+                Some(sep) => to_output(sep),
+            };
+            Ok(Value::String(items.iter().map(to_output).collect::<Vec<_>>().join(&sep)))
+        }
+        (Value::Array(items), "includes") => Ok(Value::Bool(items.iter().any(|i| strict_equals(i, &arg(0))))),
+        (Value::Array(items), "indexOf") => Ok(number(
+            items.iter().position(|i| strict_equals(i, &arg(0))).map_or(-1.0, |p| p as f64),
+        )),
+        (Value::Array(items), "slice") => {
+            let start = index_arg(0, items.len(), 0);
+            let end = index_arg(1, items.len(), items.len());
+            Ok(Value::Array(items[start..end.max(start)].to_vec()))
+        }
+        (Value::Number(_), "toFixed") => {
+            let digits = match to_number(&arg(0)) {
+                digits if digits.is_nan() => 0.0,
+                digits => digits.trunc(),
+            };
+            if !(0.0..=100.0).contains(&digits) {
+                return Err(EjsError::new("RangeError: toFixed() digits argument must be between 0 and 100"));
+            }
+            Ok(Value::String(format!("{:.*}", digits as usize, to_number(target))))
+        }
+        (_, "toString") => Ok(Value::String(to_output(target))),
+        _ => Err(EjsError::new(format!("{}.{} is not a function", type_of(target), method))),
+    }
+}
+
+fn property(target: &Value, name: &str) -> Result<Value, EjsError> {
+    match target {
+        Value::Null => Err(EjsError::new(format!(
+            "Cannot read properties of undefined (reading '{}')",
+            name
+        ))),
+        Value::String(s) if name == "length" => Ok(Value::from(s.chars().count())),
+        Value::Array(items) if name == "length" => Ok(Value::from(items.len())),
+        Value::Array(items) => Ok(name
+            .parse::<usize>()
+            .ok()
+            .and_then(|i| items.get(i).cloned())
+            .unwrap_or(Value::Null)),
+        Value::Object(map) => Ok(map.get(name).cloned().unwrap_or(Value::Null)),
+        _ => Ok(Value::Null),
+    }
+}
+
+fn binary(op: &str, lhs: &Value, rhs: &Value) -> Value {
+    match op {
+        "+" => {
+            if lhs.is_string() || rhs.is_string() || lhs.is_array() || lhs.is_object() {
+                Value::String(to_output(lhs) + &to_output(rhs))
+            } else {
+                number(to_number(lhs) + to_number(rhs))
+            }
+        }
+        "-" => number(to_number(lhs) - to_number(rhs)),
+        "*" => number(to_number(lhs) * to_number(rhs)),
+        "/" => number(to_number(lhs) / to_number(rhs)),
+        "%" => number(to_number(lhs) % to_number(rhs)),
+        "===" => Value::Bool(strict_equals(lhs, rhs)),
+        "!==" => Value::Bool(!strict_equals(lhs, rhs)),
+        "==" => Value::Bool(loose_equals(lhs, rhs)),
+        "!=" => Value::Bool(!loose_equals(lhs, rhs)),
+        _ => {
+            let ordering = match (lhs, rhs) {
+                (Value::String(a), Value::String(b)) => a.partial_cmp(b),
+                _ => to_number(lhs).partial_cmp(&to_number(rhs)),
+            };
+            Value::Bool(match (op, ordering) {
+                (_, None) => false,
+                ("<", Some(o)) => o.is_lt(),
+                (">", Some(o)) => o.is_gt(),
+                ("<=", Some(o)) => o.is_le(),
+                (_, Some(o)) => o.is_ge(),
+            })
+        }
+    }
+}
+
+fn number(n: f64) -> Value {
+    if n.fract() == 0.0 && n.abs() < 9.007_199_254_740_992e15 {
+        Value::from(n as i64)
+    } else {
+        Number::from_f64(n).map_or(Value::Null, Value::Number)
+    }
+}
+
+fn to_number(value: &Value) -> f64 {
+    match value {
+        Value::Number(n) => n.as_f64().unwrap_or(f64::NAN),
+        Value::Bool(b) => f64::from(u8::from(*b)),
+        Value::Null => 0.0,
+        Value::String(s) if s.trim().is_empty() => 0.0,
+        Value::String(s) => s.trim().parse().unwrap_or(f64::NAN),
+        _ => f64::NAN,
+    }
+}
+
+fn truthy(value: &Value) -> bool {
+    match value {
+        Value::Null => false,
+        Value::Bool(b) => *b,
+        Value::Number(n) => n.as_f64().is_some_and(|n| n != 0.0 && !n.is_nan()),
+        Value::String(s) => !s.is_empty(),
+        _ => true,
+    }
+}
+
+fn strict_equals(lhs: &Value, rhs: &Value) -> bool {
+    match (lhs, rhs) {
+        (Value::Number(a), Value::Number(b)) => a.as_f64() == b.as_f64(),
+        (Value::Array(_) | Value::Object(_), _) => false,
+        _ => lhs == rhs,
+    }
+}
+
+fn loose_equals(lhs: &Value, rhs: &Value) -> bool {
+    match (lhs, rhs) {
+        (Value::Null, Value::Null) => true,
+        (Value::Null, _) | (_, Value::Null) => false,
+        (Value::String(a), Value::String(b)) => a == b,
+        (Value::Array(_) | Value::Object(_), _) | (_, Value::Array(_) | Value::Object(_)) => false,
+        _ => to_number(lhs) == to_number(rhs),
+    }
+}
+
+fn type_of(value: &Value) -> &'static str {
+    match value {
+        Value::Null => "undefined",
+        Value::Bool(_) => "boolean",
+        Value::Number(_) => "number",
+        Value::String(_) => "string",
+        _ => "object",
+    }
+}
+
+/// Converts a value to the text `String(value)` would produce, with `null` as empty.
+fn to_output(value: &Value) -> String {
+    match value {
+        Value::Null => String::new(),
+        Value::String(s) => s.clone(),
+        Value::Bool(b) => b.to_string(),
+        Value::Number(n) => match (n.as_i64(), n.as_f64()) {
+            (Some(i), _) => i.to_string(),
+            (None, Some(f)) if f.fract() == 0.0 && f.abs() < 1e21 => format!("{:.0}", f),
+            (None, Some(f)) => f.to_string(),
+            _ => n.to_string(),
+        },
+        Value::Array(items) => items.iter().map(to_output).collect::<Vec<_>>().join(","),
+        Value::Object(_) => "[object Object]".to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{env, fs};
+
+    use serde_json::json;
+
+    use super::*;
+
+    fn ok(source: &str, context: Value) -> String {
+        render(source, &context).unwrap()
+    }
+
+    fn message(source: &str) -> String {
+        render(source, &json!({})).unwrap_err().message
+    }
+
+    #[test]
+    fn escaped_and_raw_output() {
+        let context = json!({"name": "<b>\"Ann\" & 'Bo'</b>"});
+        assert_eq!(ok("<%= name %>", context.clone()), "&lt;b&gt;&#34;Ann&#34; &amp; &#39;Bo&#39;&lt;/b&gt;");
+        assert_eq!(ok("<%- name %>", context), "<b>\"Ann\" & 'Bo'</b>");
+        assert_eq!(ok("a<%# note %>b", json!({})), "ab");
+    }
+
+    #[test]
+    fn control_flow() {
+        let context = json!({"items": ["a", "b"], "user": {"admin": true}});
+        let source = "<% if (user.admin) { %>admin<% } else { %>guest<% } %>";
+        assert_eq!(ok(source, context.clone()), "admin");
+        let source = "<% for (const item of items) { %>[<%= item %>]<% } %>";
+        assert_eq!(ok(source, context.clone()), "[a][b]");
+        let source = "<% items.forEach(function (item, i) { %><%= i %>=<%= item %>;<% }) %>";
+        assert_eq!(ok(source, context.clone()), "0=a;1=b;");
+        let source = "<% for (const key in user) { %><%= key %><% } %>";
+        assert_eq!(ok(source, context), "admin");
+    }
+
+    #[test]
+    fn whitespace_trimming() {
+        assert_eq!(ok("a\n<% if (true) { -%>\nb\n<% } -%>\nc", json!({})), "a\nb\nc");
+        assert_eq!(ok("a\n    <%_ if (true) { _%>\nb\n<% } %>", json!({})), "a\nb\n");
+    }
+
+    #[test]
+    fn errors_name_the_line() {
+        let error = render("a\n<%= missing %>", &json!({})).unwrap_err();
+        assert_eq!((error.message.as_str(), error.line), ("missing is not defined", Some(2)));
+        assert!(message("<%= 'a'.nope() %>").contains("is not a function"));
+    }
+
+    #[test]
+    fn to_fixed_bounds_its_digits() {
+        assert_eq!(ok("<%= (1.005).toFixed(1) %>", json!({})), "1.0");
+        assert_eq!(ok("<%= n.toFixed() %>", json!({"n": 2.5})), "2");
+        assert_eq!(ok("<%= n.toFixed(2.9) %>", json!({"n": 1})), "1.00");
+        assert_eq!(ok("<%= n.toFixed(100) %>", json!({"n": 0})).len(), 102);
+        assert!(message("<%= (1).toFixed(101) %>").starts_with("RangeError"));
+        assert!(message("<%= (1).toFixed(-1) %>").starts_with("RangeError"));
+        assert!(message("<%= (1).toFixed(1e300) %>").starts_with("RangeError"));
+    }
+
+    /// A content root holding `files`, canonicalized like `VlmFiles` does.
+    fn root(name: &str, files: &[(&str, &str)]) -> PathBuf {
+        let root = env::temp_dir().join(format!("vlm-ejs-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&root);
+        for (path, source) in files {
+            let path = root.join(path);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, source).unwrap();
+        }
+        root.canonicalize().unwrap()
+    }
+
+    fn render_in(root: &Path, path: &str, context: Value) -> Result<String, EjsError> {
+        let load = |path: &Path| fs::read_to_string(path).map(Arc::new);
+        render_file(&root.join(path), root, &context, &load)
+    }
+
+    #[test]
+    fn includes_resolve_relative_to_the_including_file() {
+        let root = root(
+            "include",
+            &[
+                ("pages/index.ejs", "<%- include('../partials/title', {title: 'Home'}) %>|<%= site %>"),
+                ("partials/title.ejs", "<h1><%= title %> - <%= site %></h1>"),
+            ],
+        );
+        let page = render_in(&root, "pages/index.ejs", json!({"site": "vlm"})).unwrap();
+        assert_eq!(page, "<h1>Home - vlm</h1>|vlm");
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn includes_stay_in_the_root_and_stop_recursing() {
+        let outside = root("outside", &[("secret.ejs", "secret")]);
+        let include = format!("<%- include('{}') %>", outside.join("secret").display());
+        let root = root("escape", &[("escape.ejs", &include), ("self.ejs", "<%- include('self') %>")]);
+        let error = render_in(&root, "escape.ejs", json!({})).unwrap_err();
+        assert!(error.message.contains("outside of the content root"));
+        let error = render_in(&root, "self.ejs", json!({})).unwrap_err();
+        assert_eq!(error.message, "Maximum include depth exceeded");
+        assert!(render("<%- include('x') %>", &json!({})).is_err());
+        fs::remove_dir_all(root).unwrap();
+        fs::remove_dir_all(outside).unwrap();
+    }
+}
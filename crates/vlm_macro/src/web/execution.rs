@@ -0,0 +1,167 @@
+use std::{fmt, time::Duration};
+
+use serde::Serialize;
+use serde_json::Value;
+
+/// Why a snippet did not run to completion.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum VlmRunError {
+    /// The code failed to parse, so nothing ran.
+    Syntax { message: String, line: Option<u32> },
+    /// The code raised an error or exception while running.
+    Runtime { message: String, traceback: Option<String> },
+    /// The code ran past the wall-clock limit of the environment.
+    Timeout {
+        #[serde(with = "millis")]
+        limit: Duration,
+    },
+    /// The code hit a resource limit other than time, such as memory or instructions.
+    ResourceLimit { message: String },
+}
+
+impl fmt::Display for VlmRunError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            VlmRunError::Syntax {
+                message,
+                line: Some(line),
+            } => write!(f, "Syntax error on line {}: {}", line, message),
+            VlmRunError::Syntax { message, line: None } => write!(f, "Syntax error: {}", message),
+            VlmRunError::Runtime { message, .. } => write!(f, "Runtime error: {}", message),
+            VlmRunError::Timeout { limit } => write!(f, "Timed out after {:.1}s", limit.as_secs_f64()),
+            VlmRunError::ResourceLimit { message } => f.write_str(message),
+        }
+    }
+}
+
+impl std::error::Error for VlmRunError {}
+
+/// How a snippet ended.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(tag = "status", rename_all = "snake_case")]
+pub enum VlmExitStatus {
+    /// The code ran to the end.
+    Success,
+    /// The code exited the process it ran in with this code.
+    Exited { code: i32 },
+    /// The code failed, see [`VlmRunError`].
+    Failed { error: VlmRunError },
+}
+
+/// What a [`VirtualEnv::run_code`](super::VirtualEnv::run_code) call produced.
+///
+/// Output written before a failure is kept, so `stdout` and `stderr` are
+/// worth showing whatever the status.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct VlmExecution {
+    pub stdout: String,
+    pub stderr: String,
+    pub status: VlmExitStatus,
+    /// The value the snippet evaluated to, `None` when it has none or failed.
+    pub value: Option<Value>,
+    #[serde(rename = "duration_ms", with = "millis")]
+    pub duration: Duration,
+}
+
+impl VlmExecution {
+    pub fn is_success(&self) -> bool {
+        matches!(self.status, VlmExitStatus::Success | VlmExitStatus::Exited { code: 0 })
+    }
+
+    pub fn error(&self) -> Option<&VlmRunError> {
+        match &self.status {
+            VlmExitStatus::Failed { error } => Some(error),
+            _ => None,
+        }
+    }
+
+    /// The returned value, or the error the snippet failed with.
+    pub fn into_result(self) -> Result<Option<Value>, VlmRunError> {
+        match self.status {
+            VlmExitStatus::Failed { error } => Err(error),
+            VlmExitStatus::Exited { code } if code != 0 => Err(VlmRunError::Runtime {
+                message: format!("Exited with status {}", code),
+                traceback: None,
+            }),
+            _ => Ok(self.value),
+        }
+    }
+}
+
+mod millis {
+    use std::time::Duration;
+
+    use serde::Serializer;
+
+    pub fn serialize<S: Serializer>(duration: &Duration, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_f64(duration.as_secs_f64() * 1000.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    fn execution(status: VlmExitStatus) -> VlmExecution {
+        VlmExecution {
+            stdout: "out\n".to_string(),
+            stderr: String::new(),
+            status,
+            value: Some(json!(2)),
+            duration: Duration::from_micros(1500),
+        }
+    }
+
+    #[test]
+    fn exit_code_zero_is_success() {
+        assert!(execution(VlmExitStatus::Success).is_success());
+        assert!(execution(VlmExitStatus::Exited { code: 0 }).is_success());
+        assert!(!execution(VlmExitStatus::Exited { code: 3 }).is_success());
+        let failed = execution(VlmExitStatus::Failed {
+            error: VlmRunError::ResourceLimit {
+                message: "Out of memory".to_string(),
+            },
+        });
+        assert!(!failed.is_success());
+        assert_eq!(failed.error().map(ToString::to_string).as_deref(), Some("Out of memory"));
+    }
+
+    #[test]
+    fn into_result_fails_for_non_zero_exits() {
+        assert_eq!(execution(VlmExitStatus::Exited { code: 0 }).into_result(), Ok(Some(json!(2))));
+        assert_eq!(
+            execution(VlmExitStatus::Exited { code: 3 }).into_result(),
+            Err(VlmRunError::Runtime {
+                message: "Exited with status 3".to_string(),
+                traceback: None,
+            })
+        );
+        let limit = Duration::from_secs(2);
+        let timed_out = execution(VlmExitStatus::Failed {
+            error: VlmRunError::Timeout { limit },
+        });
+        assert_eq!(timed_out.into_result(), Err(VlmRunError::Timeout { limit }));
+    }
+
+    #[test]
+    fn durations_serialize_as_milliseconds() {
+        let serialized = serde_json::to_value(execution(VlmExitStatus::Exited { code: 1 })).unwrap();
+        assert_eq!(
+            serialized,
+            json!({
+                "stdout": "out\n",
+                "stderr": "",
+                "status": {"status": "exited", "code": 1},
+                "value": 2,
+                "duration_ms": 1.5,
+            })
+        );
+        let timeout = VlmRunError::Timeout {
+            limit: Duration::from_millis(250),
+        };
+        assert_eq!(serde_json::to_value(timeout).unwrap(), json!({"kind": "timeout", "limit": 250.0}));
+    }
+}
@@ -0,0 +1,323 @@
+use std::{
+    collections::HashMap,
+    fs, io,
+    path::{Component, Path, PathBuf},
+    sync::{Arc, Mutex},
+    time::SystemTime,
+};
+
+use serde_json::Value;
+use tokio::io::AsyncReadExt;
+
+use super::{
+    ejs::{self, EjsError},
+    registry::detect,
+    OctetStream, VlmContentType,
+};
+
+/// Index files tried, in order, when a request resolves to a directory.
+pub const INDEX_FILES: &[&str] = &["index.html", "index.htm", "index.ejs", "index.xml"];
+
+/// Bytes read from the start of a file with an unknown extension to detect its type.
+const MAGIC_LEN: usize = 16;
+
+/// Outcome of mapping a request path onto the served content.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum VlmResolved {
+    /// A file under the content root.
+    File(PathBuf),
+    /// A directory requested without its trailing slash.
+    Redirect(String),
+}
+
+/// Static content served by `start_server`, rooted at a single file or a directory.
+pub struct VlmFiles {
+    root: PathBuf,
+    single: bool,
+    cache: Mutex<HashMap<PathBuf, Cached>>,
+}
+
+/// File content along with the metadata it was read with.
+struct Cached {
+    modified: Option<SystemTime>,
+    len: u64,
+    content: Arc<String>,
+}
+
+impl VlmFiles {
+    /// Opens the content root. A file is served on `/` only, a directory is served by path.
+    pub fn new(root: PathBuf) -> io::Result<Self> {
+        let root = root.canonicalize()?;
+        let single = root.is_file();
+        Ok(Self {
+            root,
+            single,
+            cache: Mutex::new(HashMap::new()),
+        })
+    }
+
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    /// Maps a raw request path onto a file below the root.
+    ///
+    /// Returns `None` when nothing matches or when the path would escape the root,
+    /// either through `..` segments or through a symlink pointing outside of it.
+    pub async fn resolve(&self, request_path: &str) -> Option<VlmResolved> {
+        if self.single {
+            return match request_path.trim_start_matches('/') {
+                "" => Some(VlmResolved::File(self.root.clone())),
+                _ => None,
+            };
+        }
+
+        let segments = decoded_segments(request_path)?;
+        let relative: PathBuf = segments.iter().collect();
+        let candidate = tokio::fs::canonicalize(self.root.join(&relative)).await.ok()?;
+        if !candidate.starts_with(&self.root) {
+            return None;
+        }
+        if is_file(&candidate).await {
+            return Some(VlmResolved::File(candidate));
+        }
+        if !request_path.ends_with('/') {
+            return Some(VlmResolved::Redirect(location(&segments)));
+        }
+        for index in INDEX_FILES {
+            let index = candidate.join(index);
+            if is_file(&index).await {
+                return Some(VlmResolved::File(index));
+            }
+        }
+        None
+    }
+
+    /// Reads a resolved file, keeping its content cached for later requests.
+    ///
+    /// Blocks on the file system, as does [`VlmFiles::render`]; async callers run both
+    /// through `spawn_blocking`.
+    ///
+    /// Cached content is only used while the file's modification time and size are
+    /// unchanged, so edits show up even when no [`VlmWatcher`](super::watch::VlmWatcher) runs.
+    pub fn read(&self, path: &Path) -> io::Result<Arc<String>> {
+        let meta = fs::metadata(path)?;
+        let (modified, len) = (meta.modified().ok(), meta.len());
+        if let Some(cached) = self.cache.lock().unwrap().get(path)
+            && cached.modified.is_some()
+            && (cached.modified, cached.len) == (modified, len)
+        {
+            return Ok(Arc::clone(&cached.content));
+        }
+        let content = Arc::new(fs::read_to_string(path)?);
+        let cached = Cached { modified, len, content: Arc::clone(&content) };
+        self.cache.lock().unwrap().insert(path.to_path_buf(), cached);
+        Ok(content)
+    }
+
+    /// Modification time of a resolved file, for `Last-Modified`.
+    pub async fn modified(&self, path: &Path) -> Option<SystemTime> {
+        tokio::fs::metadata(path).await.and_then(|meta| meta.modified()).ok()
+    }
+
+    /// Drops cached content for `path` and everything below it.
+    pub fn invalidate(&self, path: &Path) {
+        self.cache
+            .lock()
+            .unwrap()
+            .retain(|cached, _| !cached.starts_with(path));
+    }
+
+    /// Renders an EJS template below the root with `context` as its data.
+    pub fn render(&self, path: &Path, context: &Value) -> Result<String, EjsError> {
+        let root = match self.single {
+            true => self.root.parent().unwrap_or(&self.root),
+            false => &self.root,
+        };
+        ejs::render_file(path, root, context, &|p| self.read(p))
+    }
+}
+
+/// Narrows the registered content types to those fitting `path`.
+///
+/// Types listing the extension in `extensions()` win. Files with an unknown extension
+/// are identified by the magic bytes they start with, then fall back to
+/// `application/octet-stream`, whether or not it is registered.
+pub async fn types_for_path(
+    types: &[Arc<dyn VlmContentType>],
+    path: &Path,
+) -> Vec<Arc<dyn VlmContentType>> {
+    if let Some(ext) = path.extension().and_then(|e| e.to_str()) {
+        let ext = ext.to_ascii_lowercase();
+        let claimed: Vec<_> = types
+            .iter()
+            .filter(|ct| ct.extensions().contains(&ext.as_str()))
+            .cloned()
+            .collect();
+        if !claimed.is_empty() {
+            return claimed;
+        }
+    }
+
+    let by_magic = detect(types, &read_head(path).await);
+    if !by_magic.is_empty() {
+        return by_magic;
+    }
+    let binary: Vec<_> = types
+        .iter()
+        .filter(|ct| ct.content_type_header() == b"application/octet-stream")
+        .cloned()
+        .collect();
+    if binary.is_empty() {
+        vec![Arc::new(OctetStream::default())]
+    } else {
+        binary
+    }
+}
+
+/// Whether `path` is a regular file, following symlinks.
+async fn is_file(path: &Path) -> bool {
+    tokio::fs::metadata(path).await.is_ok_and(|meta| meta.is_file())
+}
+
+/// The first bytes of a file, enough for every magic signature; empty when unreadable.
+async fn read_head(path: &Path) -> Vec<u8> {
+    let mut head = Vec::with_capacity(MAGIC_LEN);
+    if let Ok(file) = tokio::fs::File::open(path).await {
+        let _ = file.take(MAGIC_LEN as u64).read_to_end(&mut head).await;
+    }
+    head
+}
+
+/// The segments of `request_path` as [`VlmFiles::resolve`] reads them: percent-decoded,
+/// without empty and `.` segments. `None` when a segment is `..`, is not valid
+/// UTF-8 or decodes to a separator.
+fn decoded_segments(request_path: &str) -> Option<Vec<String>> {
+    let mut segments = Vec::new();
+    for segment in request_path.split('/').filter(|s| !s.is_empty()) {
+        let segment = percent_decode(segment)?;
+        if segment.contains(['/', '\\', '\0']) {
+            return None;
+        }
+        match Path::new(&segment).components().next() {
+            Some(Component::Normal(_)) => segments.push(segment),
+            Some(Component::CurDir) => {}
+            _ => return None,
+        }
+    }
+    Some(segments)
+}
+
+/// `request_path` in the one form that names what [`VlmFiles::resolve`] serves for it,
+/// so `/%61dmin//./x` and `/admin/x` compare equal. Segments are decoded, then `%`, `?`
+/// and `#` encoded again; a trailing slash is kept. `None` for paths `resolve` rejects.
+pub(crate) fn normalize_path(request_path: &str) -> Option<String> {
+    let mut normalized = String::from("/");
+    for segment in decoded_segments(request_path)? {
+        if normalized.len() > 1 {
+            normalized.push('/');
+        }
+        for c in segment.chars() {
+            match c {
+                '%' => normalized.push_str("%25"),
+                '?' => normalized.push_str("%3F"),
+                '#' => normalized.push_str("%23"),
+                c => normalized.push(c),
+            }
+        }
+    }
+    if request_path.ends_with('/') && normalized.len() > 1 {
+        normalized.push('/');
+    }
+    Some(normalized)
+}
+
+/// The directory named by `segments` as a `Location` value: always a single leading
+/// slash, a trailing one, and every byte outside of the path characters of RFC 3986
+/// percent-encoded, so the redirect can neither leave the host nor break the header.
+fn location(segments: &[String]) -> String {
+    let mut location = String::from("/");
+    for segment in segments {
+        for &byte in segment.as_bytes() {
+            match byte {
+                b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' => location.push(byte as char),
+                b'-' | b'.' | b'_' | b'~' | b'!' | b'$' | b'&' | b'\'' | b'(' | b')' => location.push(byte as char),
+                b'*' | b'+' | b',' | b';' | b'=' | b':' | b'@' => location.push(byte as char),
+                _ => location.push_str(&format!("%{:02X}", byte)),
+            }
+        }
+        location.push('/');
+    }
+    location
+}
+
+pub(crate) fn percent_decode(segment: &str) -> Option<String> {
+    let bytes = segment.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'%' {
+            let hex = segment.get(i + 1..i + 3)?;
+            decoded.push(u8::from_str_radix(hex, 16).ok()?);
+            i += 3;
+        } else {
+            decoded.push(bytes[i]);
+            i += 1;
+        }
+    }
+    String::from_utf8(decoded).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+
+    use super::*;
+    use crate::web::{HTML, PNG};
+
+    fn content(name: &str) -> VlmFiles {
+        let root = env::temp_dir().join(format!("vlm-files-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&root);
+        fs::create_dir_all(root.join("docs/a b\nc")).unwrap();
+        fs::write(root.join("docs/index.html"), "docs").unwrap();
+        VlmFiles::new(root).unwrap()
+    }
+
+    async fn redirect(files: &VlmFiles, path: &str) -> Option<String> {
+        match files.resolve(path).await? {
+            VlmResolved::Redirect(location) => Some(location),
+            VlmResolved::File(_) => None,
+        }
+    }
+
+    #[tokio::test]
+    async fn directory_redirects_stay_on_the_host() {
+        let files = content("redirect");
+        assert_eq!(redirect(&files, "/docs").await.as_deref(), Some("/docs/"));
+        assert_eq!(redirect(&files, "//docs").await.as_deref(), Some("/docs/"));
+        assert_eq!(redirect(&files, "/.//docs").await.as_deref(), Some("/docs/"));
+        assert_eq!(redirect(&files, "/%2Fdocs").await, None);
+        let escaped = redirect(&files, "/docs/a%20b%0Ac").await;
+        assert_eq!(escaped.as_deref(), Some("/docs/a%20b%0Ac/"));
+        assert!(matches!(files.resolve("/docs/").await, Some(VlmResolved::File(_))));
+        fs::remove_dir_all(&files.root).unwrap();
+    }
+
+    fn headers(types: &[Arc<dyn VlmContentType>]) -> Vec<String> {
+        types.iter().map(|ct| ct.header_value()).collect()
+    }
+
+    #[tokio::test]
+    async fn unknown_files_are_sniffed_then_sent_as_octet_stream() {
+        let files = content("types");
+        let (png, blob) = (files.root.join("image"), files.root.join("blob.unknown"));
+        fs::write(&png, b"\x89PNG\r\n\x1a\n....").unwrap();
+        fs::write(&blob, [0u8, 159, 146, 150]).unwrap();
+        let text: Vec<Arc<dyn VlmContentType>> = vec![Arc::new(HTML::default()), Arc::new(PNG::default())];
+        assert_eq!(headers(&types_for_path(&text, &png).await), ["image/png"]);
+        assert_eq!(headers(&types_for_path(&text, &blob).await), ["application/octet-stream"]);
+        let html = files.root.join("docs/index.html");
+        assert_eq!(headers(&types_for_path(&text, &html).await), ["text/html; charset=utf-8"]);
+        fs::remove_dir_all(&files.root).unwrap();
+    }
+}
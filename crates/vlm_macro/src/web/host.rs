@@ -0,0 +1,198 @@
+use std::{
+    fmt, io,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, ToSocketAddrs},
+    path::PathBuf,
+};
+
+use super::VlmPort;
+
+/// Where a server listens.
+///
+/// Binding `V6(Ipv6Addr::UNSPECIFIED)` (`::`) also accepts IPv4 clients on
+/// systems where IPv6 sockets are dual-stack by default, such as Linux.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum VlmHost {
+    V4([u8; 4]),
+    V6(Ipv6Addr),
+    /// A hostname such as `localhost`, resolved when the server starts.
+    Name(String),
+    /// A Unix domain socket path. The port is ignored.
+    Unix(PathBuf),
+}
+
+impl VlmHost {
+    /// Resolves the TCP addresses to try, in order, for `port`.
+    pub fn socket_addrs(&self, port: VlmPort) -> io::Result<Vec<SocketAddr>> {
+        match self {
+            VlmHost::V4(ip) => Ok(vec![SocketAddr::from((Ipv4Addr::from(*ip), port))]),
+            VlmHost::V6(ip) => Ok(vec![SocketAddr::from((*ip, port))]),
+            VlmHost::Name(name) => {
+                let addrs: Vec<_> = (name.as_str(), port).to_socket_addrs()?.collect();
+                match addrs.is_empty() {
+                    true => Err(io::Error::new(
+                        io::ErrorKind::NotFound,
+                        format!("Host {} did not resolve to any address", name),
+                    )),
+                    false => Ok(addrs),
+                }
+            }
+            VlmHost::Unix(path) => Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("{} is a Unix socket, not a TCP address", path.display()),
+            )),
+        }
+    }
+}
+
+/// How a `V` mode picks its port from the one passed to `VLM::serve`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum VlmPortPolicy {
+    /// Always use this port.
+    Fixed(VlmPort),
+    /// Shift the requested port, e.g. `Offset(1000)` turns 8080 into 9080.
+    Offset(i32),
+    /// Keep the requested port.
+    Inherit,
+}
+
+impl Default for VlmPortPolicy {
+    fn default() -> Self {
+        VlmPortPolicy::Fixed(9090)
+    }
+}
+
+impl VlmPortPolicy {
+    /// The port to bind for a requested `port`, or an error when an offset leaves the valid range.
+    pub fn apply(&self, port: VlmPort) -> Result<VlmPort, String> {
+        match *self {
+            VlmPortPolicy::Fixed(fixed) => Ok(fixed),
+            VlmPortPolicy::Inherit => Ok(port),
+            VlmPortPolicy::Offset(offset) => VlmPort::try_from(i64::from(port) + i64::from(offset))
+                .map_err(|_| format!("Port {} with offset {} is out of range", port, offset)),
+        }
+    }
+}
+
+impl From<[u8; 4]> for VlmHost {
+    fn from(ip: [u8; 4]) -> Self {
+        VlmHost::V4(ip)
+    }
+}
+
+impl From<Ipv4Addr> for VlmHost {
+    fn from(ip: Ipv4Addr) -> Self {
+        VlmHost::V4(ip.octets())
+    }
+}
+
+impl From<Ipv6Addr> for VlmHost {
+    fn from(ip: Ipv6Addr) -> Self {
+        VlmHost::V6(ip)
+    }
+}
+
+impl From<IpAddr> for VlmHost {
+    fn from(ip: IpAddr) -> Self {
+        match ip {
+            IpAddr::V4(ip) => ip.into(),
+            IpAddr::V6(ip) => ip.into(),
+        }
+    }
+}
+
+/// Parses `127.0.0.1`, `::1`, `[::1]`, `unix:/run/vlm.sock` or a hostname.
+///
+/// A port written after an address, as in `[::1]:8080`, is dropped: the port is
+/// always passed along with the host.
+impl From<&str> for VlmHost {
+    fn from(host: &str) -> Self {
+        if let Some(path) = host.strip_prefix("unix:") {
+            return VlmHost::Unix(PathBuf::from(path));
+        }
+        if let Ok(addr) = host.parse::<SocketAddr>() {
+            return addr.ip().into();
+        }
+        let bare = host.strip_prefix('[').and_then(|host| host.strip_suffix(']')).unwrap_or(host);
+        match bare.parse::<IpAddr>() {
+            Ok(ip) => ip.into(),
+            Err(_) => VlmHost::Name(host.to_string()),
+        }
+    }
+}
+
+impl fmt::Display for VlmHost {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            VlmHost::V4(ip) => Ipv4Addr::from(*ip).fmt(f),
+            VlmHost::V6(ip) => write!(f, "[{}]", ip),
+            VlmHost::Name(name) => f.write_str(name),
+            VlmHost::Unix(path) => write!(f, "unix:{}", path.display()),
+        }
+    }
+}
+
+/// The address a server actually bound to.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum VlmBoundAddr {
+    Tcp(SocketAddr),
+    Unix(PathBuf),
+}
+
+impl VlmBoundAddr {
+    /// The bound TCP port, including one chosen by the OS for port 0.
+    pub fn port(&self) -> Option<VlmPort> {
+        self.socket_addr().map(|addr| addr.port())
+    }
+
+    pub fn socket_addr(&self) -> Option<SocketAddr> {
+        match self {
+            VlmBoundAddr::Tcp(addr) => Some(*addr),
+            VlmBoundAddr::Unix(_) => None,
+        }
+    }
+}
+
+impl fmt::Display for VlmBoundAddr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            VlmBoundAddr::Tcp(addr) => addr.fmt(f),
+            VlmBoundAddr::Unix(path) => write!(f, "unix:{}", path.display()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn hosts_parse_into_their_variant() {
+        assert_eq!(VlmHost::from("127.0.0.1"), VlmHost::V4([127, 0, 0, 1]));
+        assert_eq!(VlmHost::from("127.0.0.1:8080"), VlmHost::V4([127, 0, 0, 1]));
+        for host in ["::1", "[::1]", "[::1]:8080"] {
+            assert_eq!(VlmHost::from(host), VlmHost::V6(Ipv6Addr::LOCALHOST), "{}", host);
+        }
+        assert_eq!(VlmHost::from("localhost"), VlmHost::Name("localhost".to_string()));
+        assert_eq!(VlmHost::from("[::1"), VlmHost::Name("[::1".to_string()));
+        assert_eq!(VlmHost::from("unix:/run/vlm.sock"), VlmHost::Unix(PathBuf::from("/run/vlm.sock")));
+    }
+
+    #[test]
+    fn hosts_display_as_they_parse() {
+        for host in ["127.0.0.1", "[::1]", "localhost", "unix:/run/vlm.sock"] {
+            assert_eq!(VlmHost::from(host).to_string(), host);
+        }
+    }
+
+    #[test]
+    fn socket_addrs_per_variant() {
+        let v6 = VlmHost::from("[::1]:8080").socket_addrs(80).unwrap();
+        assert_eq!(v6, [SocketAddr::from((Ipv6Addr::LOCALHOST, 80))]);
+        let v4 = VlmHost::from("10.0.0.1").socket_addrs(80).unwrap();
+        assert_eq!(v4, [SocketAddr::from(([10, 0, 0, 1], 80))]);
+        let named = VlmHost::from("localhost").socket_addrs(80).unwrap();
+        assert!(named.iter().all(|addr| addr.ip().is_loopback() && addr.port() == 80), "{:?}", named);
+        let unix = VlmHost::from("unix:/run/vlm.sock").socket_addrs(80).unwrap_err();
+        assert_eq!(unix.kind(), io::ErrorKind::InvalidInput);
+    }
+}
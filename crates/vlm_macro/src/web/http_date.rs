@@ -0,0 +1,130 @@
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+pub(crate) const MONTHS: [&str; 12] = ["Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec"];
+const WEEKDAYS: [&str; 7] = ["Thu", "Fri", "Sat", "Sun", "Mon", "Tue", "Wed"];
+
+/// The last year an IMF-fixdate, with its four-digit year, can name.
+const MAX_YEAR: u64 = 9999;
+
+/// A UTC calendar date and time, broken down from seconds since the epoch.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct Civil {
+    pub year: u64,
+    pub month: u64,
+    pub day: u64,
+    pub hour: u64,
+    pub minute: u64,
+    pub second: u64,
+    /// Days since 1970-01-01, a Thursday.
+    pub days: u64,
+}
+
+impl Civil {
+    /// Civil-from-days, valid for any date after 1970.
+    pub fn from_time(time: SystemTime) -> Self {
+        let secs = time.duration_since(UNIX_EPOCH).unwrap_or_default().as_secs();
+        let (days, rem) = (secs / 86_400, secs % 86_400);
+        let z = days + 719_468;
+        let era = z / 146_097;
+        let doe = z - era * 146_097;
+        let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+        let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+        let mp = (5 * doy + 2) / 153;
+        let day = doy - (153 * mp + 2) / 5 + 1;
+        let month = if mp < 10 { mp + 3 } else { mp - 9 };
+        Self {
+            year: yoe + era * 400 + u64::from(month <= 2),
+            month,
+            day,
+            hour: rem / 3600,
+            minute: rem % 3600 / 60,
+            second: rem % 60,
+            days,
+        }
+    }
+
+    /// Days-from-civil, the inverse of [`Civil::from_time`]. Returns `None` before 1970.
+    fn days_from_civil(year: u64, month: u64, day: u64) -> Option<u64> {
+        let year = if month <= 2 { year.checked_sub(1)? } else { year };
+        let era = year / 400;
+        let yoe = year - era * 400;
+        let mp = if month > 2 { month - 3 } else { month + 9 };
+        let doy = (153 * mp + 2) / 5 + day - 1;
+        let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+        era.checked_mul(146_097)?.checked_add(doe)?.checked_sub(719_468)
+    }
+}
+
+/// Formats `time` as an HTTP date, e.g. `Sun, 18 Oct 2026 09:12:03 GMT`.
+pub fn format(time: SystemTime) -> String {
+    let c = Civil::from_time(time);
+    format!(
+        "{}, {:02} {} {} {:02}:{:02}:{:02} GMT",
+        WEEKDAYS[(c.days % 7) as usize],
+        c.day,
+        MONTHS[(c.month - 1) as usize],
+        c.year,
+        c.hour,
+        c.minute,
+        c.second
+    )
+}
+
+/// Parses an HTTP date in the preferred IMF-fixdate form. Obsolete forms yield `None`.
+pub fn parse(date: &str) -> Option<SystemTime> {
+    let (_, rest) = date.trim().split_once(", ")?;
+    let mut parts = rest.split(' ');
+    let day: u64 = parts.next()?.parse().ok()?;
+    let month = parts.next()?;
+    let month = MONTHS.iter().position(|m| *m == month)? as u64 + 1;
+    let year: u64 = parts.next()?.parse().ok()?;
+    let mut clock = parts.next()?.split(':').map(|part| part.parse::<u64>().ok());
+    let (hour, minute, second) = (clock.next()??, clock.next()??, clock.next()??);
+    if parts.next()? != "GMT" || !(1..=31).contains(&day) || hour > 23 || minute > 59 || second > 60 {
+        return None;
+    }
+    if year > MAX_YEAR {
+        return None;
+    }
+    let days = Civil::days_from_civil(year, month, day)?;
+    let secs = days
+        .checked_mul(86_400)?
+        .checked_add(hour * 3600 + minute * 60 + second)?;
+    UNIX_EPOCH.checked_add(Duration::from_secs(secs))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_round_trips_format() {
+        let time = UNIX_EPOCH + Duration::from_secs(784_111_777);
+        assert_eq!(format(time), "Sun, 06 Nov 1994 08:49:37 GMT");
+        assert_eq!(parse("Sun, 06 Nov 1994 08:49:37 GMT"), Some(time));
+        assert_eq!(parse("Thu, 01 Jan 1970 00:00:00 GMT"), Some(UNIX_EPOCH));
+        let last = parse("Fri, 31 Dec 9999 23:59:59 GMT").unwrap();
+        assert_eq!(format(last), "Fri, 31 Dec 9999 23:59:59 GMT");
+    }
+
+    #[test]
+    fn parse_rejects_out_of_range_dates() {
+        for date in [
+            "Sun, 06 Nov 300000000000 08:49:37 GMT",
+            "Sun, 06 Nov 18446744073709551615 08:49:37 GMT",
+            "Sat, 01 Jan 10000 00:00:00 GMT",
+            "Wed, 31 Dec 1969 23:59:59 GMT",
+            "Sun, 06 Nov 1994 24:00:00 GMT",
+            "Sun, 00 Nov 1994 08:49:37 GMT",
+        ] {
+            assert_eq!(parse(date), None, "{}", date);
+        }
+    }
+
+    #[test]
+    fn parse_rejects_obsolete_forms() {
+        assert_eq!(parse("Sunday, 06-Nov-94 08:49:37 GMT"), None);
+        assert_eq!(parse("Sun Nov  6 08:49:37 1994"), None);
+        assert_eq!(parse("Sun, 06 Nov 1994 08:49:37 UTC"), None);
+    }
+}
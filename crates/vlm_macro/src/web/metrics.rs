@@ -0,0 +1,163 @@
+use std::{
+    collections::BTreeMap,
+    fmt::Write,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use warp::{
+    filters::BoxedFilter,
+    http::{Method, Response, StatusCode},
+    hyper::Body,
+    reply, Filter,
+};
+
+/// Path of the Prometheus scrape endpoint.
+pub const METRICS_PATH: &str = "metrics";
+
+/// Upper bounds, in seconds, of the request latency histogram buckets.
+pub const LATENCY_BUCKETS: &[f64] = &[0.001, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0];
+
+#[derive(Debug, Default)]
+struct Histogram {
+    buckets: Vec<u64>,
+    sum: f64,
+    count: u64,
+}
+
+#[derive(Debug, Default)]
+struct Counters {
+    requests: BTreeMap<(String, u16), u64>,
+    bytes: BTreeMap<String, u64>,
+    latency: BTreeMap<String, Histogram>,
+}
+
+/// Request counters and latency histograms exported in the Prometheus text format.
+#[derive(Debug, Default)]
+pub struct VlmMetrics {
+    counters: Mutex<Counters>,
+}
+
+impl VlmMetrics {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Records one finished request. `bytes` is `None` for streamed bodies of unknown length.
+    ///
+    /// Extension methods are counted as `OTHER`, so clients cannot add label values at will.
+    pub fn record(&self, method: &Method, status: StatusCode, bytes: Option<u64>, latency: Duration) {
+        let method = method_label(method).to_string();
+        let seconds = latency.as_secs_f64();
+        let mut counters = self.counters.lock().unwrap();
+        *counters
+            .requests
+            .entry((method.clone(), status.as_u16()))
+            .or_default() += 1;
+        *counters.bytes.entry(method.clone()).or_default() += bytes.unwrap_or(0);
+        let histogram = counters.latency.entry(method).or_default();
+        histogram.buckets.resize(LATENCY_BUCKETS.len(), 0);
+        for (bucket, bound) in histogram.buckets.iter_mut().zip(LATENCY_BUCKETS) {
+            if seconds <= *bound {
+                *bucket += 1;
+            }
+        }
+        histogram.sum += seconds;
+        histogram.count += 1;
+    }
+
+    /// Renders every metric in the Prometheus text exposition format.
+    pub fn render(&self) -> String {
+        let counters = self.counters.lock().unwrap();
+        let mut out = String::new();
+
+        out.push_str("# HELP vlm_http_requests_total HTTP requests served, by method and status.\n");
+        out.push_str("# TYPE vlm_http_requests_total counter\n");
+        for ((method, status), count) in &counters.requests {
+            let _ = writeln!(out, "vlm_http_requests_total{{method=\"{}\",status=\"{}\"}} {}", method, status, count);
+        }
+
+        out.push_str("# HELP vlm_http_response_bytes_total Response body bytes sent with a known length, by method.\n");
+        out.push_str("# TYPE vlm_http_response_bytes_total counter\n");
+        for (method, bytes) in &counters.bytes {
+            let _ = writeln!(out, "vlm_http_response_bytes_total{{method=\"{}\"}} {}", method, bytes);
+        }
+
+        out.push_str("# HELP vlm_http_request_duration_seconds Time to produce a response, by method.\n");
+        out.push_str("# TYPE vlm_http_request_duration_seconds histogram\n");
+        for (method, histogram) in &counters.latency {
+            for (count, bound) in histogram.buckets.iter().zip(LATENCY_BUCKETS) {
+                let _ = writeln!(
+                    out,
+                    "vlm_http_request_duration_seconds_bucket{{method=\"{}\",le=\"{}\"}} {}",
+                    method, bound, count
+                );
+            }
+            let _ = writeln!(
+                out,
+                "vlm_http_request_duration_seconds_bucket{{method=\"{}\",le=\"+Inf\"}} {}",
+                method, histogram.count
+            );
+            let _ = writeln!(out, "vlm_http_request_duration_seconds_sum{{method=\"{}\"}} {}", method, histogram.sum);
+            let _ = writeln!(out, "vlm_http_request_duration_seconds_count{{method=\"{}\"}} {}", method, histogram.count);
+        }
+        out
+    }
+}
+
+/// The `method` label of a request: its method when standard, `OTHER` otherwise.
+fn method_label(method: &Method) -> &'static str {
+    match *method {
+        Method::GET => "GET",
+        Method::HEAD => "HEAD",
+        Method::POST => "POST",
+        Method::PUT => "PUT",
+        Method::DELETE => "DELETE",
+        Method::CONNECT => "CONNECT",
+        Method::OPTIONS => "OPTIONS",
+        Method::TRACE => "TRACE",
+        Method::PATCH => "PATCH",
+        _ => "OTHER",
+    }
+}
+
+/// Serves `GET /metrics` from `metrics`, or rejects everything when metrics are disabled.
+pub fn route(metrics: Option<Arc<VlmMetrics>>) -> BoxedFilter<(reply::Response,)> {
+    let metrics = match metrics {
+        Some(metrics) => metrics,
+        None => {
+            return warp::any()
+                .and_then(|| async { Err::<reply::Response, _>(warp::reject::not_found()) })
+                .boxed();
+        }
+    };
+    warp::get()
+        .and(warp::path(METRICS_PATH))
+        .and(warp::path::end())
+        .map(move || {
+            Response::builder()
+                .header("Content-Type", "text/plain; version=0.0.4; charset=utf-8")
+                .body(Body::from(metrics.render()))
+                .unwrap()
+        })
+        .boxed()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn extension_methods_share_one_label() {
+        let metrics = VlmMetrics::new();
+        for method in ["GET", "FOO", "BAR", "Get"] {
+            let method = Method::from_bytes(method.as_bytes()).unwrap();
+            metrics.record(&method, StatusCode::OK, Some(1), Duration::from_millis(1));
+        }
+        let rendered = metrics.render();
+        assert!(rendered.contains("vlm_http_requests_total{method=\"GET\",status=\"200\"} 1\n"), "{}", rendered);
+        assert!(rendered.contains("vlm_http_requests_total{method=\"OTHER\",status=\"200\"} 3\n"), "{}", rendered);
+        assert!(rendered.contains("vlm_http_response_bytes_total{method=\"OTHER\"} 3\n"), "{}", rendered);
+        assert!(!rendered.contains("FOO") && !rendered.contains("Get"), "{}", rendered);
+    }
+}
@@ -0,0 +1,203 @@
+use std::sync::Arc;
+
+use super::VlmContentType;
+
+/// One entry of an `Accept` header, e.g. `text/*;q=0.8`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct MediaRange {
+    pub kind: String,
+    pub subtype: String,
+    pub params: Vec<(String, String)>,
+    pub q: f32,
+}
+
+impl MediaRange {
+    /// Parses a single media range, returning `None` when it is malformed.
+    pub fn parse(range: &str) -> Option<Self> {
+        let mut parts = range.split(';');
+        let (kind, subtype) = parts.next()?.trim().split_once('/')?;
+        let (kind, subtype) = (kind.trim().to_ascii_lowercase(), subtype.trim().to_ascii_lowercase());
+        if kind.is_empty() || subtype.is_empty() || (kind == "*" && subtype != "*") {
+            return None;
+        }
+
+        let mut q = 1.0;
+        let mut params = Vec::new();
+        for param in parts {
+            let (name, value) = param.split_once('=')?;
+            let name = name.trim().to_ascii_lowercase();
+            let value = value.trim().trim_matches('"');
+            if name == "q" {
+                q = value.parse::<f32>().ok()?.clamp(0.0, 1.0);
+            } else {
+                params.push((name, value.to_string()));
+            }
+        }
+        Some(Self { kind, subtype, params, q })
+    }
+
+    /// How precisely this range matches `media_type`, or `None` when it does not match.
+    ///
+    /// Higher values are more specific: `*/*` < `type/*` < `type/subtype` < `type/subtype;params`.
+    pub fn specificity(&self, media_type: &str) -> Option<u8> {
+        let mut parts = media_type.split(';');
+        let (kind, subtype) = parts.next()?.trim().split_once('/')?;
+        let type_params: Vec<(String, String)> = parts
+            .filter_map(|p| p.split_once('='))
+            .map(|(n, v)| (n.trim().to_ascii_lowercase(), v.trim().trim_matches('"').to_string()))
+            .collect();
+
+        if self.kind == "*" {
+            return Some(0);
+        }
+        if !self.kind.eq_ignore_ascii_case(kind) {
+            return None;
+        }
+        if self.subtype == "*" {
+            return Some(1);
+        }
+        if !self.subtype.eq_ignore_ascii_case(subtype) {
+            return None;
+        }
+        if self.params.is_empty() {
+            return Some(2);
+        }
+        // Charset names are case-insensitive, other parameter values are compared as sent.
+        let matches = |(name, value): &(String, String)| {
+            type_params.iter().any(|(type_name, type_value)| {
+                type_name == name
+                    && match name.as_str() {
+                        "charset" => type_value.eq_ignore_ascii_case(value),
+                        _ => type_value == value,
+                    }
+            })
+        };
+        self.params.iter().all(matches).then_some(3)
+    }
+}
+
+/// Parses an `Accept` header into its media ranges, skipping malformed entries.
+pub fn parse_accept(header: &str) -> Vec<MediaRange> {
+    header
+        .split(',')
+        .filter(|range| !range.trim().is_empty())
+        .filter_map(MediaRange::parse)
+        .collect()
+}
+
+/// The quality the client assigns to `media_type`, taken from its most specific matching range.
+pub fn quality(ranges: &[MediaRange], media_type: &str) -> f32 {
+    ranges
+        .iter()
+        .filter_map(|range| range.specificity(media_type).map(|s| (s, range.q)))
+        .max_by_key(|(specificity, _)| *specificity)
+        .map_or(0.0, |(_, q)| q)
+}
+
+/// Picks the registered type the client prefers most.
+///
+/// Ties go to the type registered first. A missing or empty `Accept` header accepts
+/// anything. Returns `None` when every type has quality zero, i.e. `406 Not Acceptable`.
+pub fn negotiate(
+    accept: Option<&str>,
+    types: &[Arc<dyn VlmContentType>],
+) -> Option<Arc<dyn VlmContentType>> {
+    let ranges = match accept.map(parse_accept) {
+        Some(ranges) if !ranges.is_empty() => ranges,
+        _ => return types.first().cloned(),
+    };
+
+    let mut best: Option<(f32, &Arc<dyn VlmContentType>)> = None;
+    for ct in types {
+        let q = quality(&ranges, &ct.header_value());
+        if q > 0.0 && best.is_none_or(|(best_q, _)| q > best_q) {
+            best = Some((q, ct));
+        }
+    }
+    best.map(|(_, ct)| Arc::clone(ct))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::web::{HTML, JSON, PNG};
+
+    fn types() -> Vec<Arc<dyn VlmContentType>> {
+        vec![
+            Arc::new(HTML::default()),
+            Arc::new(JSON::default()),
+            Arc::new(PNG::default()),
+        ]
+    }
+
+    fn pick(accept: Option<&str>) -> Option<String> {
+        negotiate(accept, &types()).map(|ct| String::from_utf8_lossy(ct.content_type_header()).into_owned())
+    }
+
+    #[test]
+    fn ranges_parse_with_q_and_params() {
+        let range = MediaRange::parse(" Text/HTML ; level=\"1\" ; q=0.5 ").unwrap();
+        assert_eq!(range.kind, "text");
+        assert_eq!(range.subtype, "html");
+        assert_eq!(range.params, vec![("level".to_string(), "1".to_string())]);
+        assert_eq!(range.q, 0.5);
+        assert_eq!(MediaRange::parse("text/*;q=7").unwrap().q, 1.0);
+        for malformed in ["text", "/html", "*/html", "text/html;q=high", "text/html;level"] {
+            assert_eq!(MediaRange::parse(malformed), None, "{}", malformed);
+        }
+        assert_eq!(parse_accept("text/html, nonsense, ,*/*;q=0.1").len(), 2);
+    }
+
+    #[test]
+    fn the_most_specific_range_sets_the_quality() {
+        let ranges = parse_accept("*/*;q=0.1, text/*;q=0.5, text/html;q=0.8, text/html;level=1;q=0");
+        assert_eq!(quality(&ranges, "text/html"), 0.8);
+        assert_eq!(quality(&ranges, "text/html;level=1"), 0.0);
+        assert_eq!(quality(&ranges, "text/plain"), 0.5);
+        assert_eq!(quality(&ranges, "image/png"), 0.1);
+        assert_eq!(quality(&parse_accept("text/html"), "image/png"), 0.0);
+    }
+
+    #[test]
+    fn negotiation_prefers_quality_then_registration_order() {
+        assert!(pick(None).unwrap().starts_with("text/html"));
+        assert!(pick(Some("")).unwrap().starts_with("text/html"));
+        assert_eq!(pick(Some("image/png, application/json;q=0.9")).unwrap(), "image/png");
+        assert!(
+            pick(Some("image/*;q=0.5, application/json"))
+                .unwrap()
+                .starts_with("application/json")
+        );
+        assert!(
+            pick(Some("image/png, application/json"))
+                .unwrap()
+                .starts_with("application/json")
+        );
+        assert!(
+            pick(Some("*/*, text/html;q=0"))
+                .unwrap()
+                .starts_with("application/json")
+        );
+    }
+
+    #[test]
+    fn parameters_match_the_full_header_value() {
+        assert_eq!(quality(&parse_accept("text/html;charset=UTF-8"), "text/html; charset=utf-8"), 1.0);
+        assert_eq!(quality(&parse_accept("text/html;charset=latin1"), "text/html; charset=utf-8"), 0.0);
+        assert_eq!(quality(&parse_accept("text/html;level=A"), "text/html;level=a"), 0.0);
+        assert!(pick(Some("text/html;charset=utf-8")).unwrap().starts_with("text/html"));
+        assert!(
+            pick(Some("text/html;charset=latin1, application/json;q=0.5"))
+                .unwrap()
+                .starts_with("application/json")
+        );
+        assert_eq!(pick(Some("image/png;charset=utf-8")), None);
+    }
+
+    #[test]
+    fn nothing_acceptable_is_none() {
+        assert_eq!(pick(Some("application/xml")), None);
+        assert_eq!(pick(Some("*/*;q=0")), None);
+        assert!(negotiate(Some("*/*"), &[]).is_none());
+    }
+}
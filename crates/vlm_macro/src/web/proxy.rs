@@ -0,0 +1,556 @@
+use std::{
+    env, fs,
+    io::{self, BufRead, BufReader, Read},
+    net::{SocketAddr, TcpListener, TcpStream},
+    path::{Path, PathBuf},
+    process::{Child, Command, Stdio},
+    sync::{
+        atomic::{AtomicBool, AtomicU16, AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+use futures_util::TryStreamExt;
+use tokio::sync::watch;
+use warp::{
+    filters::BoxedFilter,
+    http::{HeaderMap, HeaderValue, Method, Response, StatusCode},
+    hyper::{
+        body::Buf,
+        client::HttpConnector,
+        Body, Client, Request,
+    },
+    path::FullPath,
+    reply, Filter, Stream,
+};
+
+/// How long a freshly started backend gets to accept connections.
+pub const READY_TIMEOUT: Duration = Duration::from_secs(30);
+/// Interval between health checks of a running backend.
+pub const HEALTH_INTERVAL: Duration = Duration::from_secs(5);
+/// Failed health checks in a row after which the backend is restarted.
+pub const HEALTH_FAILURES: u32 = 3;
+const MAX_BACKOFF: Duration = Duration::from_secs(10);
+/// Ports tried in a row when the backend exits before listening, e.g. because
+/// another process took the port it was given.
+const LAUNCH_ATTEMPTS: u32 = 3;
+
+/// Tells apart the programs built by several backends of one process.
+static BUILDS: AtomicUsize = AtomicUsize::new(0);
+
+/// Headers that only apply to a single connection and are never forwarded.
+const HOP_BY_HOP: &[&str] = &[
+    "connection",
+    "keep-alive",
+    "proxy-authenticate",
+    "proxy-authorization",
+    "te",
+    "trailer",
+    "transfer-encoding",
+    "upgrade",
+];
+
+/// Where a backend is in its startup.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum VlmBackendState {
+    /// Building or waiting for the program to listen.
+    Starting,
+    Ready,
+    /// The build failed or the program never listened.
+    Failed(String),
+}
+
+/// A Go program run as a child process behind the `V::Go` mode.
+///
+/// The program listens on the loopback port given in its `PORT` environment
+/// variable. It is restarted when it exits or stops answering health checks,
+/// and its stdout and stderr are forwarded to the `vlm::go` log target.
+pub struct VlmGoBackend {
+    port: Arc<AtomicU16>,
+    program: PathBuf,
+    built: bool,
+    child: Arc<Mutex<Option<Child>>>,
+    stopping: Arc<AtomicBool>,
+    state: watch::Receiver<VlmBackendState>,
+    client: Client<HttpConnector>,
+}
+
+impl VlmGoBackend {
+    /// Starts the backend on its own thread and returns right away: the program
+    /// is built when `path` is a Go package directory or `.go` file, launched,
+    /// and then supervised. Requests wait until it accepts connections.
+    ///
+    /// Any other file is treated as an already built executable.
+    pub fn start(path: &Path) -> io::Result<Self> {
+        let path = path
+            .canonicalize()
+            .map_err(|e| io::Error::new(e.kind(), format!("Go backend {}: {}", path.display(), e)))?;
+        let built = path.is_dir() || path.extension().is_some_and(|ext| ext == "go");
+        let program = match built {
+            true => env::temp_dir().join(format!(
+                "vlm-go-{}-{}",
+                std::process::id(),
+                BUILDS.fetch_add(1, Ordering::Relaxed)
+            )),
+            false => path.clone(),
+        };
+        let (state_tx, state) = watch::channel(VlmBackendState::Starting);
+        let backend = Self {
+            port: Arc::new(AtomicU16::new(0)),
+            program,
+            built,
+            child: Arc::new(Mutex::new(None)),
+            stopping: Arc::new(AtomicBool::new(false)),
+            state,
+            client: Client::new(),
+        };
+
+        let supervisor = Supervisor {
+            port: Arc::clone(&backend.port),
+            program: backend.program.clone(),
+            child: Arc::clone(&backend.child),
+            stopping: Arc::clone(&backend.stopping),
+        };
+        thread::spawn(move || {
+            let started = match built {
+                true => build(&path, &supervisor.program),
+                false => Ok(()),
+            }
+            .and_then(|()| supervisor.launch());
+            match started {
+                Ok(addr) => {
+                    log::info!(target: "vlm::go", "{} is listening on {}", supervisor.program.display(), addr);
+                    let _ = state_tx.send(VlmBackendState::Ready);
+                    supervisor.run();
+                }
+                Err(e) => {
+                    log::error!(target: "vlm::go", "{}", e);
+                    if let Some(mut child) = supervisor.child.lock().unwrap().take() {
+                        let _ = child.kill();
+                        let _ = child.wait();
+                    }
+                    let _ = state_tx.send(VlmBackendState::Failed(e.to_string()));
+                }
+            }
+            // `stop` may have run while the program was still being built.
+            if built && supervisor.stopping.load(Ordering::SeqCst) {
+                let _ = fs::remove_file(&supervisor.program);
+            }
+        });
+        Ok(backend)
+    }
+
+    /// The loopback address the backend listens on. The port changes when a
+    /// restart has to pick another one, and is 0 until the first launch.
+    pub fn addr(&self) -> SocketAddr {
+        SocketAddr::from(([127, 0, 0, 1], self.port.load(Ordering::SeqCst)))
+    }
+
+    pub fn state(&self) -> VlmBackendState {
+        self.state.borrow().clone()
+    }
+
+    /// Waits until the backend is ready or has failed to start.
+    pub async fn ready(&self) -> Result<(), String> {
+        let mut state = self.state.clone();
+        match state.wait_for(|state| *state != VlmBackendState::Starting).await {
+            Ok(state) => match &*state {
+                VlmBackendState::Failed(e) => Err(e.clone()),
+                _ => Ok(()),
+            },
+            Err(_) => Err("Backend startup was abandoned".to_string()),
+        }
+    }
+
+    /// Kills the backend and keeps it from being restarted.
+    pub fn stop(&self) {
+        let mut child = self.child.lock().unwrap();
+        self.stopping.store(true, Ordering::SeqCst);
+        if let Some(mut child) = child.take() {
+            let _ = child.kill();
+            let _ = child.wait();
+        }
+        if self.built {
+            let _ = fs::remove_file(&self.program);
+        }
+    }
+
+    /// Forwards one request to the backend, answering `502 Bad Gateway` when it is unreachable.
+    pub async fn forward(
+        &self,
+        method: Method,
+        path: FullPath,
+        query: String,
+        headers: HeaderMap,
+        remote: Option<SocketAddr>,
+        body: Body,
+    ) -> reply::Response {
+        if let Err(e) = self.ready().await {
+            return bad_gateway(e);
+        }
+        let uri = match query.is_empty() {
+            true => format!("http://{}{}", self.addr(), path.as_str()),
+            false => format!("http://{}{}?{}", self.addr(), path.as_str(), query),
+        };
+        let mut request = Request::builder().method(method).uri(uri);
+        for (name, value) in backend_headers(headers, remote).iter() {
+            request = request.header(name, value);
+        }
+        let request = match request.body(body) {
+            Ok(request) => request,
+            Err(e) => return bad_gateway(e),
+        };
+
+        match self.client.request(request).await {
+            Ok(mut response) => {
+                remove_hop_by_hop(response.headers_mut());
+                response
+            }
+            Err(e) => bad_gateway(e),
+        }
+    }
+}
+
+impl Drop for VlmGoBackend {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
+
+/// Proxies every request reaching it to `backend`, or rejects everything when there is none.
+///
+/// Meant to sit after the static file routes so content on disk wins.
+pub fn route(backend: Option<Arc<VlmGoBackend>>) -> BoxedFilter<(reply::Response,)> {
+    let backend = match backend {
+        Some(backend) => backend,
+        None => {
+            return warp::any()
+                .and_then(|| async { Err::<reply::Response, _>(warp::reject::not_found()) })
+                .boxed();
+        }
+    };
+    warp::method()
+        .and(warp::path::full())
+        .and(warp::query::raw().or(warp::any().map(String::new)).unify())
+        .and(warp::header::headers_cloned())
+        .and(warp::addr::remote())
+        .and(warp::body::stream())
+        .then(
+            move |method, path, query, headers, remote, body| {
+                let backend = Arc::clone(&backend);
+                async move {
+                    backend.forward(method, path, query, headers, remote, into_body(body)).await
+                }
+            },
+        )
+        .boxed()
+}
+
+/// The headers sent to the backend for a request with `headers` from `remote`.
+///
+/// `X-Forwarded-For` and `X-Forwarded-Host` are set from what the proxy saw, replacing
+/// whatever the client sent, so the backend can trust them.
+fn backend_headers(mut headers: HeaderMap, remote: Option<SocketAddr>) -> HeaderMap {
+    remove_hop_by_hop(&mut headers);
+    headers.remove("x-forwarded-for");
+    headers.remove("x-forwarded-host");
+    if let Some(host) = headers.get("host").cloned() {
+        headers.insert("x-forwarded-host", host);
+    }
+    if let Some(value) = remote.and_then(|remote| HeaderValue::from_str(&remote.ip().to_string()).ok()) {
+        headers.insert("x-forwarded-for", value);
+    }
+    headers
+}
+
+/// Removes the [`HOP_BY_HOP`] headers and those the `Connection` header names.
+fn remove_hop_by_hop(headers: &mut HeaderMap) {
+    let listed: Vec<String> = headers
+        .get_all("connection")
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .map(|name| name.trim().to_ascii_lowercase())
+        .collect();
+    for name in HOP_BY_HOP.iter().copied().chain(listed.iter().map(String::as_str)) {
+        headers.remove(name);
+    }
+}
+
+fn into_body<B: Buf>(body: impl Stream<Item = Result<B, warp::Error>> + Send + 'static) -> Body {
+    Body::wrap_stream(body.map_ok(|mut buf| buf.copy_to_bytes(buf.remaining())))
+}
+
+fn bad_gateway(e: impl std::fmt::Display) -> reply::Response {
+    Response::builder()
+        .status(StatusCode::BAD_GATEWAY)
+        .header("Content-Type", "text/plain; charset=utf-8")
+        .body(Body::from(format!("Backend unavailable: {}", e)))
+        .unwrap()
+}
+
+/// Runs `go build` for a package directory or single file into `output`.
+fn build(path: &Path, output: &Path) -> io::Result<()> {
+    let (dir, target) = match path.is_dir() {
+        true => (path, Path::new(".")),
+        false => (
+            path.parent().unwrap_or(Path::new("/")),
+            Path::new(path.file_name().unwrap_or_default()),
+        ),
+    };
+    log::info!(target: "vlm::go", "Building {}", path.display());
+    let result = Command::new("go")
+        .arg("build")
+        .arg("-o")
+        .arg(output)
+        .arg(target)
+        .current_dir(dir)
+        .output()
+        .map_err(|e| io::Error::new(e.kind(), format!("Failed to run go build: {}", e)))?;
+    match result.status.success() {
+        true => Ok(()),
+        false => Err(io::Error::other(format!(
+            "go build {} failed: {}",
+            path.display(),
+            String::from_utf8_lossy(&result.stderr).trim()
+        ))),
+    }
+}
+
+fn launch(program: &Path, addr: SocketAddr) -> io::Result<Child> {
+    let mut child = Command::new(program)
+        .env("PORT", addr.port().to_string())
+        .env("VLM_BACKEND_ADDR", addr.to_string())
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|e| io::Error::new(e.kind(), format!("Failed to launch {}: {}", program.display(), e)))?;
+    if let Some(stdout) = child.stdout.take() {
+        forward_output(stdout, log::Level::Info);
+    }
+    if let Some(stderr) = child.stderr.take() {
+        forward_output(stderr, log::Level::Warn);
+    }
+    Ok(child)
+}
+
+fn forward_output(stream: impl Read + Send + 'static, level: log::Level) {
+    thread::spawn(move || {
+        for line in BufReader::new(stream).lines().map_while(Result::ok) {
+            log::log!(target: "vlm::go", level, "{}", line);
+        }
+    });
+}
+
+/// A loopback address with a port free right now. Another process may take it
+/// before the backend binds it, which [`Supervisor::launch`] retries around.
+fn free_addr() -> io::Result<SocketAddr> {
+    TcpListener::bind(("127.0.0.1", 0))?.local_addr()
+}
+
+fn healthy(addr: SocketAddr) -> bool {
+    TcpStream::connect_timeout(&addr, Duration::from_secs(1)).is_ok()
+}
+
+/// Waits until the backend accepts connections, failing early when it exits.
+fn wait_ready(child: &Mutex<Option<Child>>, addr: SocketAddr) -> io::Result<()> {
+    let started = Instant::now();
+    while started.elapsed() < READY_TIMEOUT {
+        if healthy(addr) {
+            return Ok(());
+        }
+        match child.lock().unwrap().as_mut() {
+            Some(child) => {
+                if let Some(status) = child.try_wait()? {
+                    return Err(io::Error::new(
+                        io::ErrorKind::BrokenPipe,
+                        format!("Backend exited before listening on {}: {}", addr, status),
+                    ));
+                }
+            }
+            None => return Err(io::Error::other("Backend was stopped")),
+        }
+        thread::sleep(Duration::from_millis(100));
+    }
+    Err(io::Error::new(
+        io::ErrorKind::TimedOut,
+        format!("Backend did not listen on {} within {:?}", addr, READY_TIMEOUT),
+    ))
+}
+
+/// Restarts the backend when it exits or fails its health checks, backing off between attempts.
+struct Supervisor {
+    port: Arc<AtomicU16>,
+    program: PathBuf,
+    child: Arc<Mutex<Option<Child>>>,
+    stopping: Arc<AtomicBool>,
+}
+
+impl Supervisor {
+    fn addr(&self) -> SocketAddr {
+        SocketAddr::from(([127, 0, 0, 1], self.port.load(Ordering::SeqCst)))
+    }
+
+    /// Launches the program on a free port and waits until it listens, moving to
+    /// another port when it exits first.
+    fn launch(&self) -> io::Result<SocketAddr> {
+        let mut attempts = 0;
+        loop {
+            let addr = free_addr()?;
+            {
+                let mut child = self.child.lock().unwrap();
+                if self.stopping.load(Ordering::SeqCst) {
+                    return Err(io::Error::other("Backend was stopped"));
+                }
+                if let Some(mut old) = child.take() {
+                    let _ = old.kill();
+                    let _ = old.wait();
+                }
+                self.port.store(addr.port(), Ordering::SeqCst);
+                *child = Some(launch(&self.program, addr)?);
+            }
+            attempts += 1;
+            match wait_ready(&self.child, addr) {
+                Ok(()) => return Ok(addr),
+                Err(e) if e.kind() == io::ErrorKind::BrokenPipe && attempts < LAUNCH_ATTEMPTS => {
+                    log::warn!(target: "vlm::go", "{}, trying another port", e);
+                }
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    fn run(&self) {
+        let mut backoff = Duration::from_millis(250);
+        let mut started = Instant::now();
+        let mut last_check = Instant::now();
+        let mut failures = 0;
+        loop {
+            thread::sleep(Duration::from_millis(100));
+            if self.stopping.load(Ordering::SeqCst) {
+                return;
+            }
+
+            let exited = {
+                let mut child = self.child.lock().unwrap();
+                match child.as_mut().map(Child::try_wait) {
+                    Some(Ok(Some(status))) => Some(status.to_string()),
+                    Some(Err(e)) => Some(e.to_string()),
+                    Some(Ok(None)) => None,
+                    None => Some("not running".to_string()),
+                }
+            };
+            let reason = match exited {
+                Some(status) => format!("exited ({})", status),
+                None if last_check.elapsed() < HEALTH_INTERVAL => continue,
+                None => {
+                    last_check = Instant::now();
+                    failures = match healthy(self.addr()) {
+                        true => 0,
+                        false => failures + 1,
+                    };
+                    if failures < HEALTH_FAILURES {
+                        continue;
+                    }
+                    format!("failed {} health checks", failures)
+                }
+            };
+
+            if started.elapsed() > MAX_BACKOFF {
+                backoff = Duration::from_millis(250);
+            }
+            log::error!(target: "vlm::go", "{} {}, restarting in {:?}", self.program.display(), reason, backoff);
+            thread::sleep(backoff);
+            backoff = (backoff * 2).min(MAX_BACKOFF);
+
+            if self.stopping.load(Ordering::SeqCst) {
+                return;
+            }
+            match self.launch() {
+                Ok(addr) => log::info!(target: "vlm::go", "{} is listening on {}", self.program.display(), addr),
+                Err(e) => log::error!(target: "vlm::go", "{}", e),
+            }
+            started = Instant::now();
+            last_check = Instant::now();
+            failures = 0;
+        }
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use std::os::unix::fs::PermissionsExt;
+
+    use super::*;
+
+    fn script(name: &str, body: &str) -> PathBuf {
+        let path = env::temp_dir().join(format!("vlm-proxy-{}-{}", name, std::process::id()));
+        fs::write(&path, format!("#!/bin/sh\n{}\n", body)).unwrap();
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o755)).unwrap();
+        path
+    }
+
+    #[tokio::test]
+    async fn start_returns_before_the_backend_listens() {
+        let program = script("slow", "sleep 30");
+        let started = Instant::now();
+        let backend = VlmGoBackend::start(&program).unwrap();
+        assert!(started.elapsed() < Duration::from_secs(1));
+        assert_eq!(backend.state(), VlmBackendState::Starting);
+        backend.stop();
+        fs::remove_file(program).unwrap();
+    }
+
+    #[tokio::test]
+    async fn backends_exiting_early_are_retried_then_fail() {
+        let runs = env::temp_dir().join(format!("vlm-proxy-runs-{}", std::process::id()));
+        let program = script("exits", &format!("echo run >> {}\nexit 1", runs.display()));
+        let backend = VlmGoBackend::start(&program).unwrap();
+        let error = backend.ready().await.unwrap_err();
+        assert!(error.contains("exited before listening"), "{}", error);
+        let runs_seen = fs::read_to_string(&runs).unwrap().lines().count();
+        assert_eq!(runs_seen, LAUNCH_ATTEMPTS as usize);
+        let path = warp::test::request().path("/").filter(&warp::path::full()).await;
+        let response = backend
+            .forward(
+                Method::GET,
+                path.unwrap(),
+                String::new(),
+                HeaderMap::new(),
+                None,
+                Body::empty(),
+            )
+            .await;
+        assert_eq!(response.status(), StatusCode::BAD_GATEWAY);
+        fs::remove_file(program).unwrap();
+        fs::remove_file(runs).unwrap();
+    }
+
+    #[test]
+    fn forwarded_headers_are_set_by_the_proxy() {
+        let mut headers = HeaderMap::new();
+        headers.insert("host", HeaderValue::from_static("example.com"));
+        headers.insert("x-forwarded-for", HeaderValue::from_static("10.0.0.1"));
+        headers.insert("x-forwarded-host", HeaderValue::from_static("admin.internal"));
+        headers.insert("connection", HeaderValue::from_static("keep-alive, X-Secret"));
+        headers.insert("x-secret", HeaderValue::from_static("1"));
+        headers.insert("keep-alive", HeaderValue::from_static("timeout=5"));
+        headers.insert("accept", HeaderValue::from_static("*/*"));
+
+        let remote = SocketAddr::from(([192, 0, 2, 7], 4000));
+        let sent = backend_headers(headers.clone(), Some(remote));
+        let value = |name: &str| sent.get_all(name).iter().map(|v| v.to_str().unwrap()).collect::<Vec<_>>();
+        assert_eq!(value("x-forwarded-for"), ["192.0.2.7"]);
+        assert_eq!(value("x-forwarded-host"), ["example.com"]);
+        assert_eq!(value("accept"), ["*/*"]);
+        for dropped in ["connection", "x-secret", "keep-alive"] {
+            assert!(value(dropped).is_empty(), "{}", dropped);
+        }
+
+        let sent = backend_headers(headers, None);
+        assert!(sent.get("x-forwarded-for").is_none());
+    }
+}
@@ -0,0 +1,286 @@
+use std::{
+    io::{self, SeekFrom},
+    path::PathBuf,
+    time::SystemTime,
+};
+
+use futures_util::{stream, Stream, TryStreamExt};
+use tokio::io::{AsyncReadExt, AsyncSeekExt};
+use warp::{
+    http::{response::Builder, HeaderMap, StatusCode},
+    hyper::{body::Bytes, Body},
+    reply,
+};
+
+use super::http_date;
+
+/// Size of the chunks a file is streamed in.
+pub const CHUNK_LEN: usize = 64 * 1024;
+
+/// Where a response body comes from.
+#[derive(Debug, Clone)]
+pub enum VlmSource {
+    /// Content produced in memory, such as a rendered template or a compressed variant.
+    Memory(Bytes),
+    /// A file streamed from disk in [`CHUNK_LEN`] chunks.
+    File { path: PathBuf, len: u64 },
+}
+
+impl VlmSource {
+    pub fn len(&self) -> u64 {
+        match self {
+            VlmSource::Memory(bytes) => bytes.len() as u64,
+            VlmSource::File { len, .. } => *len,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Loads the whole content, reading the file when it lives on disk.
+    pub async fn read(&self) -> io::Result<Bytes> {
+        match self {
+            VlmSource::Memory(bytes) => Ok(bytes.clone()),
+            VlmSource::File { path, .. } => tokio::fs::read(path).await.map(Bytes::from),
+        }
+    }
+
+    /// A body with `len` bytes starting at `start`.
+    ///
+    /// File bodies stop after `len` bytes even when the file grew since `len` was
+    /// taken, and fail when it shrank, so the connection is dropped instead of
+    /// ending short of the `Content-Length` already sent.
+    pub fn body(&self, start: u64, len: u64) -> Body {
+        match self {
+            VlmSource::Memory(bytes) => Body::from(bytes.slice(start as usize..(start + len) as usize)),
+            VlmSource::File { path, .. } => Body::wrap_stream(file_chunks(path.clone(), start, len)),
+        }
+    }
+}
+
+/// `len` bytes of the file at `path` from `start`, in [`CHUNK_LEN`] chunks.
+fn file_chunks(path: PathBuf, start: u64, len: u64) -> impl Stream<Item = io::Result<Bytes>> {
+    let open = async move {
+        let mut file = tokio::fs::File::open(path).await?;
+        file.seek(SeekFrom::Start(start)).await?;
+        Ok::<_, io::Error>(file.take(len))
+    };
+    stream::once(open)
+        .map_ok(|file| {
+            stream::try_unfold(file, |mut file| async move {
+                if file.limit() == 0 {
+                    return Ok(None);
+                }
+                let mut chunk = vec![0; CHUNK_LEN.min(file.limit() as usize)];
+                let read = file.read(&mut chunk).await?;
+                if read == 0 {
+                    let missing = file.limit();
+                    return Err(io::Error::new(
+                        io::ErrorKind::UnexpectedEof,
+                        format!("File shrank while being sent, {} bytes missing", missing),
+                    ));
+                }
+                chunk.truncate(read);
+                Ok(Some((Bytes::from(chunk), file)))
+            })
+        })
+        .try_flatten()
+}
+
+/// The part of a representation a request asks for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VlmRange {
+    /// No usable `Range` header: send everything with `200 OK`.
+    Full,
+    /// Inclusive byte offsets to send with `206 Partial Content`.
+    Partial(u64, u64),
+    /// No requested range overlaps the content: `416 Range Not Satisfiable`.
+    Unsatisfiable,
+}
+
+/// Parses a `Range` header against content of `len` bytes.
+///
+/// Only single `bytes` ranges are served partially; multiple ranges and other
+/// units fall back to the full content, which RFC 9110 allows.
+pub fn parse_range(range: Option<&str>, len: u64) -> VlmRange {
+    let spec = match range.and_then(|range| range.trim().strip_prefix("bytes=")) {
+        Some(spec) if !spec.contains(',') => spec.trim(),
+        _ => return VlmRange::Full,
+    };
+    let (first, last) = match spec.split_once('-') {
+        Some(bounds) => bounds,
+        None => return VlmRange::Full,
+    };
+    let parse = |value: &str| value.trim().parse::<u64>().ok();
+    match (first.trim().is_empty(), last.trim().is_empty()) {
+        // `bytes=-500`: the last 500 bytes.
+        (true, false) => match parse(last) {
+            Some(0) => VlmRange::Unsatisfiable,
+            Some(_) if len == 0 => VlmRange::Unsatisfiable,
+            Some(suffix) => VlmRange::Partial(len.saturating_sub(suffix), len - 1),
+            None => VlmRange::Full,
+        },
+        // `bytes=500-`: everything from offset 500.
+        (false, true) => match parse(first) {
+            Some(start) if start < len => VlmRange::Partial(start, len - 1),
+            Some(_) => VlmRange::Unsatisfiable,
+            None => VlmRange::Full,
+        },
+        (false, false) => match (parse(first), parse(last)) {
+            (Some(start), Some(end)) if start > end => VlmRange::Full,
+            (Some(start), Some(end)) if start < len => VlmRange::Partial(start, end.min(len - 1)),
+            (Some(_), Some(_)) => VlmRange::Unsatisfiable,
+            _ => VlmRange::Full,
+        },
+        (true, true) => VlmRange::Full,
+    }
+}
+
+/// Whether an `If-Range` precondition still matches the current representation.
+///
+/// Entity tags compare strongly, dates must equal `Last-Modified` exactly.
+pub fn if_range_matches(if_range: Option<&str>, etag: &str, modified: Option<SystemTime>) -> bool {
+    match if_range.map(str::trim) {
+        None => true,
+        Some(tag) if tag.starts_with('"') => tag == etag,
+        Some(tag) if tag.starts_with("W/") => false,
+        Some(date) => match (http_date::parse(date), modified) {
+            (Some(date), Some(modified)) => http_date::format(date) == http_date::format(modified),
+            _ => false,
+        },
+    }
+}
+
+/// Finishes `response` with `source`, honouring `Range` and `If-Range` from `headers`.
+///
+/// `etag` and `modified` must describe the representation in `source`.
+pub fn respond(
+    response: Builder,
+    source: VlmSource,
+    headers: &HeaderMap,
+    etag: &str,
+    modified: Option<SystemTime>,
+) -> reply::Response {
+    let header = |name: &str| headers.get(name).and_then(|value| value.to_str().ok());
+    let len = source.len();
+    let range = match if_range_matches(header("If-Range"), etag, modified) {
+        true => parse_range(header("Range"), len),
+        false => VlmRange::Full,
+    };
+    let response = response.header("Accept-Ranges", "bytes");
+    match range {
+        VlmRange::Full => response
+            .header("Content-Length", len)
+            .body(source.body(0, len))
+            .unwrap(),
+        VlmRange::Partial(start, end) => response
+            .status(StatusCode::PARTIAL_CONTENT)
+            .header("Content-Range", format!("bytes {}-{}/{}", start, end, len))
+            .header("Content-Length", end - start + 1)
+            .body(source.body(start, end - start + 1))
+            .unwrap(),
+        VlmRange::Unsatisfiable => response
+            .status(StatusCode::RANGE_NOT_SATISFIABLE)
+            .header("Content-Range", format!("bytes */{}", len))
+            .body(Body::empty())
+            .unwrap(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{env, fs, time::Duration};
+
+    use warp::hyper::body::to_bytes;
+
+    use super::*;
+    use VlmRange::*;
+
+    #[test]
+    fn single_byte_ranges() {
+        assert_eq!(parse_range(None, 100), Full);
+        assert_eq!(parse_range(Some("bytes=0-9"), 100), Partial(0, 9));
+        assert_eq!(parse_range(Some("bytes=90-"), 100), Partial(90, 99));
+        assert_eq!(parse_range(Some("bytes=-10"), 100), Partial(90, 99));
+        assert_eq!(parse_range(Some("bytes=-500"), 100), Partial(0, 99));
+        assert_eq!(parse_range(Some("bytes=50-500"), 100), Partial(50, 99));
+        assert_eq!(parse_range(Some("bytes=100-"), 100), Unsatisfiable);
+        assert_eq!(parse_range(Some("bytes=-0"), 100), Unsatisfiable);
+        assert_eq!(parse_range(Some("bytes=-1"), 0), Unsatisfiable);
+    }
+
+    #[test]
+    fn unusable_ranges_send_everything() {
+        assert_eq!(parse_range(Some("bytes=0-1,5-6"), 100), Full);
+        assert_eq!(parse_range(Some("items=0-1"), 100), Full);
+        assert_eq!(parse_range(Some("bytes=9-0"), 100), Full);
+        assert_eq!(parse_range(Some("bytes=a-b"), 100), Full);
+        assert_eq!(parse_range(Some("bytes=-"), 100), Full);
+    }
+
+    #[test]
+    fn if_range_compares_strong_tags_and_exact_dates() {
+        let modified = SystemTime::UNIX_EPOCH + Duration::from_secs(784_111_777);
+        let date = "Sun, 06 Nov 1994 08:49:37 GMT";
+        assert!(if_range_matches(None, "\"a\"", None));
+        assert!(if_range_matches(Some("\"a\""), "\"a\"", None));
+        assert!(!if_range_matches(Some("\"b\""), "\"a\"", None));
+        assert!(!if_range_matches(Some("W/\"a\""), "W/\"a\"", None));
+        assert!(if_range_matches(Some(date), "\"a\"", Some(modified)));
+        assert!(!if_range_matches(
+            Some(date),
+            "\"a\"",
+            Some(modified + Duration::from_secs(1))
+        ));
+        assert!(!if_range_matches(Some(date), "\"a\"", None));
+    }
+
+    fn file(name: &str, content: &[u8]) -> PathBuf {
+        let path = env::temp_dir().join(format!("vlm-range-{}-{}", name, std::process::id()));
+        fs::write(&path, content).unwrap();
+        path
+    }
+
+    fn headers(range: &str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert("Range", range.parse().unwrap());
+        headers
+    }
+
+    #[tokio::test]
+    async fn partial_responses_send_the_range() {
+        let path = file("partial", b"0123456789");
+        let source = VlmSource::File {
+            path: path.clone(),
+            len: 10,
+        };
+        let response = respond(Builder::new(), source, &headers("bytes=2-4"), "\"a\"", None);
+        assert_eq!(response.status(), StatusCode::PARTIAL_CONTENT);
+        assert_eq!(response.headers()["Content-Range"], "bytes 2-4/10");
+        assert_eq!(response.headers()["Content-Length"], "3");
+        assert_eq!(to_bytes(response.into_body()).await.unwrap(), "234");
+        let source = VlmSource::Memory(Bytes::from_static(b"0123456789"));
+        let response = respond(Builder::new(), source, &headers("bytes=20-"), "\"a\"", None);
+        assert_eq!(response.status(), StatusCode::RANGE_NOT_SATISFIABLE);
+        assert_eq!(response.headers()["Content-Range"], "bytes */10");
+        fs::remove_file(path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn file_bodies_keep_to_the_announced_length() {
+        let path = file("grown", b"0123456789");
+        let source = VlmSource::File {
+            path: path.clone(),
+            len: 4,
+        };
+        assert_eq!(to_bytes(source.body(0, 4)).await.unwrap(), "0123");
+        assert_eq!(to_bytes(source.body(8, 2)).await.unwrap(), "89");
+        let shrunk = VlmSource::File {
+            path: path.clone(),
+            len: 20,
+        };
+        assert!(to_bytes(shrunk.body(0, 20)).await.is_err());
+        fs::remove_file(path).unwrap();
+    }
+}
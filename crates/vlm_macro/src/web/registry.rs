@@ -0,0 +1,98 @@
+use std::{path::Path, sync::Arc};
+
+use super::{
+    files::types_for_path, JavaScript, OctetStream, PlainText, VlmContentType, CSS, EJS, HTML, JSON, NDJSON, PNG,
+    SVG, WASM, XML,
+};
+
+/// An ordered set of content types, mapping file extensions and magic bytes to types.
+///
+/// Order matters: when several types fit a file equally well, the one listed first
+/// wins, and types added with [`VlmContentTypes::register`] go in front of the
+/// ones already present so they override built-ins.
+#[derive(Clone, Default)]
+pub struct VlmContentTypes {
+    types: Vec<Arc<dyn VlmContentType>>,
+}
+
+impl VlmContentTypes {
+    /// An empty registry.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Every built-in type with its usual media type and, for text, UTF-8.
+    pub fn builtin() -> Self {
+        Self {
+            types: vec![
+                Arc::new(HTML::default()),
+                Arc::new(EJS::default()),
+                Arc::new(XML::default()),
+                Arc::new(JSON::default()),
+                Arc::new(NDJSON::default()),
+                Arc::new(PlainText::default()),
+                Arc::new(CSS::default()),
+                Arc::new(JavaScript::default()),
+                Arc::new(SVG::default()),
+                Arc::new(WASM::default()),
+                Arc::new(PNG::default()),
+                Arc::new(OctetStream::default()),
+            ],
+        }
+    }
+
+    /// Adds `content_type` ahead of the registered types.
+    pub fn register(&mut self, content_type: impl VlmContentType + 'static) -> &mut Self {
+        self.types.insert(0, Arc::new(content_type));
+        self
+    }
+
+    /// Builder form of [`VlmContentTypes::register`].
+    pub fn with(mut self, content_type: impl VlmContentType + 'static) -> Self {
+        self.register(content_type);
+        self
+    }
+
+    pub fn types(&self) -> &[Arc<dyn VlmContentType>] {
+        &self.types
+    }
+
+    /// The first type claiming `ext` (case-insensitive, without the dot).
+    pub fn for_extension(&self, ext: &str) -> Option<Arc<dyn VlmContentType>> {
+        let ext = ext.to_ascii_lowercase();
+        self.types
+            .iter()
+            .find(|ct| ct.extensions().contains(&ext.as_str()))
+            .cloned()
+    }
+
+    /// The first type whose magic bytes start `head`.
+    pub fn detect(&self, head: &[u8]) -> Option<Arc<dyn VlmContentType>> {
+        detect(&self.types, head).into_iter().next()
+    }
+
+    /// The candidate types for a file, see [`types_for_path`].
+    pub async fn for_path(&self, path: &Path) -> Vec<Arc<dyn VlmContentType>> {
+        types_for_path(&self.types, path).await
+    }
+
+    /// The registered types in the shape `VLM::serve` takes.
+    pub fn into_shared(self) -> Arc<Option<Vec<Arc<dyn VlmContentType>>>> {
+        Arc::new(Some(self.types))
+    }
+}
+
+impl From<VlmContentTypes> for Vec<Arc<dyn VlmContentType>> {
+    fn from(registry: VlmContentTypes) -> Self {
+        registry.types
+    }
+}
+
+/// Types whose magic bytes start `head`, in registration order.
+pub(crate) fn detect(types: &[Arc<dyn VlmContentType>], head: &[u8]) -> Vec<Arc<dyn VlmContentType>> {
+    types
+        .iter()
+        .filter(|ct| ct.magic().iter().any(|magic| head.starts_with(magic)))
+        .cloned()
+        .collect()
+}
@@ -0,0 +1,654 @@
+use std::{
+    error::Error,
+    fs, io,
+    net::SocketAddr,
+    path::{Path, PathBuf},
+    sync::{mpsc, Arc},
+    thread::{self, JoinHandle},
+};
+
+use futures_util::{
+    future::{self, LocalBoxFuture},
+    stream, Future, FutureExt,
+};
+use serde_json::Value;
+use tokio::sync::{oneshot, watch};
+use warp::{
+    filters::BoxedFilter,
+    http::{Response, StatusCode},
+    hyper::Body,
+    path::FullPath,
+    reply, Filter,
+};
+
+use super::{access::VlmAccessLog, auth::VlmGuard, ws::VlmWebSockets, VlmBoundAddr, VlmHost, VlmPort};
+
+/// Server settings passed to `VLM::serve_with` alongside the content path.
+#[derive(Debug, Clone)]
+pub struct VlmOptions {
+    /// Data available to `.ejs` templates, usually a JSON object.
+    pub ejs_context: Value,
+    /// Watch the content root and drop cached files when they change. Off by default.
+    pub watch: bool,
+    /// Serve the live-reload endpoint and inject its script into HTML pages.
+    /// Implies `watch`.
+    pub live_reload: bool,
+    /// Serve HTTPS instead of plain HTTP.
+    pub tls: Option<VlmTls>,
+    /// Log every request to the `vlm::access` target in this format. Off by default.
+    pub access_log: Option<VlmAccessLog>,
+    /// Serve request counters and latency histograms on `/metrics`.
+    pub metrics: bool,
+    /// WebSocket routes served ahead of the API and static content.
+    pub websockets: VlmWebSockets,
+    /// Requirements checked before any route answers, see `VlmAuth`.
+    pub auth: Option<Arc<dyn VlmGuard>>,
+}
+
+/// PEM-encoded certificate material, read from disk or held in memory.
+#[derive(Debug, Clone)]
+pub enum VlmPem {
+    Path(PathBuf),
+    Bytes(Vec<u8>),
+}
+
+impl VlmPem {
+    pub fn load(&self) -> io::Result<Vec<u8>> {
+        match self {
+            VlmPem::Path(path) => fs::read(path),
+            VlmPem::Bytes(bytes) => Ok(bytes.clone()),
+        }
+    }
+}
+
+/// Certificate chain and private key for HTTPS serving.
+#[derive(Debug, Clone)]
+pub struct VlmTls {
+    pub cert: VlmPem,
+    pub key: VlmPem,
+    /// Plain HTTP port on the same host that redirects every request to HTTPS.
+    pub redirect_port: Option<VlmPort>,
+}
+
+impl Default for VlmOptions {
+    fn default() -> Self {
+        Self {
+            ejs_context: Value::Null,
+            watch: false,
+            live_reload: false,
+            tls: None,
+            access_log: None,
+            metrics: false,
+            websockets: VlmWebSockets::default(),
+            auth: None,
+        }
+    }
+}
+
+/// Handle to a server started by `VLM::serve`.
+///
+/// Dropping the handle leaves the server running for the rest of the process;
+/// call [`VlmServer::shutdown`] to stop it.
+#[derive(Debug)]
+pub struct VlmServer {
+    addr: VlmBoundAddr,
+    redirect_addr: Option<SocketAddr>,
+    shutdown: Option<oneshot::Sender<()>>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl VlmServer {
+    pub fn new(addr: VlmBoundAddr, shutdown: oneshot::Sender<()>, thread: JoinHandle<()>) -> Self {
+        Self {
+            addr,
+            redirect_addr: None,
+            shutdown: Some(shutdown),
+            thread: Some(thread),
+        }
+    }
+
+    /// Serves `route` on its own runtime thread and returns once the listeners are bound.
+    ///
+    /// The server always gets its own thread so callers inside or outside of a
+    /// runtime can stop it through the returned handle. `on_shutdown` runs when
+    /// shutdown starts, and in-flight requests are drained once the future it
+    /// returns completes. Hostnames are tried address by address until one binds.
+    pub fn spawn<S>(
+        route: BoxedFilter<(reply::Response,)>,
+        addr: (VlmHost, VlmPort),
+        tls: Option<&VlmTls>,
+        on_shutdown: impl FnOnce() -> S + Send + 'static,
+    ) -> Result<Self, Box<dyn Error>>
+    where
+        S: Future<Output = ()> + Send + 'static,
+    {
+        let tls = match tls {
+            Some(tls) => Some((tls.cert.load()?, tls.key.load()?, tls.redirect_port)),
+            None => None,
+        };
+        if let Some((_, _, Some(redirect_port))) = &tls
+            && *redirect_port == addr.1
+            && addr.1 != 0
+        {
+            return Err(format!("The HTTP redirect port {} is also the HTTPS port", redirect_port).into());
+        }
+        let unix = match &addr.0 {
+            VlmHost::Unix(path) if tls.is_some() => {
+                return Err(format!("TLS is not supported on Unix socket {}", path.display()).into());
+            }
+            VlmHost::Unix(path) => Some(prepare_unix_socket(path)?),
+            _ => None,
+        };
+        let sockets = match unix {
+            Some(_) => Vec::new(),
+            None => addr.0.socket_addrs(addr.1)?,
+        };
+        let (shutdown_tx, shutdown_rx) = oneshot::channel::<()>();
+        let (bound_tx, bound_rx) = mpsc::channel::<Result<(VlmBoundAddr, Option<SocketAddr>), String>>();
+
+        let thread = thread::spawn(move || {
+            let rt = match tokio::runtime::Builder::new_multi_thread()
+                .worker_threads(4)
+                .enable_all()
+                .build()
+            {
+                Ok(rt) => rt,
+                Err(e) => {
+                    let _ = bound_tx.send(Err(format!("Failed to create Tokio runtime: {}", e)));
+                    return;
+                }
+            };
+            rt.block_on(async move {
+                let (stop_tx, stop_rx) = watch::channel(false);
+                tokio::spawn(async move {
+                    // A dropped handle must not stop the server, only an explicit shutdown.
+                    if shutdown_rx.await.is_err() {
+                        future::pending::<()>().await;
+                    }
+                    on_shutdown().await;
+                    let _ = stop_tx.send(true);
+                });
+                let stopped = move || {
+                    let mut stop_rx = stop_rx.clone();
+                    async move {
+                        let _ = stop_rx.wait_for(|stopped| *stopped).await;
+                    }
+                };
+
+                let bound = match &unix {
+                    Some(path) => bind_unix(route, path, stopped())
+                        .map(|server| (VlmBoundAddr::Unix(path.clone()), server)),
+                    None => {
+                        let mut bound = Err(format!("No address to bind for {}", addr.0));
+                        for socket in &sockets {
+                            bound = bind_tcp(route.clone(), *socket, tls.as_ref(), stopped())
+                                .map(|(addr, server)| (VlmBoundAddr::Tcp(addr), server));
+                            if bound.is_ok() {
+                                break;
+                            }
+                        }
+                        bound
+                    }
+                };
+                let (bound, server) = match bound {
+                    Ok(bound) => bound,
+                    Err(e) => {
+                        let _ = bound_tx.send(Err(e));
+                        return;
+                    }
+                };
+
+                let redirect = match (bound.socket_addr(), tls.and_then(|(_, _, port)| port)) {
+                    (Some(https), Some(port)) => {
+                        let redirect_socket = SocketAddr::new(https.ip(), port);
+                        match warp::serve(redirect_to_https(https))
+                            .try_bind_with_graceful_shutdown(redirect_socket, stopped())
+                        {
+                            Ok((redirect_addr, redirect)) => Some((redirect_addr, redirect)),
+                            Err(e) => {
+                                let _ = bound_tx.send(Err(bind_error(redirect_socket, e)));
+                                return;
+                            }
+                        }
+                    }
+                    _ => None,
+                };
+
+                let _ = bound_tx.send(Ok((bound.clone(), redirect.as_ref().map(|(addr, _)| *addr))));
+                let redirect = async move {
+                    if let Some((_, redirect)) = redirect {
+                        redirect.await;
+                    }
+                };
+                future::join(server, redirect).await;
+                if let VlmBoundAddr::Unix(path) = bound {
+                    let _ = fs::remove_file(path);
+                }
+            });
+        });
+
+        let (addr, redirect_addr) = bound_rx
+            .recv()
+            .map_err(|_| "Server thread exited before binding")??;
+        log::info!("Serving on {}", addr);
+        if let Some(redirect_addr) = redirect_addr {
+            log::info!("Redirecting HTTP on {} to HTTPS", redirect_addr);
+        }
+        let server = Self::new(addr, shutdown_tx, thread);
+        Ok(match redirect_addr {
+            Some(redirect_addr) => server.with_redirect_addr(redirect_addr),
+            None => server,
+        })
+    }
+
+    /// The address the server is actually bound to, including an OS-assigned port.
+    pub fn local_addr(&self) -> &VlmBoundAddr {
+        &self.addr
+    }
+
+    /// Records the address of the HTTP listener redirecting to HTTPS.
+    pub fn with_redirect_addr(mut self, addr: SocketAddr) -> Self {
+        self.redirect_addr = Some(addr);
+        self
+    }
+
+    /// The address of the HTTP to HTTPS redirect listener, when one was requested.
+    pub fn redirect_addr(&self) -> Option<SocketAddr> {
+        self.redirect_addr
+    }
+
+    /// Stops accepting connections, lets in-flight requests finish and waits for the server to exit.
+    pub fn shutdown(mut self) -> Result<(), Box<dyn Error>> {
+        if let Some(shutdown) = self.shutdown.take() {
+            // The receiver is gone only if the server already stopped.
+            let _ = shutdown.send(());
+        }
+        self.join()
+    }
+
+    /// Blocks until the server exits.
+    pub fn wait(mut self) -> Result<(), Box<dyn Error>> {
+        self.join()
+    }
+
+    fn join(&mut self) -> Result<(), Box<dyn Error>> {
+        match self.thread.take() {
+            Some(thread) => thread
+                .join()
+                .map_err(|_| format!("Server thread on {} panicked", self.addr).into()),
+            None => Ok(()),
+        }
+    }
+}
+
+fn bind_tcp(
+    route: BoxedFilter<(reply::Response,)>,
+    socket: SocketAddr,
+    tls: Option<&(Vec<u8>, Vec<u8>, Option<VlmPort>)>,
+    signal: impl Future<Output = ()> + Send + 'static,
+) -> Result<(SocketAddr, LocalBoxFuture<'static, ()>), String> {
+    let bound = match tls {
+        Some((cert, key, _)) => warp::serve(route)
+            .tls()
+            .cert(cert)
+            .key(key)
+            .try_bind_with_graceful_shutdown(socket, signal)
+            .map(|(addr, server)| (addr, server.boxed_local())),
+        None => warp::serve(route)
+            .try_bind_with_graceful_shutdown(socket, signal)
+            .map(|(addr, server)| (addr, server.boxed_local())),
+    };
+    bound.map_err(|e| bind_error(socket, e))
+}
+
+/// Names the port when binding failed because it is taken, the usual conflict.
+fn bind_error(socket: SocketAddr, error: warp::Error) -> String {
+    let mut source: Option<&(dyn Error + 'static)> = Some(&error);
+    while let Some(e) = source {
+        if e.downcast_ref::<io::Error>().is_some_and(|e| e.kind() == io::ErrorKind::AddrInUse) {
+            return format!("Port {} on {} is already in use", socket.port(), socket.ip());
+        }
+        source = e.source();
+    }
+    format!("Failed to start server on {}: {}", socket, error)
+}
+
+/// How long accepting pauses after a failed accept, e.g. when out of file descriptors.
+#[cfg(unix)]
+const ACCEPT_ERROR_DELAY: std::time::Duration = std::time::Duration::from_secs(1);
+
+#[cfg(unix)]
+fn bind_unix(
+    route: BoxedFilter<(reply::Response,)>,
+    path: &Path,
+    signal: impl Future<Output = ()> + Send + 'static,
+) -> Result<LocalBoxFuture<'static, ()>, String> {
+    let listener = tokio::net::UnixListener::bind(path)
+        .map_err(|e| format!("Failed to bind {}: {}", path.display(), e))?;
+    // Like the TCP listener, a failed accept is logged and never ends the server.
+    let incoming = stream::unfold((listener, path.to_path_buf()), |(listener, path)| async move {
+        loop {
+            match listener.accept().await {
+                Ok((stream, _)) => return Some((Ok::<_, io::Error>(stream), (listener, path))),
+                Err(e) => {
+                    log::error!("Failed to accept a connection on {}: {}", path.display(), e);
+                    tokio::time::sleep(ACCEPT_ERROR_DELAY).await;
+                }
+            }
+        }
+    });
+    Ok(warp::serve(route)
+        .serve_incoming_with_graceful_shutdown(Box::pin(incoming), signal)
+        .boxed_local())
+}
+
+#[cfg(not(unix))]
+fn bind_unix(
+    _route: BoxedFilter<(reply::Response,)>,
+    path: &Path,
+    _signal: impl Future<Output = ()> + Send + 'static,
+) -> Result<LocalBoxFuture<'static, ()>, String> {
+    Err(format!("Unix socket {} is not supported on this platform", path.display()))
+}
+
+/// Removes a stale socket file left by a previous server, refusing to touch live
+/// sockets and anything that is not a socket.
+#[cfg(unix)]
+fn prepare_unix_socket(path: &Path) -> io::Result<PathBuf> {
+    use std::os::unix::fs::FileTypeExt;
+
+    match fs::symlink_metadata(path) {
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(path.to_path_buf()),
+        Err(e) => Err(e),
+        Ok(meta) if !meta.file_type().is_socket() => Err(io::Error::new(
+            io::ErrorKind::AlreadyExists,
+            format!("{} exists and is not a socket", path.display()),
+        )),
+        Ok(_) if std::os::unix::net::UnixStream::connect(path).is_ok() => Err(io::Error::new(
+            io::ErrorKind::AddrInUse,
+            format!("{} is already in use", path.display()),
+        )),
+        Ok(_) => fs::remove_file(path).map(|()| path.to_path_buf()),
+    }
+}
+
+#[cfg(not(unix))]
+fn prepare_unix_socket(path: &Path) -> io::Result<PathBuf> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        format!("Unix socket {} is not supported on this platform", path.display()),
+    ))
+}
+
+/// Answers every plain HTTP request with a permanent redirect to the HTTPS listener at `https`.
+fn redirect_to_https(https: SocketAddr) -> BoxedFilter<(reply::Response,)> {
+    warp::path::full()
+        .and(warp::query::raw().or(warp::any().map(String::new)).unify())
+        .and(warp::header::optional::<String>("host"))
+        .map(move |path: FullPath, query: String, host: Option<String>| {
+            let host = match host.as_deref().map(strip_port) {
+                Some(host) => host.to_string(),
+                None if https.is_ipv6() => format!("[{}]", https.ip()),
+                None => https.ip().to_string(),
+            };
+            let port = match https.port() {
+                443 => String::new(),
+                port => format!(":{}", port),
+            };
+            let query = match query.is_empty() {
+                true => query,
+                false => format!("?{}", query),
+            };
+            Response::builder()
+                .status(StatusCode::MOVED_PERMANENTLY)
+                .header("Location", format!("https://{}{}{}{}", host, port, path.as_str(), query))
+                .body(Body::empty())
+                .unwrap()
+        })
+        .boxed()
+}
+
+/// Removes the port from a `Host` header value, keeping IPv6 brackets.
+fn strip_port(host: &str) -> &str {
+    if host.starts_with('[') {
+        return host.find(']').map_or(host, |end| &host[..=end]);
+    }
+    match host.rsplit_once(':') {
+        Some((name, _)) if !name.contains(':') => name,
+        _ => host,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn route() -> BoxedFilter<(reply::Response,)> {
+        warp::any().map(|| reply::Reply::into_response("ok")).boxed()
+    }
+
+    fn spawn(port: VlmPort, tls: Option<&VlmTls>) -> Result<VlmServer, Box<dyn Error>> {
+        VlmServer::spawn(route(), (VlmHost::V4([127, 0, 0, 1]), port), tls, || async {})
+    }
+
+    #[test]
+    fn taken_ports_fail_with_the_port_named() {
+        let taken = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = taken.local_addr().unwrap().port();
+        let error = spawn(port, None).err().unwrap().to_string();
+        assert_eq!(error, format!("Port {} on 127.0.0.1 is already in use", port));
+    }
+
+    #[test]
+    fn the_bound_address_comes_from_the_listener() {
+        let server = spawn(0, None).unwrap();
+        let addr = server.local_addr().socket_addr().unwrap();
+        assert_ne!(addr.port(), 0);
+        assert!(std::net::TcpStream::connect(addr).is_ok());
+        server.shutdown().unwrap();
+    }
+
+    #[test]
+    fn the_redirect_port_must_differ_from_the_https_port() {
+        let tls = VlmTls {
+            cert: VlmPem::Bytes(Vec::new()),
+            key: VlmPem::Bytes(Vec::new()),
+            redirect_port: Some(8443),
+        };
+        let error = spawn(8443, Some(&tls)).err().unwrap().to_string();
+        assert_eq!(error, "The HTTP redirect port 8443 is also the HTTPS port");
+    }
+
+    /// A certificate for `localhost` signed by itself, as PEM cert and key.
+    fn self_signed() -> (String, String) {
+        let cert = rcgen::generate_simple_self_signed(vec!["localhost".to_string()]).unwrap();
+        (cert.serialize_pem().unwrap(), cert.serialize_private_key_pem())
+    }
+
+    /// Sends a GET over a TLS connection that only trusts `cert`, returning the raw response.
+    fn https_get(addr: SocketAddr, cert: &str, path: &str) -> String {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+        use tokio_rustls::rustls;
+
+        let mut roots = rustls::RootCertStore::empty();
+        for der in rustls_pemfile::certs(&mut cert.as_bytes()).unwrap() {
+            roots.add(&rustls::Certificate(der)).unwrap();
+        }
+        let config = rustls::ClientConfig::builder()
+            .with_safe_defaults()
+            .with_root_certificates(roots)
+            .with_no_client_auth();
+        let connector = tokio_rustls::TlsConnector::from(Arc::new(config));
+        let rt = tokio::runtime::Runtime::new().unwrap();
+        rt.block_on(async {
+            let tcp = tokio::net::TcpStream::connect(addr).await.unwrap();
+            let name = rustls::ServerName::try_from("localhost").unwrap();
+            let mut tls = connector.connect(name, tcp).await.unwrap();
+            let request = format!("GET {} HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n", path);
+            tls.write_all(request.as_bytes()).await.unwrap();
+            let mut response = Vec::new();
+            // Servers may close without a TLS close_notify, which rustls reports as an error.
+            let _ = tls.read_to_end(&mut response).await;
+            String::from_utf8(response).unwrap()
+        })
+    }
+
+    #[test]
+    fn https_serves_with_a_self_signed_cert() {
+        let (cert, key) = self_signed();
+        let tls = VlmTls {
+            cert: VlmPem::Bytes(cert.clone().into_bytes()),
+            key: VlmPem::Bytes(key.into_bytes()),
+            redirect_port: None,
+        };
+        let server = spawn(0, Some(&tls)).unwrap();
+        let addr = server.local_addr().socket_addr().unwrap();
+        let response = https_get(addr, &cert, "/");
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"), "{}", response);
+        assert!(response.ends_with("\r\n\r\nok"), "{}", response);
+        assert_eq!(server.redirect_addr(), None);
+        server.shutdown().unwrap();
+    }
+
+    #[test]
+    fn pem_files_are_read_from_their_paths() {
+        let (cert, key) = self_signed();
+        let dir = std::env::temp_dir().join(format!("vlm-tls-paths-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("cert.pem"), &cert).unwrap();
+        fs::write(dir.join("key.pem"), &key).unwrap();
+        let tls = VlmTls {
+            cert: VlmPem::Path(dir.join("cert.pem")),
+            key: VlmPem::Path(dir.join("key.pem")),
+            redirect_port: None,
+        };
+        let server = spawn(0, Some(&tls)).unwrap();
+        let addr = server.local_addr().socket_addr().unwrap();
+        assert!(https_get(addr, &cert, "/").starts_with("HTTP/1.1 200 OK\r\n"));
+        server.shutdown().unwrap();
+
+        let missing = VlmTls {
+            cert: VlmPem::Path(dir.join("missing.pem")),
+            ..tls
+        };
+        assert!(spawn(0, Some(&missing)).is_err());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn keys_that_do_not_parse_fail_to_start() {
+        let (cert, _) = self_signed();
+        let tls = VlmTls {
+            cert: VlmPem::Bytes(cert.into_bytes()),
+            key: VlmPem::Bytes(b"not a key".to_vec()),
+            redirect_port: None,
+        };
+        let error = spawn(0, Some(&tls)).err().unwrap().to_string();
+        assert!(error.starts_with("Failed to start server on 127.0.0.1:"), "{}", error);
+    }
+
+    #[test]
+    fn plain_http_is_redirected_to_https() {
+        use std::io::{Read, Write};
+
+        let (cert, key) = self_signed();
+        let tls = VlmTls {
+            cert: VlmPem::Bytes(cert.into_bytes()),
+            key: VlmPem::Bytes(key.into_bytes()),
+            redirect_port: Some(0),
+        };
+        let server = spawn(0, Some(&tls)).unwrap();
+        let https = server.local_addr().socket_addr().unwrap();
+        let redirect = server.redirect_addr().unwrap();
+        assert_ne!(redirect.port(), https.port());
+
+        let mut http = std::net::TcpStream::connect(redirect).unwrap();
+        http.write_all(b"GET /docs/a.html?b=1 HTTP/1.1\r\nHost: localhost:8080\r\nConnection: close\r\n\r\n")
+            .unwrap();
+        let mut response = String::new();
+        http.read_to_string(&mut response).unwrap();
+        assert!(
+            response.starts_with("HTTP/1.1 301 Moved Permanently\r\n"),
+            "{}",
+            response
+        );
+        let location = format!("https://localhost:{}/docs/a.html?b=1", https.port());
+        assert!(
+            response
+                .lines()
+                .any(|line| line.eq_ignore_ascii_case(&format!("location: {}", location))),
+            "{}",
+            response
+        );
+        server.shutdown().unwrap();
+    }
+
+    /// Sends a `GET` for `path` and returns the whole response.
+    fn http_get(addr: SocketAddr, path: &str) -> io::Result<String> {
+        use std::io::{Read, Write};
+
+        let mut stream = std::net::TcpStream::connect(addr)?;
+        write!(stream, "GET {} HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n", path)?;
+        let mut response = String::new();
+        stream.read_to_string(&mut response)?;
+        Ok(response)
+    }
+
+    #[test]
+    fn shutdown_drains_requests_then_stops_serving() {
+        use std::{
+            sync::atomic::{AtomicBool, Ordering},
+            time::Duration,
+        };
+
+        let slow = warp::path("slow")
+            .then(|| async {
+                tokio::time::sleep(Duration::from_millis(300)).await;
+                reply::Reply::into_response("slow")
+            })
+            .or(warp::any().map(|| reply::Reply::into_response("ok")))
+            .unify()
+            .boxed();
+        let closed = Arc::new(AtomicBool::new(false));
+        let on_shutdown = {
+            let closed = Arc::clone(&closed);
+            move || async move { closed.store(true, Ordering::SeqCst) }
+        };
+        let server = VlmServer::spawn(slow, (VlmHost::V4([127, 0, 0, 1]), 0), None, on_shutdown).unwrap();
+        let addr = server.local_addr().socket_addr().unwrap();
+        assert!(http_get(addr, "/").unwrap().ends_with("\r\n\r\nok"));
+
+        let in_flight = thread::spawn(move || http_get(addr, "/slow"));
+        thread::sleep(Duration::from_millis(100));
+        server.shutdown().unwrap();
+        assert!(closed.load(Ordering::SeqCst));
+        let response = in_flight.join().unwrap().unwrap();
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n") && response.ends_with("slow"), "{}", response);
+        assert!(http_get(addr, "/").is_err());
+    }
+
+    #[test]
+    fn wait_returns_once_the_server_thread_exits() {
+        use std::time::{Duration, Instant};
+
+        let addr = VlmBoundAddr::Tcp(SocketAddr::from(([127, 0, 0, 1], 0)));
+        let started = Instant::now();
+        let (shutdown, _) = oneshot::channel();
+        let thread = thread::spawn(|| thread::sleep(Duration::from_millis(100)));
+        VlmServer::new(addr.clone(), shutdown, thread).wait().unwrap();
+        assert!(started.elapsed() >= Duration::from_millis(100));
+
+        let (shutdown, _) = oneshot::channel();
+        let server = VlmServer::new(addr, shutdown, thread::spawn(|| panic!("boom")));
+        let error = server.wait().unwrap_err().to_string();
+        assert_eq!(error, "Server thread on 127.0.0.1:0 panicked");
+    }
+
+    #[test]
+    fn redirect_hosts_lose_their_port_but_keep_ipv6_brackets() {
+        assert_eq!(strip_port("example.com:8080"), "example.com");
+        assert_eq!(strip_port("[::1]:8080"), "[::1]");
+        assert_eq!(strip_port("[::1]"), "[::1]");
+        assert_eq!(strip_port("::1"), "::1");
+    }
+}
@@ -0,0 +1,114 @@
+use std::{convert::Infallible, path::Path, sync::Arc};
+
+use futures_util::{stream, Stream};
+use notify::{Event, EventKind, RecommendedWatcher, RecursiveMode, Watcher};
+use tokio::sync::{broadcast, watch};
+
+use super::files::VlmFiles;
+
+/// Path of the server-sent events endpoint used by live reload.
+pub const LIVE_RELOAD_PATH: &str = "/__vlm/livereload";
+
+/// Snippet injected into HTML responses when live reload is enabled.
+pub const LIVE_RELOAD_SCRIPT: &str = "<script>new EventSource(\"/__vlm/livereload\").addEventListener(\"reload\", () => location.reload());</script>";
+
+/// Watches the content root, invalidating cached files and announcing changes.
+pub struct VlmWatcher {
+    reload: broadcast::Sender<()>,
+    closed: watch::Sender<bool>,
+    _watcher: RecommendedWatcher,
+}
+
+impl VlmWatcher {
+    pub fn new(files: Arc<VlmFiles>) -> notify::Result<Self> {
+        let (reload, _) = broadcast::channel(16);
+        let notify_reload = reload.clone();
+        let cache = Arc::clone(&files);
+        let mut watcher = notify::recommended_watcher(move |event: notify::Result<Event>| {
+            let Ok(event) = event else { return };
+            if matches!(
+                event.kind,
+                EventKind::Create(_) | EventKind::Modify(_) | EventKind::Remove(_)
+            ) {
+                event.paths.iter().for_each(|path| cache.invalidate(path));
+                // Nobody listening is fine, the cache is already invalidated.
+                let _ = notify_reload.send(());
+            }
+        })?;
+
+        // Editors often replace a file instead of writing to it, so a single
+        // file is watched through its directory.
+        let root = files.root();
+        match root.is_dir() {
+            true => watcher.watch(root, RecursiveMode::Recursive)?,
+            false => watcher.watch(root.parent().unwrap_or(Path::new("/")), RecursiveMode::NonRecursive)?,
+        }
+        Ok(Self {
+            reload,
+            closed: watch::Sender::new(false),
+            _watcher: watcher,
+        })
+    }
+
+    /// Receives a message every time served content changes.
+    pub fn subscribe(&self) -> broadcast::Receiver<()> {
+        self.reload.subscribe()
+    }
+
+    /// Server-sent `reload` events for the live-reload endpoint.
+    ///
+    /// The stream ends once [`VlmWatcher::close`] is called, so open pages do not
+    /// hold up a graceful shutdown.
+    pub fn events(&self) -> impl Stream<Item = Result<warp::sse::Event, Infallible>> + Send + use<> {
+        stream::unfold((self.subscribe(), self.closed.subscribe()), |(mut rx, mut closed)| async move {
+            let reload = tokio::select! {
+                received = rx.recv() => !matches!(received, Err(broadcast::error::RecvError::Closed)),
+                _ = closed.wait_for(|closed| *closed) => false,
+            };
+            reload.then(|| {
+                let event = warp::sse::Event::default().event("reload").data("reload");
+                (Ok(event), (rx, closed))
+            })
+        })
+    }
+
+    /// Ends every live-reload stream.
+    pub fn close(&self) {
+        self.closed.send_replace(true);
+    }
+}
+
+/// Adds the live-reload script to an HTML document, before `</body>` when present.
+pub fn inject_live_reload(html: &str) -> String {
+    match html.rfind("</body>") {
+        Some(at) => format!("{}{}{}", &html[..at], LIVE_RELOAD_SCRIPT, &html[at..]),
+        None => format!("{}{}", html, LIVE_RELOAD_SCRIPT),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{env, fs, time::Duration};
+
+    use super::*;
+
+    #[test]
+    fn edits_are_read_without_a_watcher() {
+        let root = env::temp_dir().join(format!("vlm-watch-{}", std::process::id()));
+        fs::create_dir_all(&root).unwrap();
+        let (page, include) = (root.join("page.ejs"), root.join("header.ejs"));
+        fs::write(&page, "<%- include('header.ejs') %> page").unwrap();
+        fs::write(&include, "old").unwrap();
+        let files = VlmFiles::new(root.clone()).unwrap();
+        let context = serde_json::json!({});
+        assert_eq!(files.render(&page, &context).unwrap(), "old page");
+
+        // Same size, so only the modification time tells the versions apart.
+        fs::write(&include, "new").unwrap();
+        let file = fs::File::options().write(true).open(&include).unwrap();
+        file.set_modified(fs::metadata(&include).unwrap().modified().unwrap() + Duration::from_secs(1)).unwrap();
+        assert_eq!(files.render(&page, &context).unwrap(), "new page");
+        assert_eq!(*files.read(&include).unwrap(), "new");
+        fs::remove_dir_all(&root).unwrap();
+    }
+}
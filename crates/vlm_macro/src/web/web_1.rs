@@ -1,23 +1,141 @@
 pub trait VlmContentType: Send + Sync {
     fn content_type_header(&self) -> &[u8];
     fn charset(&self) -> &[u8];
+    /// Charset parameter sent with the media type, `None` when [`charset`](Self::charset)
+    /// is empty, as it is for binary types.
+    fn charset_param(&self) -> Option<&[u8]> {
+        Some(self.charset()).filter(|charset| !charset.is_empty())
+    }
+    /// File extensions (lowercase, without the dot) served with this type.
+    fn extensions(&self) -> &[&str] {
+        &[]
+    }
+    /// Leading byte signatures identifying files of this type regardless of their extension.
+    fn magic(&self) -> &[&[u8]] {
+        &[]
+    }
+    /// The full `Content-Type` header value, e.g. `text/html; charset=utf-8`.
+    fn header_value(&self) -> String {
+        let media = String::from_utf8_lossy(self.content_type_header());
+        match self.charset_param() {
+            Some(charset) => format!("{}; charset={}", media, String::from_utf8_lossy(charset)),
+            None => media.into_owned(),
+        }
+    }
 }
 
+/// Defines a content type struct with its `VlmContentType` impl and a `Default`
+/// using the usual media type.
+///
+/// `text` types take a charset in `new` and default to UTF-8, `binary` types have an empty one.
+macro_rules! vlm_content_type {
+    ($(#[$doc:meta])* $name:ident: text $media:literal, [$($ext:literal),*] $(, magic [$($magic:literal),*])?) => {
+        vlm_content_type!(@define $(#[$doc])* $name, $media, b"utf-8".to_vec(), [$($ext),*] $(, [$($magic),*])?);
 
-// Define the EJS struct
-pub struct EJS {
-    pub(crate) content_type: Vec<u8>,
-    pub(crate) charset: Vec<u8>,
+        impl $name {
+            pub fn new(content_type: &[u8], charset: &[u8]) -> Self {
+                Self {
+                    content_type: content_type.to_vec(),
+                    charset: charset.to_vec(),
+                }
+            }
+        }
+    };
+    ($(#[$doc:meta])* $name:ident: binary $media:literal, [$($ext:literal),*] $(, magic [$($magic:literal),*])?) => {
+        vlm_content_type!(@define $(#[$doc])* $name, $media, Vec::new(), [$($ext),*] $(, [$($magic),*])?);
+
+        impl $name {
+            pub fn new(content_type: &[u8]) -> Self {
+                Self {
+                    content_type: content_type.to_vec(),
+                    charset: Vec::new(),
+                }
+            }
+        }
+    };
+    (@define $(#[$doc:meta])* $name:ident, $media:literal, $charset:expr, [$($ext:literal),*] $(, [$($magic:literal),*])?) => {
+        $(#[$doc])*
+        pub struct $name {
+            pub(crate) content_type: Vec<u8>,
+            pub(crate) charset: Vec<u8>,
+        }
+
+        impl Default for $name {
+            fn default() -> Self {
+                Self {
+                    content_type: $media.as_bytes().to_vec(),
+                    charset: $charset,
+                }
+            }
+        }
+
+        impl VlmContentType for $name {
+            fn content_type_header(&self) -> &[u8] {
+                &self.content_type
+            }
+
+            fn charset(&self) -> &[u8] {
+                &self.charset
+            }
+
+            fn extensions(&self) -> &[&str] {
+                &[$($ext),*]
+            }
+
+            fn magic(&self) -> &[&[u8]] {
+                &[$($($magic),*)?]
+            }
+        }
+    };
+}
+
+vlm_content_type! {
+    /// Embedded JavaScript templates, rendered before they are sent.
+    EJS: text "text/html", ["ejs"]
+}
+
+vlm_content_type! {
+    HTML: text "text/html", ["html", "htm"]
+}
+
+vlm_content_type! {
+    XML: text "application/xml", ["xml"], magic [b"<?xml"]
+}
+
+vlm_content_type! {
+    JSON: text "application/json", ["json"]
 }
 
-// Define the HTML struct
-pub struct HTML {
-    pub(crate) content_type: Vec<u8>,
-    pub(crate) charset: Vec<u8>,
+vlm_content_type! {
+    /// Newline-delimited JSON, one document per line.
+    NDJSON: text "application/x-ndjson", ["ndjson", "jsonl"]
 }
 
-// Define the XML struct
-pub struct XML {
-    pub(crate) content_type: Vec<u8>,
-    pub(crate) charset: Vec<u8>,
-}
\ No newline at end of file
+vlm_content_type! {
+    PlainText: text "text/plain", ["txt", "text", "log"]
+}
+
+vlm_content_type! {
+    CSS: text "text/css", ["css"]
+}
+
+vlm_content_type! {
+    JavaScript: text "text/javascript", ["js", "mjs"]
+}
+
+vlm_content_type! {
+    SVG: text "image/svg+xml", ["svg"]
+}
+
+vlm_content_type! {
+    WASM: binary "application/wasm", ["wasm"], magic [b"\0asm"]
+}
+
+vlm_content_type! {
+    PNG: binary "image/png", ["png"], magic [b"\x89PNG\r\n\x1a\n"]
+}
+
+vlm_content_type! {
+    /// Arbitrary bytes, the fallback for files no other type claims.
+    OctetStream: binary "application/octet-stream", ["bin"]
+}
@@ -0,0 +1,484 @@
+use std::{
+    collections::HashMap,
+    fmt,
+    future::Future,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use futures_util::{future::BoxFuture, FutureExt, SinkExt, StreamExt};
+use tokio::sync::{broadcast, mpsc, watch};
+use warp::{
+    filters::{
+        ws::{Message, WebSocket, Ws},
+        BoxedFilter,
+    },
+    path::FullPath,
+    reply, Filter, Reply,
+};
+
+/// How long shutdown waits for clients to acknowledge the close frame.
+pub const CLOSE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// A text or binary WebSocket message.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum VlmWsMessage {
+    Text(String),
+    Binary(Vec<u8>),
+}
+
+impl From<String> for VlmWsMessage {
+    fn from(text: String) -> Self {
+        VlmWsMessage::Text(text)
+    }
+}
+
+impl From<&str> for VlmWsMessage {
+    fn from(text: &str) -> Self {
+        VlmWsMessage::Text(text.to_string())
+    }
+}
+
+impl From<Vec<u8>> for VlmWsMessage {
+    fn from(bytes: Vec<u8>) -> Self {
+        VlmWsMessage::Binary(bytes)
+    }
+}
+
+impl From<VlmWsMessage> for Message {
+    fn from(message: VlmWsMessage) -> Self {
+        match message {
+            VlmWsMessage::Text(text) => Message::text(text),
+            VlmWsMessage::Binary(bytes) => Message::binary(bytes),
+        }
+    }
+}
+
+/// What to do with a client that falls behind a [`VlmBroadcast`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VlmLagPolicy {
+    /// Drop the messages it missed and carry on.
+    Skip,
+    /// Close the connection, so the client can reconnect and resynchronize.
+    Disconnect,
+}
+
+/// Limits applied to every WebSocket connection.
+#[derive(Debug, Clone)]
+pub struct VlmWsOptions {
+    /// Largest message accepted from a client.
+    pub max_message_size: usize,
+    /// How often the server pings an idle client.
+    pub ping_interval: Duration,
+    /// Connections without a pong for this long are closed.
+    pub pong_timeout: Duration,
+    /// Messages queued for a client before `send` waits for it to catch up.
+    pub send_buffer: usize,
+    pub lag: VlmLagPolicy,
+}
+
+impl VlmWsOptions {
+    /// Checks the limits can drive a connection: a ping interval and send buffer
+    /// above zero, and a pong timeout no shorter than the ping interval.
+    pub fn validate(&self) -> Result<(), String> {
+        if self.ping_interval.is_zero() {
+            return Err("WebSocket ping_interval must be above zero".to_string());
+        }
+        if self.pong_timeout < self.ping_interval {
+            return Err(format!(
+                "WebSocket pong_timeout {:?} is shorter than ping_interval {:?}",
+                self.pong_timeout, self.ping_interval
+            ));
+        }
+        if self.send_buffer == 0 {
+            return Err("WebSocket send_buffer must be above zero".to_string());
+        }
+        Ok(())
+    }
+}
+
+impl Default for VlmWsOptions {
+    fn default() -> Self {
+        Self {
+            max_message_size: 1024 * 1024,
+            ping_interval: Duration::from_secs(30),
+            pong_timeout: Duration::from_secs(75),
+            send_buffer: 64,
+            lag: VlmLagPolicy::Disconnect,
+        }
+    }
+}
+
+/// The connection was closed; the message was not sent.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct VlmWsClosed;
+
+impl fmt::Display for VlmWsClosed {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("WebSocket connection closed")
+    }
+}
+
+impl std::error::Error for VlmWsClosed {}
+
+/// Fans messages out to every connection subscribed to it.
+#[derive(Debug, Clone)]
+pub struct VlmBroadcast {
+    sender: broadcast::Sender<VlmWsMessage>,
+}
+
+impl VlmBroadcast {
+    /// A channel keeping up to `capacity` messages for clients that fall behind.
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            sender: broadcast::channel(capacity).0,
+        }
+    }
+
+    /// Sends `message` to every subscriber and returns how many there were.
+    pub fn send(&self, message: impl Into<VlmWsMessage>) -> usize {
+        self.sender.send(message.into()).unwrap_or(0)
+    }
+
+    pub fn subscribers(&self) -> usize {
+        self.sender.receiver_count()
+    }
+}
+
+/// One client connection, handed to the route handler.
+pub struct VlmWsConnection {
+    path: String,
+    outgoing: mpsc::Sender<Message>,
+    incoming: mpsc::Receiver<VlmWsMessage>,
+    lag: VlmLagPolicy,
+}
+
+impl VlmWsConnection {
+    /// The request path the client connected to.
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+
+    /// Queues `message`, waiting while the client's send buffer is full.
+    pub async fn send(&self, message: impl Into<VlmWsMessage>) -> Result<(), VlmWsClosed> {
+        let message: VlmWsMessage = message.into();
+        self.outgoing.send(message.into()).await.map_err(|_| VlmWsClosed)
+    }
+
+    /// Queues `message` without waiting. Returns `false` when the buffer is full or the connection closed.
+    pub fn try_send(&self, message: impl Into<VlmWsMessage>) -> bool {
+        let message: VlmWsMessage = message.into();
+        self.outgoing.try_send(message.into()).is_ok()
+    }
+
+    /// The next message from the client, `None` once it disconnects.
+    pub async fn recv(&mut self) -> Option<VlmWsMessage> {
+        self.incoming.recv().await
+    }
+
+    /// Forwards everything sent on `broadcast` to this client until either side closes.
+    pub fn subscribe(&self, broadcast: &VlmBroadcast) {
+        let mut receiver = broadcast.sender.subscribe();
+        let outgoing = self.outgoing.clone();
+        let lag = self.lag;
+        let path = self.path.clone();
+        tokio::spawn(async move {
+            loop {
+                let message = match receiver.recv().await {
+                    Ok(message) => message,
+                    Err(broadcast::error::RecvError::Lagged(missed)) if lag == VlmLagPolicy::Skip => {
+                        log::warn!(target: "vlm::ws", "Client on {} skipped {} messages", path, missed);
+                        continue;
+                    }
+                    Err(broadcast::error::RecvError::Lagged(missed)) => {
+                        log::warn!(target: "vlm::ws", "Closing client on {} after missing {} messages", path, missed);
+                        let _ = outgoing.send(Message::close_with(1008u16, "too slow")).await;
+                        return;
+                    }
+                    Err(broadcast::error::RecvError::Closed) => return,
+                };
+                if outgoing.send(message.into()).await.is_err() {
+                    return;
+                }
+            }
+        });
+    }
+}
+
+type Handler = dyn Fn(VlmWsConnection) -> BoxFuture<'static, ()> + Send + Sync;
+
+/// WebSocket routes served by the `Vlm` server, configured through `VlmOptions::websockets`.
+#[derive(Clone)]
+pub struct VlmWebSockets {
+    routes: HashMap<String, Arc<Handler>>,
+    options: VlmWsOptions,
+    closed: Arc<Mutex<Option<watch::Sender<bool>>>>,
+    closing: watch::Receiver<bool>,
+    /// Number of open connections.
+    open: Arc<watch::Sender<usize>>,
+}
+
+impl Default for VlmWebSockets {
+    fn default() -> Self {
+        let (closed, closing) = watch::channel(false);
+        Self {
+            routes: HashMap::new(),
+            options: VlmWsOptions::default(),
+            closed: Arc::new(Mutex::new(Some(closed))),
+            closing,
+            open: Arc::new(watch::channel(0).0),
+        }
+    }
+}
+
+impl fmt::Debug for VlmWebSockets {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("VlmWebSockets")
+            .field("routes", &self.routes.keys().collect::<Vec<_>>())
+            .field("options", &self.options)
+            .finish()
+    }
+}
+
+impl VlmWebSockets {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Applies `options` to every connection, see [`VlmWsOptions::validate`].
+    pub fn with_options(mut self, options: VlmWsOptions) -> Result<Self, String> {
+        options.validate()?;
+        self.options = options;
+        Ok(self)
+    }
+
+    /// Runs `handler` for every client connecting to `path`.
+    pub fn route<F, Fut>(mut self, path: &str, handler: F) -> Self
+    where
+        F: Fn(VlmWsConnection) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = ()> + Send + 'static,
+    {
+        let handler = move |connection| handler(connection).boxed();
+        self.routes.insert(normalize(path), Arc::new(handler));
+        self
+    }
+
+    /// Sends everything on `broadcast` to clients of `path`, ignoring what they send.
+    pub fn broadcast(self, path: &str, broadcast: VlmBroadcast) -> Self {
+        self.route(path, move |mut connection| {
+            connection.subscribe(&broadcast);
+            async move { while connection.recv().await.is_some() {} }
+        })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.routes.is_empty()
+    }
+
+    /// Number of clients currently connected.
+    pub fn connections(&self) -> usize {
+        *self.open.borrow()
+    }
+
+    /// Sends a close frame to every open connection, e.g. when the server shuts
+    /// down, and waits up to [`CLOSE_TIMEOUT`] for them to finish.
+    pub async fn close(&self) {
+        if let Some(closed) = self.closed.lock().unwrap().take() {
+            let _ = closed.send(true);
+        }
+        let mut open = self.open.subscribe();
+        let _ = tokio::time::timeout(CLOSE_TIMEOUT, open.wait_for(|open| *open == 0)).await;
+    }
+
+    /// Warp filter upgrading requests on the registered paths, rejecting everything else.
+    pub fn filter(&self) -> BoxedFilter<(reply::Response,)> {
+        let sockets = self.clone();
+        warp::path::full()
+            .and_then(move |path: FullPath| {
+                let handler = sockets.routes.get(&normalize(path.as_str())).cloned();
+                let sockets = sockets.clone();
+                async move {
+                    match handler {
+                        Some(handler) => Ok((sockets, handler, path.as_str().to_string())),
+                        None => Err(warp::reject::not_found()),
+                    }
+                }
+            })
+            .and(warp::ws())
+            .map(
+                |(sockets, handler, path): (VlmWebSockets, Arc<Handler>, String), ws: Ws| {
+                    ws.max_message_size(sockets.options.max_message_size)
+                        .on_upgrade(move |socket| sockets.drive(socket, handler, path))
+                        .into_response()
+                },
+            )
+            .boxed()
+    }
+
+    /// Pumps messages between the socket and the handler, pinging the client and
+    /// closing it when pongs stop arriving or the server shuts down.
+    async fn drive(self, socket: WebSocket, handler: Arc<Handler>, path: String) {
+        let _open = OpenConnection::new(Arc::clone(&self.open));
+        let options = self.options;
+        let (mut sink, mut stream) = socket.split();
+        let (outgoing, mut to_client) = mpsc::channel::<Message>(options.send_buffer);
+        let (from_client, incoming) = mpsc::channel::<VlmWsMessage>(options.send_buffer);
+        let last_pong = Arc::new(Mutex::new(Instant::now()));
+
+        tokio::spawn(handler(VlmWsConnection {
+            path: path.clone(),
+            outgoing: outgoing.clone(),
+            incoming,
+            lag: options.lag,
+        }));
+
+        let reader = {
+            let last_pong = Arc::clone(&last_pong);
+            let outgoing = outgoing.clone();
+            async move {
+                while let Some(Ok(message)) = stream.next().await {
+                    let message = if message.is_pong() {
+                        *last_pong.lock().unwrap() = Instant::now();
+                        continue;
+                    } else if message.is_close() {
+                        return outgoing.send(Message::close()).await.is_ok();
+                    } else if let Ok(text) = message.to_str() {
+                        VlmWsMessage::Text(text.to_string())
+                    } else if message.is_binary() {
+                        VlmWsMessage::Binary(message.into_bytes())
+                    } else {
+                        continue;
+                    };
+                    // Waiting here stops reading from a client the handler cannot keep up with.
+                    if from_client.send(message).await.is_err() {
+                        break;
+                    }
+                }
+                false
+            }
+        };
+        drop(outgoing);
+
+        let mut closing = self.closing.clone();
+        let writer = async move {
+            let mut ping = tokio::time::interval(options.ping_interval);
+            ping.tick().await;
+            loop {
+                let message = tokio::select! {
+                    message = to_client.recv() => match message {
+                        Some(message) => message,
+                        None => Message::close(),
+                    },
+                    _ = ping.tick() => {
+                        if last_pong.lock().unwrap().elapsed() > options.pong_timeout {
+                            log::info!(target: "vlm::ws", "Closing client on {} after missing pongs", path);
+                            Message::close_with(1001u16, "ping timeout")
+                        } else {
+                            Message::ping(Vec::new())
+                        }
+                    }
+                    _ = closing.wait_for(|closed| *closed) => Message::close_with(1001u16, "server shutting down"),
+                };
+                let close = message.is_close();
+                if sink.send(message).await.is_err() || close {
+                    let _ = sink.close().await;
+                    return;
+                }
+            }
+        };
+
+        tokio::pin!(writer);
+        tokio::select! {
+            replied = reader => {
+                // The close reply is queued behind whatever the handler sent; let the writer flush it.
+                if replied {
+                    let _ = tokio::time::timeout(CLOSE_TIMEOUT, &mut writer).await;
+                }
+            }
+            _ = &mut writer => {}
+        }
+    }
+}
+
+/// Counts a connection as open until dropped.
+struct OpenConnection(Arc<watch::Sender<usize>>);
+
+impl OpenConnection {
+    fn new(open: Arc<watch::Sender<usize>>) -> Self {
+        open.send_modify(|open| *open += 1);
+        Self(open)
+    }
+}
+
+impl Drop for OpenConnection {
+    fn drop(&mut self) {
+        self.0.send_modify(|open| *open -= 1);
+    }
+}
+
+fn normalize(path: &str) -> String {
+    format!("/{}", path.trim_matches('/'))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn options_that_cannot_drive_a_connection_are_refused() {
+        let zero_ping = VlmWsOptions {
+            ping_interval: Duration::ZERO,
+            ..Default::default()
+        };
+        assert!(VlmWebSockets::new().with_options(zero_ping).is_err());
+        let no_buffer = VlmWsOptions {
+            send_buffer: 0,
+            ..Default::default()
+        };
+        assert!(VlmWebSockets::new().with_options(no_buffer).is_err());
+        let short_pong = VlmWsOptions {
+            pong_timeout: Duration::from_secs(1),
+            ..Default::default()
+        };
+        assert!(VlmWebSockets::new().with_options(short_pong).is_err());
+        assert!(VlmWebSockets::new().with_options(VlmWsOptions::default()).is_ok());
+    }
+
+    /// Opcodes of the frames the server sends after a raw client sends "hi" and a close frame.
+    async fn frames_after_close(filter: BoxedFilter<(reply::Response,)>) -> Vec<u8> {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        let (addr, server) = warp::serve(filter).bind_ephemeral(([127, 0, 0, 1], 0));
+        tokio::spawn(server);
+        let mut client = tokio::net::TcpStream::connect(addr).await.unwrap();
+        let handshake = "GET /echo HTTP/1.1\r\nHost: test\r\nConnection: Upgrade\r\nUpgrade: websocket\r\n\
+            Sec-WebSocket-Version: 13\r\nSec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\n\r\n";
+        client.write_all(handshake.as_bytes()).await.unwrap();
+        // Masked with a zero key, so the payload goes out as is.
+        client.write_all(&[0x81, 0x82, 0, 0, 0, 0, b'h', b'i']).await.unwrap();
+        client.write_all(&[0x88, 0x80, 0, 0, 0, 0]).await.unwrap();
+        let mut received = Vec::new();
+        let read = tokio::time::timeout(CLOSE_TIMEOUT, client.read_to_end(&mut received));
+        read.await.unwrap().unwrap();
+
+        let head = received.windows(4).position(|w| w == b"\r\n\r\n").unwrap() + 4;
+        let mut frames = &received[head..];
+        let mut opcodes = Vec::new();
+        while frames.len() >= 2 {
+            opcodes.push(frames[0] & 0x0f);
+            frames = &frames[(2 + (frames[1] & 0x7f) as usize).min(frames.len())..];
+        }
+        opcodes
+    }
+
+    #[tokio::test]
+    async fn client_close_is_answered() {
+        let sockets = VlmWebSockets::new().route("/echo", |mut connection| async move {
+            while let Some(message) = connection.recv().await {
+                let _ = connection.send(message).await;
+            }
+        });
+        let opcodes = frames_after_close(sockets.filter()).await;
+        assert_eq!(opcodes.last(), Some(&0x8), "frames: {:?}", opcodes);
+    }
+}
@@ -97,17 +97,52 @@ pub fn vlm_macro_derive(input: TokenStream) -> TokenStream {
         where
             Self: 'static,
         {
-            fn vlm(
+            fn vlm_with_context(
                 &self,
                 addr: (::vlm_macro::web::VlmHost, ::vlm_macro::web::VlmPort),
                 content: ::std::path::PathBuf,
+                context: ::serde_json::Value,
                 content_type: ::std::sync::Arc<Option<Vec<::std::sync::Arc<dyn ::vlm_macro::web::VlmContentType>>>>,
                 mode: ::std::sync::Arc<Option<::vlm_macro::V>>,
             ) -> Result<Option<#trait_param>, Box<dyn ::std::error::Error>> {
-                Self::start_server(addr, content, content_type, mode)?;
+                let options = ::vlm_macro::web::server::VlmOptions {
+                    ejs_context: context,
+                    ..Default::default()
+                };
+                let server = Self::start_server(addr, content, content_type, mode, options, None)?;
+                // Inside a runtime the server keeps running in the background, as before.
+                if tokio::runtime::Handle::try_current().is_err() {
+                    server.wait()?;
+                }
                 Ok(None)
             }
 
+            fn serve_with(
+                &self,
+                addr: (::vlm_macro::web::VlmHost, ::vlm_macro::web::VlmPort),
+                content: ::std::path::PathBuf,
+                content_type: ::std::sync::Arc<Option<Vec<::std::sync::Arc<dyn ::vlm_macro::web::VlmContentType>>>>,
+                mode: ::std::sync::Arc<Option<::vlm_macro::V>>,
+                options: ::vlm_macro::web::server::VlmOptions,
+            ) -> Result<::vlm_macro::web::server::VlmServer, Box<dyn ::std::error::Error>> {
+                Self::start_server(addr, content, content_type, mode, options, None)
+            }
+
+            fn serve_api(
+                &self,
+                addr: (::vlm_macro::web::VlmHost, ::vlm_macro::web::VlmPort),
+                content: ::std::path::PathBuf,
+                content_type: ::std::sync::Arc<Option<Vec<::std::sync::Arc<dyn ::vlm_macro::web::VlmContentType>>>>,
+                mode: ::std::sync::Arc<Option<::vlm_macro::V>>,
+                options: ::vlm_macro::web::server::VlmOptions,
+                api: ::vlm_macro::web::api::VlmApi<#trait_param>,
+            ) -> Result<::vlm_macro::web::server::VlmServer, Box<dyn ::std::error::Error>>
+            where
+                #trait_param: ::vlm_macro::web::api::VlmApiOutput,
+            {
+                Self::start_server(addr, content, content_type, mode, options, Some(api.into_filter()))
+            }
+
             fn type_id(&self) -> ::std::any::TypeId {
                 ::std::any::TypeId::of::<Self>()
             }
@@ -123,89 +158,270 @@ pub fn vlm_macro_derive(input: TokenStream) -> TokenStream {
                 content_path: ::std::path::PathBuf,
                 content_types: ::std::sync::Arc<Option<Vec<::std::sync::Arc<dyn ::vlm_macro::web::VlmContentType>>>>,
                 mode: ::std::sync::Arc<Option<::vlm_macro::V>>,
-            ) -> Result<(), Box<dyn ::std::error::Error>> {
-                use ::std::fs;
+                options: ::vlm_macro::web::server::VlmOptions,
+                api: Option<warp::filters::BoxedFilter<(warp::reply::Response,)>>,
+            ) -> Result<::vlm_macro::web::server::VlmServer, Box<dyn ::std::error::Error>> {
                 use warp::Filter;
-                use ::std::net::{Ipv4Addr, SocketAddr};
-                use warp::http::Response;
+                use warp::http::{Response, StatusCode};
                 use warp::hyper::Body;
-                use tokio::sync::Mutex;
                 use ::std::sync::Arc;
+                use ::vlm_macro::web::files::{types_for_path, VlmFiles, VlmResolved};
+                use ::vlm_macro::web::negotiate::negotiate;
+                use ::vlm_macro::web::{compress, conditional, range::VlmSource};
+
+                let final_addr = Self::transform_address(addr, &mode)?;
+                let files = Arc::new(VlmFiles::new(content_path)?);
+                let options = Arc::new(options);
+                let watcher = match options.watch || options.live_reload {
+                    true => Some(Arc::new(::vlm_macro::web::watch::VlmWatcher::new(Arc::clone(&files))?)),
+                    false => None,
+                };
+                let backend = match &*mode {
+                    Some(::vlm_macro::V::Go(program, _)) => {
+                        Some(Arc::new(::vlm_macro::web::proxy::VlmGoBackend::start(program)?))
+                    }
+                    None => None,
+                };
+                let compressor = Arc::new(compress::VlmCompressor::new());
+                let metrics = match options.metrics {
+                    true => Some(Arc::new(::vlm_macro::web::metrics::VlmMetrics::new())),
+                    false => None,
+                };
 
-                let final_addr = Self::transform_address(addr, &mode);
-                let content = Arc::new(Mutex::new(fs::read_to_string(&content_path)?));
+                let live_reload = warp::get()
+                    .and(warp::path!("__vlm" / "livereload"))
+                    .and_then({
+                        let watcher = watcher.clone();
+                        let enabled = options.live_reload;
+                        move || {
+                            let watcher = watcher.clone();
+                            async move {
+                                match watcher {
+                                    Some(watcher) if enabled => {
+                                        let events = watcher.events();
+                                        Ok(warp::sse::reply(warp::sse::keep_alive().stream(events)))
+                                    }
+                                    _ => Err(warp::reject::not_found()),
+                                }
+                            }
+                        }
+                    });
 
-                let route = warp::path::end()
-                    .and(warp::header::optional("Accept"))
+                let static_files = warp::get()
+                    .and(warp::path::full())
+                    .and(warp::header::headers_cloned())
                     .and_then({
-                        let content = Arc::clone(&content);
+                        let files = Arc::clone(&files);
                         let content_types = Arc::clone(&content_types);
-                        move |accept_header: Option<String>| {
-                            let content = Arc::clone(&content);
+                        let options = Arc::clone(&options);
+                        let compressor = Arc::clone(&compressor);
+                        let proxied = backend.is_some();
+                        move |path: warp::path::FullPath, headers: warp::http::HeaderMap| {
+                            let files = Arc::clone(&files);
                             let content_types = Arc::clone(&content_types);
+                            let options = Arc::clone(&options);
+                            let compressor = Arc::clone(&compressor);
                             async move {
-                                let content_val = content.lock().await.clone();
-                                let chosen_type = if let Some(ref types) = *content_types {
-                                    types.iter().find(|ct| {
-                                        accept_header.as_ref().map_or(false, |accept| {
-                                            let header = String::from_utf8_lossy(ct.content_type_header());
-                                            header.contains(accept)
-                                        })
-                                    }).cloned().unwrap_or_else(|| types.first().cloned().expect("No content types provided"))
+                                let header = |name: &str| headers.get(name).and_then(|value| value.to_str().ok());
+                                let file = match files.resolve(path.as_str()).await {
+                                    Some(VlmResolved::File(file)) => file,
+                                    Some(VlmResolved::Redirect(location)) => {
+                                        let response = Response::builder()
+                                            .status(StatusCode::MOVED_PERMANENTLY)
+                                            .header("Location", location)
+                                            .body(Body::empty())
+                                            .unwrap();
+                                        return Ok::<_, warp::Rejection>(response);
+                                    }
+                                    // Leave paths missing on disk to the backend.
+                                    None if proxied => return Err(warp::reject::not_found()),
+                                    None => {
+                                        let response = Response::builder()
+                                            .status(StatusCode::NOT_FOUND)
+                                            .header("Content-Type", "text/plain; charset=utf-8")
+                                            .body(Body::from("404 Not Found"))
+                                            .unwrap();
+                                        return Ok(response);
+                                    }
+                                };
+                                let types = match *content_types {
+                                    Some(ref types) if !types.is_empty() => types,
+                                    _ => {
+                                        let response = Response::builder()
+                                            .status(StatusCode::INTERNAL_SERVER_ERROR)
+                                            .header("Content-Type", "text/plain; charset=utf-8")
+                                            .body(Body::from("No content types configured"))
+                                            .unwrap();
+                                        return Ok(response);
+                                    }
+                                };
+                                let candidates = types_for_path(types, &file).await;
+                                let chosen_type = match negotiate(header("Accept"), &candidates) {
+                                    Some(chosen_type) => chosen_type,
+                                    None => {
+                                        let available = candidates
+                                            .iter()
+                                            .map(|ct| String::from_utf8_lossy(ct.content_type_header()).into_owned())
+                                            .collect::<Vec<_>>()
+                                            .join(", ");
+                                        let response = Response::builder()
+                                            .status(StatusCode::NOT_ACCEPTABLE)
+                                            .header("Content-Type", "text/plain; charset=utf-8")
+                                            .header("Vary", "Accept")
+                                            .body(Body::from(format!("406 Not Acceptable. Available: {}", available)))
+                                            .unwrap();
+                                        return Ok(response);
+                                    }
+                                };
+                                let content_type = chosen_type.header_value();
+
+                                // Templates and pages getting the live-reload script are built in
+                                // memory, everything else is streamed from disk.
+                                let is_template = file.extension().is_some_and(|ext| ext.eq_ignore_ascii_case("ejs"));
+                                let inject = options.live_reload && content_type.contains("html");
+                                let in_memory = if is_template || inject {
+                                    let (files, file, options) = (Arc::clone(&files), file.clone(), Arc::clone(&options));
+                                    // Templates and their includes are read from disk, so off the async workers.
+                                    let loaded = ::tokio::task::spawn_blocking(move || match is_template {
+                                        true => files
+                                            .render(&file, &options.ejs_context)
+                                            .map(Some)
+                                            .map_err(|e| format!("Failed to render template: {}", e)),
+                                        false => match files.read(&file) {
+                                            Ok(content_val) => Ok(Some(content_val.as_ref().clone())),
+                                            // Not text after all, send it untouched.
+                                            Err(e) if e.kind() == ::std::io::ErrorKind::InvalidData => Ok(None),
+                                            Err(e) => Err(format!("Failed to read content: {}", e)),
+                                        },
+                                    })
+                                    .await
+                                    .unwrap_or_else(|e| Err(format!("Failed to read content: {}", e)));
+                                    match loaded {
+                                        Ok(content_val) => content_val,
+                                        Err(message) => {
+                                            let response = Response::builder()
+                                                .status(StatusCode::INTERNAL_SERVER_ERROR)
+                                                .header("Content-Type", "text/plain; charset=utf-8")
+                                                .body(Body::from(message))
+                                                .unwrap();
+                                            return Ok(response);
+                                        }
+                                    }
                                 } else {
-                                    panic!("No content types provided");
+                                    None
+                                };
+                                let (source, tag, modified) = match in_memory {
+                                    Some(content_val) => {
+                                        let content_val = match inject {
+                                            true => ::vlm_macro::web::watch::inject_live_reload(&content_val),
+                                            false => content_val,
+                                        };
+                                        let tag = conditional::content_tag(content_val.as_bytes());
+                                        // Templates also depend on their includes and context, so only the ETag tracks them.
+                                        let modified = match is_template {
+                                            true => None,
+                                            false => files.modified(&file).await,
+                                        };
+                                        (VlmSource::Memory(content_val.into()), tag, modified)
+                                    }
+                                    None => match ::tokio::fs::metadata(&file).await {
+                                        Ok(meta) => {
+                                            let tag = conditional::file_tag(&meta);
+                                            (VlmSource::File { path: file.clone(), len: meta.len() }, tag, meta.modified().ok())
+                                        }
+                                        Err(e) => {
+                                            let response = Response::builder()
+                                                .status(StatusCode::INTERNAL_SERVER_ERROR)
+                                                .header("Content-Type", "text/plain; charset=utf-8")
+                                                .body(Body::from(format!("Failed to read content: {}", e)))
+                                                .unwrap();
+                                            return Ok(response);
+                                        }
+                                    },
                                 };
 
-                                let response = Response::builder()
-                                    .header(
-                                        "Content-Type",
-                                        format!("{}; charset={}",
-                                            String::from_utf8_lossy(chosen_type.content_type_header()),
-                                            String::from_utf8_lossy(chosen_type.charset())
-                                        )
-                                    )
-                                    .body(Body::from(content_val))
-                                    .unwrap();
-                                Ok::<_, warp::Rejection>(response)
+                                // Ranges always address the unencoded bytes.
+                                let compress = header("Range").is_none()
+                                    && (compress::MIN_COMPRESS_LEN as u64..=compress::MAX_COMPRESS_LEN).contains(&source.len())
+                                    && compress::compressible(&content_type);
+                                let encoding = match compress {
+                                    true => compress::negotiate_encoding(header("Accept-Encoding")),
+                                    false => compress::VlmEncoding::Identity,
+                                };
+                                let etag = conditional::etag(&tag, encoding);
+
+                                let mut response = Response::builder()
+                                    .header("Vary", "Accept, Accept-Encoding")
+                                    .header("ETag", &etag);
+                                if let Some(modified) = modified {
+                                    response = response.header("Last-Modified", ::vlm_macro::web::http_date::format(modified));
+                                }
+                                if conditional::not_modified(&headers, &etag, modified) {
+                                    return Ok(response.status(StatusCode::NOT_MODIFIED).body(Body::empty()).unwrap());
+                                }
+                                let source = match encoding {
+                                    compress::VlmEncoding::Identity => source,
+                                    _ => match compressor.get(&etag, encoding, &source).await {
+                                        Ok(body) => {
+                                            response = response.header("Content-Encoding", encoding.token());
+                                            VlmSource::Memory(body)
+                                        }
+                                        Err(e) => {
+                                            let response = Response::builder()
+                                                .status(StatusCode::INTERNAL_SERVER_ERROR)
+                                                .header("Content-Type", "text/plain; charset=utf-8")
+                                                .body(Body::from(format!("Failed to compress content: {}", e)))
+                                                .unwrap();
+                                            return Ok(response);
+                                        }
+                                    },
+                                };
+                                let response = response.header("Content-Type", content_type);
+                                Ok(::vlm_macro::web::range::respond(response, source, &headers, &etag, modified))
                             }
                         }
                     });
 
-                let socket = SocketAddr::from((Ipv4Addr::from(final_addr.0), final_addr.1));
-
-                if tokio::runtime::Handle::try_current().is_ok() {
-                    ::std::thread::spawn(move || {
-                        let rt = tokio::runtime::Builder::new_multi_thread()
-                            .worker_threads(4)
-                            .enable_all()
-                            .build()
-                            .expect("Failed to create Tokio runtime");
-                        rt.block_on(async move {
-                            warp::serve(route).run(socket).await;
-                        });
-                    });
-                    Ok(())
-                } else {
-                    let rt = tokio::runtime::Builder::new_multi_thread()
-                        .worker_threads(4)
-                        .enable_all()
-                        .build()
-                        .map_err(|e| format!("Failed to create Tokio runtime: {}", e))?;
-                    rt.block_on(async move {
-                        warp::serve(route).run(socket).await;
-                    });
-                    Ok(())
-                }
+                // Without an API every request falls through to the static content.
+                let api = api.unwrap_or_else(|| {
+                    warp::any()
+                        .and_then(|| async { Err::<warp::reply::Response, _>(warp::reject::not_found()) })
+                        .boxed()
+                });
+
+                let websockets = options.websockets.clone();
+                let route = ::vlm_macro::web::metrics::route(metrics.clone())
+                    .or(websockets.filter())
+                    .unify()
+                    .or(api)
+                    .unify()
+                    .or(live_reload.or(static_files).map(warp::Reply::into_response))
+                    .unify()
+                    .or(::vlm_macro::web::proxy::route(backend.clone()))
+                    .unify()
+                    .boxed();
+                let route = ::vlm_macro::web::auth::protect(route, options.auth.clone());
+                let route = ::vlm_macro::web::access::instrument(route, options.access_log, metrics);
+
+                let closing = watcher.clone();
+                ::vlm_macro::web::server::VlmServer::spawn(route, final_addr, options.tls.as_ref(), move || {
+                    if let Some(watcher) = closing {
+                        watcher.close();
+                    }
+                    if let Some(backend) = backend {
+                        backend.stop();
+                    }
+                    async move { websockets.close().await }
+                })
             }
 
             fn transform_address(
                 addr: (::vlm_macro::web::VlmHost, ::vlm_macro::web::VlmPort),
                 mode: &::std::sync::Arc<Option<::vlm_macro::V>>
-            ) -> (::vlm_macro::web::VlmHost, ::vlm_macro::web::VlmPort) {
-                if let Some(_) = &**mode {
-                    (addr.0, 9090)
-                } else {
-                    addr
+            ) -> Result<(::vlm_macro::web::VlmHost, ::vlm_macro::web::VlmPort), Box<dyn ::std::error::Error>> {
+                match &**mode {
+                    Some(mode) => mode.bind_address(addr),
+                    None => Ok(addr),
                 }
             }
 
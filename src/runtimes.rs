@@ -1 +1,5 @@
+#[cfg(feature = "deno")]
 pub mod deno;
+pub mod lua;
+pub mod net;
+pub mod python;
@@ -0,0 +1,407 @@
+use std::{
+    borrow::Cow,
+    cell::RefCell,
+    error::Error,
+    fs, io,
+    path::{Path, PathBuf},
+    sync::mpsc::{self, RecvTimeoutError},
+    sync::Arc,
+    thread,
+    time::{Duration, Instant},
+};
+
+use deno_core::{error::JsError, op2, v8, Extension, JsRuntime, OpState, RuntimeOptions};
+use deno_error::JsErrorBox;
+use serde::Serialize;
+use vlm_macro::{
+    common::capabilities::{VlmCapability, VlmCapabilityCheck},
+    web::{VirtualEnv, VlmExecution, VlmExitStatus, VlmHost, VlmPort, VlmRunError},
+};
+
+use super::net;
+
+/// The bootstrap defining `console` and `vlmrunjs` on top of the ops below.
+const RUNTIME_JS: &str = include_str!("../../benchmarks/runtime.js");
+
+/// Globals `runtime.js` expects but bare `deno_core` does not provide.
+const PRELUDE_JS: &str = "globalThis.performance ??= { now: ((start) => () => Date.now() - start)(Date.now()) };";
+
+/// What `console` printed during one call.
+#[derive(Default)]
+struct Output {
+    stdout: String,
+    stderr: String,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct DirEntry {
+    name: String,
+    is_file: bool,
+    is_directory: bool,
+    is_symlink: bool,
+}
+
+/// An embedded V8 runtime running `benchmarks/runtime.js`.
+///
+/// Scripts share one global scope, so definitions persist from one `run_code`
+/// call to the next until [`VlmDenoRuntime::reset`]. Every file system and
+/// network op behind `vlmrunjs` is checked against `capabilities` first; a
+/// denial throws a `PermissionDenied` error scripts can catch.
+pub struct VlmDenoRuntime {
+    capabilities: Arc<dyn VlmCapabilityCheck>,
+    timeout: Option<Duration>,
+    runtime: RefCell<Option<JsRuntime>>,
+}
+
+impl VlmDenoRuntime {
+    pub fn new(capabilities: Arc<dyn VlmCapabilityCheck>) -> Self {
+        Self {
+            capabilities,
+            timeout: Some(Duration::from_secs(30)),
+            runtime: RefCell::new(None),
+        }
+    }
+
+    /// How long one `run_code` call may take before V8 terminates it.
+    pub fn timeout(mut self, timeout: Option<Duration>) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Drops the runtime and everything scripts defined in it.
+    pub fn reset(&self) {
+        self.runtime.borrow_mut().take();
+    }
+
+    fn create(&self) -> Result<JsRuntime, Box<dyn Error>> {
+        let capabilities = Arc::clone(&self.capabilities);
+        let extension = Extension {
+            name: "vlmrunjs",
+            ops: Cow::Owned(vec![
+                op_read_file(),
+                op_write_file(),
+                op_remove_file(),
+                op_read_dir(),
+                op_make_dir(),
+                op_remove_dir(),
+                op_read_link(),
+                op_tcp_request(),
+            ]),
+            // `console` goes through `Deno.core.print`, captured instead of written to stdout.
+            middleware_fn: Some(Box::new(|op| match op.name {
+                "op_print" => op.with_implementation_from(&op_vlm_print()),
+                _ => op,
+            })),
+            op_state_fn: Some(Box::new(move |state| {
+                state.put(capabilities);
+                state.put(Output::default());
+            })),
+            ..Default::default()
+        };
+        let mut runtime = JsRuntime::new(RuntimeOptions {
+            extensions: vec![extension],
+            ..Default::default()
+        });
+        runtime.execute_script("[vlm:prelude]", PRELUDE_JS)?;
+        runtime.execute_script("[vlm:runtime.js]", format!("({})(globalThis);", RUNTIME_JS.trim_end()))?;
+        Ok(runtime)
+    }
+
+    /// Runs `code`, terminating it once the timeout passes.
+    fn execute(&self, runtime: &mut JsRuntime, code: &str) -> Result<Option<serde_json::Value>, VlmRunError> {
+        let isolate = runtime.v8_isolate().thread_safe_handle();
+        let (done, finished) = mpsc::channel::<()>();
+        let watchdog = self.timeout.map(|timeout| {
+            thread::spawn(move || match finished.recv_timeout(timeout) {
+                Err(RecvTimeoutError::Timeout) => isolate.terminate_execution(),
+                _ => false,
+            })
+        });
+        let result = runtime.execute_script("run_code", code.to_string());
+        drop(done);
+        if watchdog.is_some_and(|watchdog| watchdog.join().unwrap_or(false)) {
+            runtime.v8_isolate().cancel_terminate_execution();
+            return Err(VlmRunError::Timeout {
+                limit: self.timeout.unwrap_or_default(),
+            });
+        }
+        let value = result.map_err(|e| run_error(&e))?;
+        deno_core::scope!(scope, runtime);
+        let value = v8::Local::new(scope, value);
+        if value.is_undefined() {
+            return Ok(None);
+        }
+        Ok(Some(
+            deno_core::serde_v8::from_v8::<serde_json::Value>(scope, value)
+                .unwrap_or_else(|_| serde_json::Value::String(value.to_rust_string_lossy(scope))),
+        ))
+    }
+}
+
+impl VirtualEnv for VlmDenoRuntime {
+    fn name(&self, _addr: (VlmHost, VlmPort)) -> &str {
+        "deno"
+    }
+
+    fn is_active(&self) -> Result<bool, Box<dyn Error>> {
+        Ok(self.runtime.borrow().is_some())
+    }
+
+    fn init(&self) -> Result<(), Box<dyn Error>> {
+        if self.runtime.borrow().is_none() {
+            let runtime = self.create()?;
+            *self.runtime.borrow_mut() = Some(runtime);
+        }
+        Ok(())
+    }
+
+    fn run_code(&self, code: &str) -> Result<VlmExecution, Box<dyn Error>> {
+        self.init()?;
+        let mut runtime = self.runtime.borrow_mut();
+        let runtime = runtime.as_mut().ok_or("JavaScript runtime is not initialized")?;
+        let started = Instant::now();
+        let (status, value) = match self.execute(runtime, code) {
+            Ok(value) => (VlmExitStatus::Success, value),
+            Err(error) => (VlmExitStatus::Failed { error }, None),
+        };
+        let output = std::mem::take(runtime.op_state().borrow_mut().borrow_mut::<Output>());
+        Ok(VlmExecution {
+            stdout: output.stdout,
+            stderr: output.stderr,
+            status,
+            value,
+            duration: started.elapsed(),
+        })
+    }
+}
+
+fn run_error(error: &JsError) -> VlmRunError {
+    let message = error.exception_message.trim_start_matches("Uncaught ").to_string();
+    match error.name.as_deref() {
+        Some("SyntaxError") if error.frames.is_empty() => VlmRunError::Syntax {
+            message,
+            line: error.stack.as_deref().and_then(script_line),
+        },
+        _ => VlmRunError::Runtime {
+            message,
+            traceback: error.stack.clone(),
+        },
+    }
+}
+
+/// The line of the first `run_code:<line>:<column>` location in `stack`.
+fn script_line(stack: &str) -> Option<u32> {
+    let (_, location) = stack.split_once("run_code:")?;
+    location.split(':').next()?.parse().ok()
+}
+
+/// `path` if the capabilities of the runtime allow `op` to use it.
+fn permitted(
+    state: &OpState,
+    op: &str,
+    capability: VlmCapability,
+    path: &str,
+    follow_links: bool,
+) -> Result<PathBuf, JsErrorBox> {
+    state
+        .borrow::<Arc<dyn VlmCapabilityCheck>>()
+        .check_path("deno", op, capability, path, follow_links)
+        .map_err(|e| JsErrorBox::new("PermissionDenied", e))
+}
+
+fn io_error(path: &Path, error: io::Error) -> JsErrorBox {
+    JsErrorBox::generic(format!("{}: {}", path.display(), error))
+}
+
+/// Replaces `op_print`, whose fast path would otherwise bypass the capture.
+#[op2(nofast)]
+fn op_vlm_print(state: &mut OpState, #[string] msg: &str, is_err: bool) {
+    let output = state.borrow_mut::<Output>();
+    match is_err {
+        true => output.stderr.push_str(msg),
+        false => output.stdout.push_str(msg),
+    }
+}
+
+#[op2]
+#[string]
+fn op_read_file(state: &mut OpState, #[string] path: &str) -> Result<String, JsErrorBox> {
+    let path = permitted(state, "op_read_file", VlmCapability::Read, path, true)?;
+    fs::read_to_string(&path).map_err(|e| io_error(&path, e))
+}
+
+#[op2(fast)]
+fn op_write_file(state: &mut OpState, #[string] path: &str, #[string] data: &str) -> Result<(), JsErrorBox> {
+    let path = permitted(state, "op_write_file", VlmCapability::Write, path, true)?;
+    fs::write(&path, data).map_err(|e| io_error(&path, e))
+}
+
+#[op2(fast)]
+fn op_remove_file(state: &mut OpState, #[string] path: &str) -> Result<(), JsErrorBox> {
+    let path = permitted(state, "op_remove_file", VlmCapability::Write, path, false)?;
+    fs::remove_file(&path).map_err(|e| io_error(&path, e))
+}
+
+#[op2]
+#[serde]
+fn op_read_dir(state: &mut OpState, #[string] path: &str) -> Result<Vec<DirEntry>, JsErrorBox> {
+    let path = permitted(state, "op_read_dir", VlmCapability::Read, path, true)?;
+    let mut entries = Vec::new();
+    for entry in fs::read_dir(&path).map_err(|e| io_error(&path, e))? {
+        let entry = entry.map_err(|e| io_error(&path, e))?;
+        let file_type = entry.file_type().map_err(|e| io_error(&entry.path(), e))?;
+        entries.push(DirEntry {
+            name: entry.file_name().to_string_lossy().into_owned(),
+            is_file: file_type.is_file(),
+            is_directory: file_type.is_dir(),
+            is_symlink: file_type.is_symlink(),
+        });
+    }
+    Ok(entries)
+}
+
+/// Creates the directory and any missing parents.
+#[op2(fast)]
+fn op_make_dir(state: &mut OpState, #[string] path: &str) -> Result<(), JsErrorBox> {
+    let path = permitted(state, "op_make_dir", VlmCapability::Write, path, true)?;
+    fs::create_dir_all(&path).map_err(|e| io_error(&path, e))
+}
+
+/// Removes the directory with its contents.
+#[op2(fast)]
+fn op_remove_dir(state: &mut OpState, #[string] path: &str) -> Result<(), JsErrorBox> {
+    let path = permitted(state, "op_remove_dir", VlmCapability::Write, path, false)?;
+    fs::remove_dir_all(&path).map_err(|e| io_error(&path, e))
+}
+
+#[op2]
+#[string]
+fn op_read_link(state: &mut OpState, #[string] path: &str) -> Result<String, JsErrorBox> {
+    let path = permitted(state, "op_read_link", VlmCapability::Read, path, false)?;
+    fs::read_link(&path)
+        .map(|target| target.to_string_lossy().into_owned())
+        .map_err(|e| io_error(&path, e))
+}
+
+/// Sends `data` to `address` (`host:port`) and returns what came back.
+#[op2]
+#[buffer]
+fn op_tcp_request(state: &mut OpState, #[string] address: &str, #[string] data: &str) -> Result<Vec<u8>, JsErrorBox> {
+    state
+        .borrow::<Arc<dyn VlmCapabilityCheck>>()
+        .check_host("deno", "op_tcp_request", address)
+        .map_err(|e| JsErrorBox::new("PermissionDenied", e))?;
+    net::tcp_request(address, data.as_bytes()).map_err(|e| JsErrorBox::generic(format!("{}: {}", address, e)))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+
+    use vlm_macro::common::capabilities::VlmCapabilities;
+
+    use super::*;
+
+    fn deno(capabilities: VlmCapabilities) -> VlmDenoRuntime {
+        VlmDenoRuntime::new(Arc::new(capabilities)).timeout(Some(Duration::from_secs(5)))
+    }
+
+    fn error(execution: &VlmExecution) -> Option<&VlmRunError> {
+        match &execution.status {
+            VlmExitStatus::Failed { error } => Some(error),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn file_ops_work_where_allowed() {
+        let dir = env::temp_dir().join(format!("vlm-deno-ops-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let deno = deno(VlmCapabilities::new().allow_read(&dir).allow_write(&dir));
+        let execution = deno
+            .run_code(&format!(
+                "const dir = {:?};
+                vlmrunjs.makeDir(dir + '/sub/deeper');
+                vlmrunjs.writeFile(dir + '/sub/a.txt', 'hello');
+                const read = vlmrunjs.readFile(dir + '/sub/a.txt');
+                const names = vlmrunjs.readDir(dir + '/sub').map((e) => e.name + (e.isDirectory ? '/' : '')).sort();
+                vlmrunjs.removeFile(dir + '/sub/a.txt');
+                vlmrunjs.removeDir(dir + '/sub');
+                [read, names, vlmrunjs.readDir(dir).length]",
+                dir.to_str().unwrap()
+            ))
+            .unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+        assert!(execution.is_success(), "{:?}", execution.status);
+        assert_eq!(execution.value, Some(serde_json::json!(["hello", ["a.txt", "deeper/"], 0])));
+    }
+
+    #[test]
+    fn denied_ops_throw_permission_denied() {
+        let dir = env::temp_dir().join(format!("vlm-deno-denied-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("secret"), "secret").unwrap();
+        let secret = dir.join("secret");
+        let deno = deno(VlmCapabilities::new().allow_read(&dir).deny_path(VlmCapability::Read, &secret));
+
+        let caught = deno
+            .run_code(&format!(
+                "const caught = [];
+                for (const op of [() => vlmrunjs.readFile({0:?}), () => vlmrunjs.writeFile({0:?}, 'x'),
+                                  () => vlmrunjs.tcpRequest('example.com:80')]) {{
+                    try {{ op(); }} catch (e) {{ caught.push(String(e)); }}
+                }}
+                caught",
+                secret.to_str().unwrap()
+            ))
+            .unwrap();
+        let uncaught = deno.run_code(&format!("vlmrunjs.readFile({:?})", secret.to_str().unwrap())).unwrap();
+        let unchanged = fs::read_to_string(&secret).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+
+        let caught = caught.value.unwrap();
+        let caught: Vec<&str> = caught.as_array().unwrap().iter().map(|e| e.as_str().unwrap()).collect();
+        assert_eq!(caught.len(), 3, "{:?}", caught);
+        assert!(caught[0].contains(&format!("Requires read access to \"{}\"", secret.display())), "{}", caught[0]);
+        assert!(caught[1].contains("Requires write access"), "{}", caught[1]);
+        assert!(caught[2].contains("Requires net access to \"example.com:80\""), "{}", caught[2]);
+        assert_eq!(unchanged, "secret");
+        assert!(
+            matches!(error(&uncaught), Some(VlmRunError::Runtime { message, .. }) if message.contains("Requires read access")),
+            "{:?}",
+            uncaught.status
+        );
+    }
+
+    #[test]
+    fn runaway_scripts_time_out_and_the_runtime_recovers() {
+        let deno = deno(VlmCapabilities::new()).timeout(Some(Duration::from_millis(200)));
+        let execution = deno.run_code("globalThis.kept = 1; while (true) {}").unwrap();
+        assert_eq!(
+            error(&execution),
+            Some(&VlmRunError::Timeout {
+                limit: Duration::from_millis(200)
+            })
+        );
+        let execution = deno.run_code("kept + 1").unwrap();
+        assert!(execution.is_success(), "{:?}", execution.status);
+        assert_eq!(execution.value, Some(serde_json::json!(2)));
+    }
+
+    #[test]
+    fn console_output_is_captured_per_call() {
+        let deno = deno(VlmCapabilities::new());
+        let execution = deno
+            .run_code("console.log('a', {b: 1}); console.error('oops'); 'done'")
+            .unwrap();
+        assert_eq!(execution.stdout, "a {\"b\":1}\n");
+        assert_eq!(execution.stderr, "oops\n");
+        assert_eq!(execution.value, Some(serde_json::json!("done")));
+
+        let execution = deno.run_code("console.log('again')").unwrap();
+        assert_eq!(execution.stdout, "again\n");
+        assert_eq!(execution.stderr, "");
+        assert_eq!(execution.value, None);
+    }
+}
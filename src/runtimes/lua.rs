@@ -0,0 +1,557 @@
+use std::{
+    cell::{Cell, RefCell},
+    error::Error,
+    fs, io,
+    path::{Path, PathBuf},
+    rc::Rc,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use mlua::{HookTriggers, Lua, LuaOptions, MultiValue, StdLib, Value, Variadic};
+use vlm_macro::{
+    common::capabilities::{VlmCapabilities, VlmCapability, VlmCapabilityCheck},
+    web::{VirtualEnv, VlmExecution, VlmExitStatus, VlmHost, VlmPort, VlmRunError},
+};
+
+use super::net;
+
+/// Instructions run between two checks of the instruction and time limits.
+const HOOK_INTERVAL: u32 = 1000;
+
+/// How deep nested tables are followed when converting a result to JSON.
+const MAX_VALUE_DEPTH: usize = 32;
+
+/// Libraries scripts get. `io`, `package` and `debug` are left out, and `os`
+/// is trimmed down to its clock functions.
+fn sandbox_libs() -> StdLib {
+    StdLib::COROUTINE | StdLib::TABLE | StdLib::STRING | StdLib::UTF8 | StdLib::MATH | StdLib::OS
+}
+
+/// Base functions able to reach the file system or bypass the sandbox.
+const REMOVED_GLOBALS: &[&str] = &["dofile", "loadfile", "load", "require", "collectgarbage"];
+
+/// The `os` functions kept in the sandbox.
+const OS_FUNCTIONS: &[&str] = &["clock", "date", "difftime", "time"];
+
+/// Wraps the functions able to catch errors so they re-raise once a limit
+/// tripped; otherwise `pcall` in a loop would swallow the hook's error forever.
+/// Written in Lua so coroutines can still yield across them.
+const GUARD_PROTECTED_CALLS: &str = r#"
+local check, pcall, xpcall, resume = ...
+local function checked(...)
+    check()
+    return ...
+end
+return function(...) return checked(pcall(...)) end,
+    function(...) return checked(xpcall(...)) end,
+    function(...) return checked(resume(...)) end
+"#;
+
+/// Bounds on what a single `run_code` call may use.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct VlmLuaLimits {
+    /// VM instructions per call, checked every [`HOOK_INTERVAL`] instructions.
+    pub max_instructions: Option<u64>,
+    /// Bytes the whole Lua state may allocate.
+    pub max_memory: Option<usize>,
+    /// Wall-clock time per call, checked alongside the instruction limit.
+    pub timeout: Option<Duration>,
+}
+
+impl Default for VlmLuaLimits {
+    fn default() -> Self {
+        Self {
+            max_instructions: Some(100_000_000),
+            max_memory: Some(64 * 1024 * 1024),
+            timeout: Some(Duration::from_secs(30)),
+        }
+    }
+}
+
+/// The limit the hook stopped a call for.
+#[derive(Debug, Clone, Copy)]
+enum Tripped {
+    Instructions(u64),
+    Timeout(Duration),
+}
+
+/// Per-call state shared with the hook and the output functions.
+#[derive(Default)]
+struct Run {
+    stdout: RefCell<String>,
+    stderr: RefCell<String>,
+    instructions: Cell<u64>,
+    started: Cell<Option<Instant>>,
+    tripped: Cell<Option<Tripped>>,
+}
+
+/// An embedded Lua 5.4 environment.
+///
+/// Globals persist from one `run_code` call to the next; [`VlmLua::reset`]
+/// starts over with a fresh state. `print` and `warn` write to the stdout and
+/// stderr of the returned [`VlmExecution`], and values returned by the chunk
+/// become its JSON `value`. The `vlm` table offers the file system ops of the
+/// JavaScript runtime and `tcp_request`, each checked against the capabilities
+/// first; denials raise errors scripts can catch with `pcall`.
+pub struct VlmLua {
+    limits: VlmLuaLimits,
+    capabilities: Arc<dyn VlmCapabilityCheck>,
+    lua: RefCell<Option<Lua>>,
+    run: Rc<Run>,
+}
+
+impl Default for VlmLua {
+    fn default() -> Self {
+        Self::new(VlmLuaLimits::default())
+    }
+}
+
+impl VlmLua {
+    pub fn new(limits: VlmLuaLimits) -> Self {
+        Self {
+            limits,
+            capabilities: Arc::new(VlmCapabilities::new()),
+            lua: RefCell::new(None),
+            run: Rc::new(Run::default()),
+        }
+    }
+
+    /// What the `vlm` ops may touch. Nothing by default.
+    pub fn capabilities(mut self, capabilities: Arc<dyn VlmCapabilityCheck>) -> Self {
+        self.capabilities = capabilities;
+        self
+    }
+
+    pub fn limits(&self) -> VlmLuaLimits {
+        self.limits
+    }
+
+    /// Drops the state and everything scripts defined in it.
+    pub fn reset(&self) {
+        self.lua.borrow_mut().take();
+    }
+
+    fn create(&self) -> Result<Lua, Box<dyn Error>> {
+        let lua = Lua::new_with(sandbox_libs(), LuaOptions::default())?;
+        if let Some(max_memory) = self.limits.max_memory {
+            lua.set_memory_limit(max_memory)?;
+        }
+        if self.limits.max_instructions.is_some() || self.limits.timeout.is_some() {
+            let run = Rc::clone(&self.run);
+            let limits = self.limits;
+            lua.set_hook(
+                HookTriggers {
+                    every_nth_instruction: Some(HOOK_INTERVAL),
+                    ..Default::default()
+                },
+                move |_, _| {
+                    run.instructions.set(run.instructions.get() + u64::from(HOOK_INTERVAL));
+                    let tripped = match (limits.max_instructions, limits.timeout, run.started.get()) {
+                        (Some(max), _, _) if run.instructions.get() > max => Some(Tripped::Instructions(max)),
+                        (_, Some(timeout), Some(started)) if started.elapsed() > timeout => {
+                            Some(Tripped::Timeout(timeout))
+                        }
+                        _ => None,
+                    };
+                    match tripped {
+                        Some(tripped) => {
+                            run.tripped.set(Some(tripped));
+                            Err(mlua::Error::RuntimeError("Lua limit exceeded".to_string()))
+                        }
+                        None => Ok(()),
+                    }
+                },
+            )?;
+        }
+        self.sandbox(&lua)?;
+        Ok(lua)
+    }
+
+    /// Strips unsafe globals, guards `pcall` and friends, and routes `print` and
+    /// `warn` to the captured output.
+    fn sandbox(&self, lua: &Lua) -> mlua::Result<()> {
+        let globals = lua.globals();
+        for name in REMOVED_GLOBALS {
+            globals.set(*name, Value::Nil)?;
+        }
+        let os = lua.create_table()?;
+        let full_os: mlua::Table = globals.get("os")?;
+        for name in OS_FUNCTIONS {
+            os.set(*name, full_os.get::<_, Value>(*name)?)?;
+        }
+        globals.set("os", os)?;
+
+        let run = Rc::clone(&self.run);
+        let check = lua.create_function(move |_, ()| match run.tripped.get() {
+            Some(_) => Err(mlua::Error::RuntimeError("Lua limit exceeded".to_string())),
+            None => Ok(()),
+        })?;
+        let coroutine: mlua::Table = globals.get("coroutine")?;
+        let (pcall, xpcall, resume): (mlua::Function, mlua::Function, mlua::Function) =
+            lua.load(GUARD_PROTECTED_CALLS).set_name("[vlm:sandbox]")?.call((
+                check,
+                globals.get::<_, mlua::Function>("pcall")?,
+                globals.get::<_, mlua::Function>("xpcall")?,
+                coroutine.get::<_, mlua::Function>("resume")?,
+            ))?;
+        globals.set("pcall", pcall)?;
+        globals.set("xpcall", xpcall)?;
+        coroutine.set("resume", resume)?;
+
+        let run = Rc::clone(&self.run);
+        let print = lua.create_function(move |lua, values: MultiValue| {
+            let tostring: mlua::Function = lua.globals().get("tostring")?;
+            let mut line = Vec::new();
+            for value in values {
+                line.push(tostring.call::<_, String>(value)?);
+            }
+            let mut stdout = run.stdout.borrow_mut();
+            stdout.push_str(&line.join("\t"));
+            stdout.push('\n');
+            Ok(())
+        })?;
+        globals.set("print", print)?;
+
+        let run = Rc::clone(&self.run);
+        let warn = lua.create_function(move |_, parts: Variadic<String>| {
+            let mut stderr = run.stderr.borrow_mut();
+            stderr.push_str(&parts.concat());
+            stderr.push('\n');
+            Ok(())
+        })?;
+        globals.set("warn", warn)?;
+        self.ops(lua)
+    }
+
+    /// The `vlm` table of file system and network ops.
+    fn ops(&self, lua: &Lua) -> mlua::Result<()> {
+        let vlm = lua.create_table()?;
+        let check = |op: &'static str, capability: VlmCapability, follow_links: bool| {
+            let capabilities = Arc::clone(&self.capabilities);
+            move |path: &str| -> mlua::Result<PathBuf> {
+                capabilities
+                    .check_path("lua", op, capability, path, follow_links)
+                    .map_err(|e| mlua::Error::RuntimeError(format!("PermissionDenied: {}", e)))
+            }
+        };
+
+        let permitted = check("read_file", VlmCapability::Read, true);
+        vlm.set(
+            "read_file",
+            lua.create_function(move |_, path: String| {
+                let path = permitted(&path)?;
+                fs::read_to_string(&path).map_err(|e| io_error(&path, e))
+            })?,
+        )?;
+        let permitted = check("write_file", VlmCapability::Write, true);
+        vlm.set(
+            "write_file",
+            lua.create_function(move |_, (path, data): (String, mlua::String)| {
+                let path = permitted(&path)?;
+                fs::write(&path, data.as_bytes()).map_err(|e| io_error(&path, e))
+            })?,
+        )?;
+        let permitted = check("remove_file", VlmCapability::Write, false);
+        vlm.set(
+            "remove_file",
+            lua.create_function(move |_, path: String| {
+                let path = permitted(&path)?;
+                fs::remove_file(&path).map_err(|e| io_error(&path, e))
+            })?,
+        )?;
+        let permitted = check("read_dir", VlmCapability::Read, true);
+        vlm.set(
+            "read_dir",
+            lua.create_function(move |lua, path: String| {
+                let path = permitted(&path)?;
+                let entries = lua.create_table()?;
+                for entry in fs::read_dir(&path).map_err(|e| io_error(&path, e))? {
+                    let entry = entry.map_err(|e| io_error(&path, e))?;
+                    let file_type = entry.file_type().map_err(|e| io_error(&entry.path(), e))?;
+                    let item = lua.create_table()?;
+                    item.set("name", entry.file_name().to_string_lossy().into_owned())?;
+                    item.set("is_file", file_type.is_file())?;
+                    item.set("is_directory", file_type.is_dir())?;
+                    item.set("is_symlink", file_type.is_symlink())?;
+                    entries.set(entries.raw_len() + 1, item)?;
+                }
+                Ok(entries)
+            })?,
+        )?;
+        let permitted = check("make_dir", VlmCapability::Write, true);
+        vlm.set(
+            "make_dir",
+            lua.create_function(move |_, path: String| {
+                let path = permitted(&path)?;
+                fs::create_dir_all(&path).map_err(|e| io_error(&path, e))
+            })?,
+        )?;
+        let permitted = check("remove_dir", VlmCapability::Write, false);
+        vlm.set(
+            "remove_dir",
+            lua.create_function(move |_, path: String| {
+                let path = permitted(&path)?;
+                fs::remove_dir_all(&path).map_err(|e| io_error(&path, e))
+            })?,
+        )?;
+        let permitted = check("read_link", VlmCapability::Read, false);
+        vlm.set(
+            "read_link",
+            lua.create_function(move |_, path: String| {
+                let path = permitted(&path)?;
+                fs::read_link(&path)
+                    .map(|target| target.to_string_lossy().into_owned())
+                    .map_err(|e| io_error(&path, e))
+            })?,
+        )?;
+        let capabilities = Arc::clone(&self.capabilities);
+        vlm.set(
+            "tcp_request",
+            lua.create_function(move |lua, (address, data): (String, Option<mlua::String>)| {
+                capabilities
+                    .check_host("lua", "tcp_request", &address)
+                    .map_err(|e| mlua::Error::RuntimeError(format!("PermissionDenied: {}", e)))?;
+                let data = data.as_ref().map(mlua::String::as_bytes).unwrap_or_default();
+                let response = net::tcp_request(&address, data)
+                    .map_err(|e| mlua::Error::RuntimeError(format!("{}: {}", address, e)))?;
+                lua.create_string(&response)
+            })?,
+        )?;
+        lua.globals().set("vlm", vlm)
+    }
+
+    /// Maps a failed call to the error it reports.
+    fn run_error(&self, error: mlua::Error) -> VlmRunError {
+        match (self.run.tripped.take(), root_cause(error)) {
+            (Some(Tripped::Timeout(limit)), _) => VlmRunError::Timeout { limit },
+            (Some(Tripped::Instructions(max)), _) => VlmRunError::ResourceLimit {
+                message: format!("Lua instruction limit of {} exceeded", max),
+            },
+            (None, mlua::Error::MemoryError(_)) => VlmRunError::ResourceLimit {
+                message: format!(
+                    "Lua memory limit of {} bytes exceeded",
+                    self.limits.max_memory.unwrap_or_default()
+                ),
+            },
+            (None, mlua::Error::SyntaxError { message, .. }) => {
+                let (line, message) = split_location(&message);
+                VlmRunError::Syntax { message, line }
+            }
+            (None, mlua::Error::RuntimeError(message)) => {
+                let (message, traceback) = match message.split_once("\nstack traceback:\n") {
+                    Some((message, traceback)) => (message.to_string(), Some(traceback.to_string())),
+                    None => (message, None),
+                };
+                VlmRunError::Runtime { message, traceback }
+            }
+            (None, error) => VlmRunError::Runtime {
+                message: error.to_string(),
+                traceback: None,
+            },
+        }
+    }
+}
+
+impl VirtualEnv for VlmLua {
+    fn name(&self, _addr: (VlmHost, VlmPort)) -> &str {
+        "lua"
+    }
+
+    fn is_active(&self) -> Result<bool, Box<dyn Error>> {
+        Ok(self.lua.borrow().is_some())
+    }
+
+    fn init(&self) -> Result<(), Box<dyn Error>> {
+        if self.lua.borrow().is_none() {
+            let lua = self.create()?;
+            *self.lua.borrow_mut() = Some(lua);
+        }
+        Ok(())
+    }
+
+    fn run_code(&self, code: &str) -> Result<VlmExecution, Box<dyn Error>> {
+        self.init()?;
+        let lua = self.lua.borrow();
+        let lua = lua.as_ref().ok_or("Lua environment is not initialized")?;
+        let started = Instant::now();
+        self.run.stdout.borrow_mut().clear();
+        self.run.stderr.borrow_mut().clear();
+        self.run.instructions.set(0);
+        self.run.tripped.set(None);
+        self.run.started.set(Some(started));
+        let result = lua
+            .load(code)
+            .set_name("run_code")?
+            .eval::<MultiValue>()
+            .map(|values| returned_value(values.into_vec()));
+        self.run.started.set(None);
+        let (status, value) = match result {
+            Ok(value) => (VlmExitStatus::Success, value),
+            Err(e) => (
+                VlmExitStatus::Failed {
+                    error: self.run_error(e),
+                },
+                None,
+            ),
+        };
+        Ok(VlmExecution {
+            stdout: self.run.stdout.take(),
+            stderr: self.run.stderr.take(),
+            status,
+            value,
+            duration: started.elapsed(),
+        })
+    }
+}
+
+fn io_error(path: &Path, error: io::Error) -> mlua::Error {
+    mlua::Error::RuntimeError(format!("{}: {}", path.display(), error))
+}
+
+/// The error a Rust callback, such as the instruction hook, raised.
+fn root_cause(error: mlua::Error) -> mlua::Error {
+    match error {
+        mlua::Error::CallbackError { cause, .. } => root_cause(cause.as_ref().clone()),
+        error => error,
+    }
+}
+
+/// Splits `[string "run_code"]:3: message` into the line and the message.
+fn split_location(message: &str) -> (Option<u32>, String) {
+    let location = message.split_once("]:").and_then(|(_, rest)| rest.split_once(": "));
+    match location.and_then(|(line, rest)| Some((line.parse().ok()?, rest))) {
+        Some((line, rest)) => (Some(line), rest.to_string()),
+        None => (None, message.to_string()),
+    }
+}
+
+/// Nothing for no values, the value itself for one, an array for several.
+fn returned_value(mut values: Vec<Value>) -> Option<serde_json::Value> {
+    match values.len() {
+        0 => None,
+        1 => Some(to_json(values.remove(0), 0)),
+        _ => Some(serde_json::Value::Array(
+            values.into_iter().map(|value| to_json(value, 0)).collect(),
+        )),
+    }
+}
+
+/// Sequences become arrays and other tables objects keyed by their string
+/// form. Values JSON has no equivalent for are named by their Lua type.
+fn to_json(value: Value, depth: usize) -> serde_json::Value {
+    match value {
+        Value::Nil => serde_json::Value::Null,
+        Value::Boolean(b) => serde_json::Value::Bool(b),
+        Value::Integer(i) => serde_json::Value::from(i),
+        Value::Number(n) => serde_json::Number::from_f64(n)
+            .map(serde_json::Value::Number)
+            .unwrap_or(serde_json::Value::Null),
+        Value::String(s) => serde_json::Value::String(s.to_string_lossy().into_owned()),
+        Value::Table(table) if depth < MAX_VALUE_DEPTH => {
+            let pairs: Vec<(Value, Value)> = table.clone().pairs().filter_map(Result::ok).collect();
+            let len = table.raw_len();
+            if len > 0 && pairs.len() as i64 == len {
+                return serde_json::Value::Array(
+                    table
+                        .sequence_values::<Value>()
+                        .filter_map(Result::ok)
+                        .map(|value| to_json(value, depth + 1))
+                        .collect(),
+                );
+            }
+            let object = pairs.into_iter().map(|(key, value)| {
+                let key = match key {
+                    Value::String(s) => s.to_string_lossy().into_owned(),
+                    Value::Integer(i) => i.to_string(),
+                    Value::Number(n) => n.to_string(),
+                    Value::Boolean(b) => b.to_string(),
+                    key => format!("<{}>", key.type_name()),
+                };
+                (key, to_json(value, depth + 1))
+            });
+            serde_json::Value::Object(object.collect())
+        }
+        Value::Error(error) => serde_json::Value::String(root_cause(error).to_string()),
+        value => serde_json::Value::String(format!("<{}>", value.type_name())),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn limited() -> VlmLua {
+        VlmLua::new(VlmLuaLimits {
+            max_instructions: Some(100_000),
+            max_memory: None,
+            timeout: Some(Duration::from_secs(5)),
+        })
+    }
+
+    fn error(execution: &VlmExecution) -> Option<&VlmRunError> {
+        match &execution.status {
+            VlmExitStatus::Failed { error } => Some(error),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn pcall_cannot_swallow_limits() {
+        let lua = limited();
+        for code in [
+            "while true do pcall(function() while true do end end) end",
+            "while true do xpcall(function() while true do end end, function(e) return e end) end",
+            "while true do pcall(pcall, function() while true do end end) end",
+            "while true do coroutine.resume(coroutine.create(function() while true do end end)) end",
+        ] {
+            let execution = lua.run_code(code).unwrap();
+            assert!(
+                matches!(error(&execution), Some(VlmRunError::ResourceLimit { .. })),
+                "{}: {:?}",
+                code,
+                execution.status
+            );
+        }
+    }
+
+    #[test]
+    fn protected_calls_still_work() {
+        let lua = limited();
+        let execution = lua
+            .run_code(
+                "local ok, e = pcall(error, 'boom')
+                local co = coroutine.wrap(function() pcall(coroutine.yield, 1) return 2 end)
+                return ok, e, co(), co()",
+            )
+            .unwrap();
+        assert!(execution.is_success(), "{:?}", execution.status);
+        assert_eq!(execution.value, Some(serde_json::json!([false, "boom", 1, 2])));
+        // The next call starts with fresh counters.
+        assert_eq!(lua.run_code("return 1").unwrap().value, Some(serde_json::json!(1)));
+    }
+
+    #[test]
+    fn tcp_request_needs_net_access() {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        let server = std::thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut request = String::new();
+            io::Read::read_to_string(&mut stream, &mut request).unwrap();
+            io::Write::write_all(&mut stream, request.to_uppercase().as_bytes()).unwrap();
+        });
+        let lua = limited().capabilities(Arc::new(VlmCapabilities::new().allow_net(&address)));
+        let execution = lua
+            .run_code(&format!(
+                "local ok, e = pcall(vlm.tcp_request, 'example.com:80', '')
+                return vlm.tcp_request('{}', 'ping'), e",
+                address
+            ))
+            .unwrap();
+        server.join().unwrap();
+        let value = execution.value.unwrap();
+        assert_eq!(value[0], "PING");
+        assert!(value[1].as_str().unwrap().contains("PermissionDenied"), "{}", value);
+    }
+}
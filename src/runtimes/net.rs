@@ -0,0 +1,37 @@
+use std::{
+    io::{self, Read, Write},
+    net::{Shutdown, TcpStream, ToSocketAddrs},
+    time::Duration,
+};
+
+/// How long connecting, and then each read or write, may take.
+pub const NET_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Responses longer than this are cut off.
+pub const MAX_RESPONSE: u64 = 16 * 1024 * 1024;
+
+/// Sends `data` to `address` (`host:port`) over TCP, closes the sending half and
+/// returns everything read back until the peer closes the connection.
+///
+/// Callers check `address` with `check_host` first; it is connected to as given.
+pub fn tcp_request(address: &str, data: &[u8]) -> io::Result<Vec<u8>> {
+    let mut last = io::Error::new(
+        io::ErrorKind::InvalidInput,
+        format!("{} resolves to no address", address),
+    );
+    for addr in address.to_socket_addrs()? {
+        match TcpStream::connect_timeout(&addr, NET_TIMEOUT) {
+            Ok(mut stream) => {
+                stream.set_read_timeout(Some(NET_TIMEOUT))?;
+                stream.set_write_timeout(Some(NET_TIMEOUT))?;
+                stream.write_all(data)?;
+                stream.shutdown(Shutdown::Write)?;
+                let mut response = Vec::new();
+                stream.take(MAX_RESPONSE).read_to_end(&mut response)?;
+                return Ok(response);
+            }
+            Err(e) => last = e,
+        }
+    }
+    Err(last)
+}
@@ -0,0 +1,723 @@
+use std::{
+    cell::RefCell,
+    env,
+    error::Error,
+    fs,
+    io::{BufRead, BufReader, Read, Write},
+    path::{Path, PathBuf},
+    process::{Child, ChildStdin, Command, Stdio},
+    sync::mpsc::{self, Receiver, RecvTimeoutError},
+    sync::{Arc, Mutex},
+    thread,
+    time::{Duration, Instant},
+};
+
+use serde::Deserialize;
+use vlm_macro::{
+    common::capabilities::{VlmCapabilities, VlmCapability, VlmCapabilityCheck},
+    web::{VirtualEnv, VlmExecution, VlmExitStatus, VlmHost, VlmPort, VlmRunError},
+};
+
+/// Environment variable naming the interpreter to create the venv with.
+pub const PYTHON_ENV: &str = "VLM_PYTHON";
+
+/// Where venvs live unless [`VlmPython::new`] is given another directory.
+pub const DEFAULT_CACHE_DIR: &str = ".vlm/python";
+
+/// The REPL run inside the venv, see the protocol described at its top.
+const REPL: &str = include_str!("python_repl.py");
+
+/// Records what was installed, so unchanged requirements are not reinstalled.
+const INSTALLED_MARKER: &str = ".vlm-installed";
+
+/// A response of `python_repl.py`.
+#[derive(Debug, Deserialize)]
+struct Response {
+    status: String,
+    value: Option<serde_json::Value>,
+    message: Option<String>,
+    line: Option<u32>,
+    traceback: Option<String>,
+    code: Option<i32>,
+}
+
+/// An access the audit hook of `python_repl.py` asks the capabilities about.
+#[derive(Debug, Deserialize)]
+struct Check {
+    id: String,
+    op: String,
+    capability: String,
+    path: Option<String>,
+    /// The working directory relative paths are resolved against.
+    cwd: Option<String>,
+    host: Option<String>,
+    #[serde(default)]
+    follow: bool,
+}
+
+/// Text a snippet printed, sent by `python_repl.py` as it is written.
+#[derive(Debug, Deserialize)]
+struct Printed {
+    output: String,
+    text: String,
+}
+
+/// What a snippet printed so far.
+#[derive(Debug, Default)]
+struct Output {
+    stdout: String,
+    stderr: String,
+}
+
+#[derive(Deserialize)]
+struct CheckRequest {
+    check: Check,
+}
+
+/// The first line of `python_repl.py`: the interpreter's own directories.
+#[derive(Deserialize)]
+struct Trusted {
+    trusted: Vec<PathBuf>,
+}
+
+/// A running `python_repl.py`.
+struct Repl {
+    child: Child,
+    stdin: Arc<Mutex<ChildStdin>>,
+    responses: Receiver<String>,
+}
+
+impl Drop for Repl {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+/// A Python venv with a persistent interpreter.
+///
+/// The venv is created under the cache directory with a local `python3`, or
+/// the interpreter named by [`PYTHON_ENV`], and reused afterwards. Packages
+/// come from a requirements file and/or a wheelhouse directory, always with
+/// `--no-index` so nothing is downloaded; requirements files naming URLs, VCS
+/// checkouts, indexes or other requirements files are refused. Code runs in one long-lived
+/// interpreter, so variables and imports persist from one `run_code` call to
+/// the next until [`VlmPython::reset`] or a timeout restarts it. Output is
+/// collected as it is printed, so it survives a snippet that kills the
+/// interpreter.
+///
+/// An audit hook checks file, socket and DNS access against the capabilities,
+/// raising `PermissionError` on denial; reading the interpreter's own files is
+/// always allowed. Spawning processes and loading native libraries through
+/// `ctypes` are refused outright. Audit hooks guard against scripts using the
+/// standard library, not against native extension modules working around them.
+pub struct VlmPython {
+    cache_dir: PathBuf,
+    interpreter: Option<PathBuf>,
+    requirements: Option<PathBuf>,
+    wheelhouse: Option<PathBuf>,
+    timeout: Option<Duration>,
+    capabilities: Arc<dyn VlmCapabilityCheck>,
+    repl: RefCell<Option<Repl>>,
+}
+
+impl Default for VlmPython {
+    fn default() -> Self {
+        Self::new(DEFAULT_CACHE_DIR)
+    }
+}
+
+impl VlmPython {
+    pub fn new(cache_dir: impl Into<PathBuf>) -> Self {
+        Self {
+            cache_dir: cache_dir.into(),
+            interpreter: None,
+            requirements: None,
+            wheelhouse: None,
+            timeout: Some(Duration::from_secs(30)),
+            capabilities: Arc::new(VlmCapabilities::new()),
+            repl: RefCell::new(None),
+        }
+    }
+
+    /// The interpreter to create the venv with, instead of searching for one.
+    pub fn interpreter(mut self, path: impl Into<PathBuf>) -> Self {
+        self.interpreter = Some(path.into());
+        self
+    }
+
+    /// A `requirements.txt` to install, resolved against the wheelhouse if any.
+    pub fn requirements(mut self, path: impl Into<PathBuf>) -> Self {
+        self.requirements = Some(path.into());
+        self
+    }
+
+    /// A directory of wheels. Without requirements, every wheel in it is installed.
+    pub fn wheelhouse(mut self, path: impl Into<PathBuf>) -> Self {
+        self.wheelhouse = Some(path.into());
+        self
+    }
+
+    /// How long one `run_code` call may take before the interpreter is killed.
+    pub fn timeout(mut self, timeout: Option<Duration>) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// What snippets may touch. Nothing but the interpreter's own files by default.
+    pub fn capabilities(mut self, capabilities: Arc<dyn VlmCapabilityCheck>) -> Self {
+        self.capabilities = capabilities;
+        self
+    }
+
+    pub fn venv_dir(&self) -> PathBuf {
+        self.cache_dir.join("venv")
+    }
+
+    /// The interpreter inside the venv.
+    pub fn venv_python(&self) -> PathBuf {
+        match cfg!(windows) {
+            true => self.venv_dir().join("Scripts").join("python.exe"),
+            false => self.venv_dir().join("bin").join("python"),
+        }
+    }
+
+    /// Stops the interpreter, dropping every variable. The venv is kept.
+    pub fn reset(&self) {
+        self.repl.borrow_mut().take();
+    }
+
+    /// Finds `python3`, or `python` when it is Python 3, on the `PATH`.
+    pub fn find_python() -> Result<PathBuf, String> {
+        if let Some(python) = env::var_os(PYTHON_ENV).filter(|python| !python.is_empty()) {
+            return Ok(PathBuf::from(python));
+        }
+        let paths = env::var_os("PATH").unwrap_or_default();
+        env::split_paths(&paths)
+            .flat_map(|dir| ["python3", "python"].map(|name| dir.join(name).with_extension(env::consts::EXE_EXTENSION)))
+            .find(|candidate| {
+                candidate.is_file()
+                    && Command::new(candidate)
+                        .args(["-c", "import sys; sys.exit(sys.version_info < (3, 8))"])
+                        .stdout(Stdio::null())
+                        .stderr(Stdio::null())
+                        .status()
+                        .is_ok_and(|status| status.success())
+            })
+            .ok_or_else(|| format!("No Python 3.8+ interpreter found on PATH; set {}", PYTHON_ENV))
+    }
+
+    /// Creates the venv unless it already exists.
+    fn ensure_venv(&self) -> Result<(), Box<dyn Error>> {
+        if self.venv_python().is_file() {
+            return Ok(());
+        }
+        let python = match &self.interpreter {
+            Some(python) => python.clone(),
+            None => Self::find_python()?,
+        };
+        let venv = self.venv_dir();
+        fs::create_dir_all(&self.cache_dir)
+            .map_err(|e| format!("Failed to create {}: {}", self.cache_dir.display(), e))?;
+        log::info!(target: "vlm::python", "Creating venv {} with {}", venv.display(), python.display());
+        run(Command::new(&python).arg("-m").arg("venv").arg(&venv), "python -m venv")
+    }
+
+    /// Installs the requirements and wheelhouse unless they are already installed.
+    fn install(&self) -> Result<(), Box<dyn Error>> {
+        let mut wanted = String::new();
+        if let Some(requirements) = &self.requirements {
+            let contents = fs::read_to_string(requirements)
+                .map_err(|e| format!("Failed to read {}: {}", requirements.display(), e))?;
+            check_requirements(requirements, &contents)?;
+            wanted += &contents;
+        }
+        let wheels = match &self.wheelhouse {
+            Some(wheelhouse) => wheels(wheelhouse)?,
+            None => Vec::new(),
+        };
+        for wheel in &wheels {
+            wanted += &format!("\n{}", wheel.display());
+        }
+        if wanted.is_empty() {
+            return Ok(());
+        }
+        let marker = self.venv_dir().join(INSTALLED_MARKER);
+        if fs::read_to_string(&marker).is_ok_and(|installed| installed == wanted) {
+            return Ok(());
+        }
+
+        let mut pip = Command::new(self.venv_python());
+        pip.args(["-m", "pip", "install", "--no-index", "--disable-pip-version-check"]);
+        match (&self.requirements, &self.wheelhouse) {
+            (Some(requirements), wheelhouse) => {
+                pip.arg("-r").arg(requirements);
+                if let Some(wheelhouse) = wheelhouse {
+                    pip.arg("--find-links").arg(wheelhouse);
+                }
+            }
+            (None, Some(wheelhouse)) => {
+                pip.arg("--find-links").arg(wheelhouse).args(&wheels);
+            }
+            (None, None) => {}
+        }
+        log::info!(target: "vlm::python", "Installing packages into {}", self.venv_dir().display());
+        run(&mut pip, "pip install")?;
+        fs::write(&marker, wanted).map_err(|e| format!("Failed to write {}: {}", marker.display(), e).into())
+    }
+
+    fn spawn(&self) -> Result<Repl, Box<dyn Error>> {
+        let python = self.venv_python();
+        let mut child = Command::new(&python)
+            .args(["-u", "-c", REPL])
+            .env("PYTHONIOENCODING", "utf-8")
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .map_err(|e| format!("Failed to launch {}: {}", python.display(), e))?;
+        let stdin = Arc::new(Mutex::new(child.stdin.take().ok_or("Python stdin is not piped")?));
+        let stdout = child.stdout.take().ok_or("Python stdout is not piped")?;
+        if let Some(stderr) = child.stderr.take() {
+            forward_output(stderr);
+        }
+        let (sender, responses) = mpsc::channel();
+        let capabilities = Arc::clone(&self.capabilities);
+        let replies = Arc::clone(&stdin);
+        // Checks are answered here, so threads a snippet left running get answers too.
+        thread::spawn(move || {
+            let mut lines = BufReader::new(stdout).lines().map_while(Result::ok);
+            // Sent before any snippet runs, so no snippet can choose what is trusted.
+            let trusted = match lines.next().map(|line| serde_json::from_str::<Trusted>(&line)) {
+                Some(Ok(Trusted { trusted })) => trusted,
+                _ => Vec::new(),
+            };
+            for line in lines {
+                if let Ok(CheckRequest { check }) = serde_json::from_str(&line) {
+                    let error = answer(&*capabilities, &trusted, &check).err();
+                    let reply = serde_json::json!({ "reply": check.id, "error": error });
+                    let mut stdin = replies.lock().unwrap();
+                    if writeln!(stdin, "{}", reply).and_then(|_| stdin.flush()).is_err() {
+                        break;
+                    }
+                } else if sender.send(line).is_err() {
+                    break;
+                }
+            }
+        });
+        Ok(Repl {
+            child,
+            stdin,
+            responses,
+        })
+    }
+
+    /// Sends `code` to the interpreter and waits for its response, collecting
+    /// what it prints into `output` on the way.
+    fn request(&self, repl: &mut Repl, code: &str, output: &mut Output) -> Result<Option<Response>, Box<dyn Error>> {
+        let request = serde_json::json!({ "code": code });
+        {
+            let mut stdin = repl.stdin.lock().unwrap();
+            writeln!(stdin, "{}", request).and_then(|_| stdin.flush())?;
+        }
+        let deadline = self.timeout.map(|timeout| Instant::now() + timeout);
+        loop {
+            let line = match deadline {
+                Some(deadline) => repl
+                    .responses
+                    .recv_timeout(deadline.saturating_duration_since(Instant::now())),
+                None => repl.responses.recv().map_err(|_| RecvTimeoutError::Disconnected),
+            };
+            let line = match line {
+                Ok(line) => line,
+                Err(RecvTimeoutError::Timeout) => return Ok(None),
+                Err(RecvTimeoutError::Disconnected) => return Err("Python interpreter exited".into()),
+            };
+            match serde_json::from_str(&line) {
+                Ok(Printed { output: stream, text }) if stream == "stderr" => output.stderr += &text,
+                Ok(Printed { text, .. }) => output.stdout += &text,
+                Err(_) => {
+                    return Ok(Some(
+                        serde_json::from_str(&line).map_err(|e| format!("Invalid response from Python: {}", e))?,
+                    ));
+                }
+            }
+        }
+    }
+}
+
+impl VirtualEnv for VlmPython {
+    fn name(&self, _addr: (VlmHost, VlmPort)) -> &str {
+        "python"
+    }
+
+    fn is_active(&self) -> Result<bool, Box<dyn Error>> {
+        match self.repl.borrow_mut().as_mut() {
+            Some(repl) => Ok(repl.child.try_wait()?.is_none()),
+            None => Ok(false),
+        }
+    }
+
+    fn init(&self) -> Result<(), Box<dyn Error>> {
+        if self.is_active()? {
+            return Ok(());
+        }
+        self.ensure_venv()?;
+        self.install()?;
+        *self.repl.borrow_mut() = Some(self.spawn()?);
+        Ok(())
+    }
+
+    fn run_code(&self, code: &str) -> Result<VlmExecution, Box<dyn Error>> {
+        self.init()?;
+        let started = Instant::now();
+        let mut slot = self.repl.borrow_mut();
+        let repl = slot.as_mut().ok_or("Python environment is not initialized")?;
+        let mut output = Output::default();
+        let response = match self.request(repl, code, &mut output) {
+            Ok(Some(response)) => response,
+            Ok(None) => {
+                slot.take();
+                return Ok(failed(
+                    VlmRunError::Timeout {
+                        limit: self.timeout.unwrap_or_default(),
+                    },
+                    output,
+                    started,
+                ));
+            }
+            // The snippet took the interpreter down, e.g. with os._exit().
+            Err(_) => {
+                let status = repl.child.wait()?;
+                slot.take();
+                let status = match status.code() {
+                    Some(code) => VlmExitStatus::Exited { code },
+                    None => VlmExitStatus::Failed {
+                        error: VlmRunError::Runtime {
+                            message: format!("Python interpreter was terminated ({})", status),
+                            traceback: None,
+                        },
+                    },
+                };
+                return Ok(VlmExecution {
+                    stdout: output.stdout,
+                    stderr: output.stderr,
+                    status,
+                    value: None,
+                    duration: started.elapsed(),
+                });
+            }
+        };
+        let status = match response.status.as_str() {
+            "success" => VlmExitStatus::Success,
+            "exited" => VlmExitStatus::Exited {
+                code: response.code.unwrap_or_default(),
+            },
+            "syntax" => VlmExitStatus::Failed {
+                error: VlmRunError::Syntax {
+                    message: response.message.unwrap_or_default(),
+                    line: response.line,
+                },
+            },
+            _ => VlmExitStatus::Failed {
+                error: VlmRunError::Runtime {
+                    message: response.message.unwrap_or_default(),
+                    traceback: response.traceback.filter(|traceback| !traceback.is_empty()),
+                },
+            },
+        };
+        // `sys.exit()` ends the session the same way it would end a script.
+        if matches!(status, VlmExitStatus::Exited { .. }) {
+            slot.take();
+        }
+        Ok(VlmExecution {
+            stdout: output.stdout,
+            stderr: output.stderr,
+            status,
+            value: response.value,
+            duration: started.elapsed(),
+        })
+    }
+}
+
+/// Checks an access of the interpreter, failing with the message to raise.
+///
+/// Reads within `trusted`, where the interpreter's own modules live, are always allowed.
+fn answer(capabilities: &dyn VlmCapabilityCheck, trusted: &[PathBuf], check: &Check) -> Result<(), String> {
+    let capability = match check.capability.as_str() {
+        "read" => VlmCapability::Read,
+        "write" => VlmCapability::Write,
+        "net" => VlmCapability::Net,
+        _ => return Err(format!("Unknown capability {}", check.capability)),
+    };
+    match (capability, &check.path, &check.host) {
+        (VlmCapability::Net, _, Some(host)) => capabilities.check_host("python", &check.op, host),
+        (VlmCapability::Read | VlmCapability::Write, Some(path), _) => {
+            let path = match &check.cwd {
+                Some(cwd) => Path::new(cwd).join(path),
+                None => PathBuf::from(path),
+            };
+            let canonical = path.canonicalize().unwrap_or_else(|_| path.clone());
+            if capability == VlmCapability::Read && trusted.iter().any(|dir| canonical.starts_with(dir)) {
+                return Ok(());
+            }
+            capabilities
+                .check_path("python", &check.op, capability, &path.to_string_lossy(), check.follow)
+                .map(drop)
+        }
+        _ => Err(format!("Invalid {} check from {}", capability, check.op)),
+    }
+}
+
+fn failed(error: VlmRunError, output: Output, started: Instant) -> VlmExecution {
+    VlmExecution {
+        stdout: output.stdout,
+        stderr: output.stderr,
+        status: VlmExitStatus::Failed { error },
+        value: None,
+        duration: started.elapsed(),
+    }
+}
+
+/// Runs a setup command, failing with its stderr.
+fn run(command: &mut Command, what: &str) -> Result<(), Box<dyn Error>> {
+    let output = command
+        .stdin(Stdio::null())
+        .output()
+        .map_err(|e| format!("Failed to run {}: {}", what, e))?;
+    match output.status.success() {
+        true => Ok(()),
+        false => Err(format!("{} failed: {}", what, String::from_utf8_lossy(&output.stderr).trim()).into()),
+    }
+}
+
+/// Fails on the first requirement `--no-index` would not keep off the network.
+fn check_requirements(path: &Path, contents: &str) -> Result<(), String> {
+    let mut requirement = String::new();
+    let mut first = 1;
+    for (index, line) in contents.lines().enumerate() {
+        if requirement.is_empty() {
+            first = index + 1;
+        }
+        // A trailing backslash continues the requirement on the next line.
+        if let Some(line) = line.strip_suffix('\\') {
+            requirement += line;
+            continue;
+        }
+        requirement += line;
+        if let Some(reason) = remote_requirement(&std::mem::take(&mut requirement)) {
+            return Err(format!(
+                "{} line {}: {} are not allowed, packages only come from local files",
+                path.display(),
+                first,
+                reason
+            ));
+        }
+    }
+    Ok(())
+}
+
+/// What makes a requirements file line reach beyond local files, if anything.
+fn remote_requirement(line: &str) -> Option<&'static str> {
+    let line = match line.find('#') {
+        Some(0) => "",
+        Some(at) if line[..at].ends_with(char::is_whitespace) => &line[..at],
+        _ => line,
+    };
+    let line = line.trim().to_ascii_lowercase();
+    if line.starts_with('-') {
+        let option = match line.strip_prefix("--") {
+            Some(long) => long
+                .split(|c: char| c == '=' || c.is_whitespace())
+                .next()
+                .unwrap_or_default(),
+            None => &line[1..line.len().min(2)],
+        };
+        return match option {
+            "e" | "editable" => Some("editable requirements"),
+            "i" | "index-url" | "extra-index-url" => Some("package indexes"),
+            "f" | "find-links" => Some("find-links options"),
+            "r" | "requirement" | "c" | "constraint" => Some("nested requirements files"),
+            _ => None,
+        };
+    }
+    if ["git+", "hg+", "svn+", "bzr+"].iter().any(|vcs| line.starts_with(vcs)) {
+        return Some("VCS requirements");
+    }
+    let url = line
+        .split(|c: char| c == '@' || c == ';' || c.is_whitespace())
+        .any(|part| part.contains("://") && !part.starts_with("file://"));
+    match url {
+        true => Some("URL requirements"),
+        false => None,
+    }
+}
+
+/// The wheels in `wheelhouse`, sorted by name.
+fn wheels(wheelhouse: &Path) -> Result<Vec<PathBuf>, Box<dyn Error>> {
+    let mut wheels = fs::read_dir(wheelhouse)
+        .map_err(|e| format!("Failed to read {}: {}", wheelhouse.display(), e))?
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| path.extension().is_some_and(|extension| extension == "whl"))
+        .collect::<Vec<_>>();
+    wheels.sort();
+    Ok(wheels)
+}
+
+/// Logs what the interpreter writes outside of snippets, such as output of subprocesses.
+fn forward_output(stream: impl Read + Send + 'static) {
+    thread::spawn(move || {
+        for line in BufReader::new(stream).lines().map_while(Result::ok) {
+            log::warn!(target: "vlm::python", "{}", line);
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn only_local_requirements_pass() {
+        for line in [
+            "requests==2.31",
+            "numpy>=1.24; python_version >= \"3.9\"",
+            "# git+https://example.com/commented.git",
+            "pkg @ file:///wheels/pkg-1.0-py3-none-any.whl",
+            "./wheels/pkg-1.0-py3-none-any.whl",
+            "--no-index",
+            "",
+        ] {
+            assert_eq!(remote_requirement(line), None, "{}", line);
+        }
+        for (line, reason) in [
+            ("pkg @ https://example.com/pkg.whl", "URL requirements"),
+            ("https://example.com/pkg-1.0.tar.gz", "URL requirements"),
+            ("git+https://example.com/pkg.git", "VCS requirements"),
+            ("pkg@git+ssh://git@example.com/pkg.git", "URL requirements"),
+            ("-e git+https://example.com/pkg.git#egg=pkg", "editable requirements"),
+            ("--index-url=https://example.com/simple", "package indexes"),
+            ("--extra-index-url https://example.com/simple", "package indexes"),
+            ("-f https://example.com/wheels", "find-links options"),
+            ("-r other.txt", "nested requirements files"),
+        ] {
+            assert_eq!(remote_requirement(line), Some(reason), "{}", line);
+        }
+    }
+
+    #[test]
+    fn refused_requirements_name_their_line() {
+        let contents = "requests==2.31\nflask \\\n  @ https://example.com/flask.whl\n";
+        let error = check_requirements(Path::new("requirements.txt"), contents).unwrap_err();
+        assert_eq!(
+            error,
+            "requirements.txt line 2: URL requirements are not allowed, packages only come from local files"
+        );
+        assert!(check_requirements(Path::new("requirements.txt"), "requests==2.31\n").is_ok());
+    }
+
+    #[test]
+    fn output_survives_the_interpreter_exiting() {
+        if VlmPython::find_python().is_err() {
+            return;
+        }
+        let dir = env::temp_dir().join(format!("vlm-python-exit-{}", std::process::id()));
+        let python = VlmPython::new(&dir);
+        let execution = python
+            .run_code("import os, sys\nprint('before')\nprint('oops', file=sys.stderr)\nos._exit(3)")
+            .unwrap();
+        assert_eq!(execution.stdout, "before\n");
+        assert_eq!(execution.stderr, "oops\n");
+        assert_eq!(execution.status, VlmExitStatus::Exited { code: 3 });
+
+        let execution = python.run_code("print('again')\n1 + 1").unwrap();
+        assert_eq!(execution.stdout, "again\n");
+        assert_eq!(execution.value, Some(serde_json::json!(2)));
+        drop(python);
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    /// The message a snippet failed with, or its value when it did not fail.
+    fn outcome(python: &VlmPython, code: &str) -> String {
+        let execution = python.run_code(code).unwrap();
+        match execution.into_result() {
+            Ok(value) => format!("ok: {}", value.unwrap_or_default()),
+            Err(error) => error.to_string(),
+        }
+    }
+
+    #[test]
+    fn snippets_cannot_switch_the_hook_off() {
+        if VlmPython::find_python().is_err() {
+            return;
+        }
+        let dir = env::temp_dir().join(format!("vlm-python-hook-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let secret = dir.join("secret.txt");
+        fs::write(&secret, "secret").unwrap();
+        let read = format!("open({:?}).read()", secret.to_str().unwrap());
+        let python = VlmPython::new(dir.join("cache"));
+        let denied = format!(
+            "Runtime error: PermissionError: Requires read access to \"{}\"",
+            secret.display()
+        );
+        assert_eq!(outcome(&python, &read), denied);
+
+        let attacks = [
+            // The REPL's own module and its globals are out of reach.
+            "import sys\ntry:\n    sys.modules['__main__'].checking.active = True\nexcept AttributeError:\n    pass",
+            "import sys\nfor name in ('checking', 'resolved', 'TRUSTED', 'replies'):\n    assert name not in vars(sys.modules['__main__'])",
+            // Patching what the hook calls changes nothing.
+            "import builtins, os, json, posixpath\nbuiltins.issubclass = builtins.isinstance = lambda *args: False\n\
+             os.getcwd = posixpath.realpath = lambda *args: '/'",
+        ];
+        for attack in attacks {
+            python.reset();
+            assert_eq!(outcome(&python, attack), "ok: null", "{}", attack);
+            assert_eq!(outcome(&python, &read), denied, "{}", attack);
+        }
+
+        python.reset();
+        // Walking up from a snippet ends at the thread it runs on.
+        let callers = "import sys\nf, names = sys._getframe(0), []\nwhile f:\n    names.append(f.f_code.co_name)\n    \
+                       f = f.f_back\nnames";
+        assert_eq!(outcome(&python, callers), r#"ok: ["<module>","execute","run"]"#);
+        // The frames of a check that failed are not handed out.
+        let hook_frame = format!(
+            "try:\n    {}\nexcept PermissionError as e:\n    tb = e.__traceback__\nwhile tb.tb_next:\n    tb = tb.tb_next\ntb.tb_frame",
+            read
+        );
+        assert_eq!(
+            outcome(&python, &hook_frame),
+            "Runtime error: PermissionError: Frames of the REPL are not available"
+        );
+        // Tracebacks stop short of them instead of failing.
+        let formatted = format!(
+            "import traceback\ntry:\n    {}\nexcept PermissionError:\n    formatted = traceback.format_exc()\n\
+             formatted.count('File ')",
+            read
+        );
+        assert_eq!(outcome(&python, &formatted), "ok: 1");
+        // Refused hooks are dropped silently, as Python does for any hook raising an Exception.
+        let hook =
+            "import sys\nseen = []\nsys.addaudithook(lambda event, args: seen.append(event))\nsys.audit('x')\nseen";
+        assert_eq!(outcome(&python, hook), "ok: []");
+        for (code, message) in [
+            ("import gc\ngc.get_referrers(open)", "gc.get_referrers is not allowed"),
+            ("import sys\nsys.settrace(lambda *args: None)", "sys.settrace is not allowed"),
+            ("import _posixsubprocess\n_posixsubprocess.fork_exec()", "_posixsubprocess.fork_exec is not allowed"),
+            (
+                "import sys\ndel sys.modules['_posixsubprocess']\nimport _posixsubprocess",
+                "_posixsubprocess is not allowed",
+            ),
+            (
+                "import socket\nsocket.socket(socket.AF_INET, socket.SOCK_DGRAM).sendmsg([b'x'], [], 0, ('127.0.0.1', 9))",
+                "Requires net access to \"127.0.0.1:9\"",
+            ),
+        ] {
+            assert_eq!(outcome(&python, code), format!("Runtime error: PermissionError: {}", message), "{}", code);
+        }
+        drop(python);
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
@@ -0,0 +1,398 @@
+# REPL driven by vlm's VlmPython: one JSON request per line on stdin, one JSON
+# response per line on the original stdout. Snippets share one namespace, the
+# __main__ module they see, and each runs on a thread of its own.
+#
+# The first line sent is {"trusted": [...]}, the interpreter's own directories,
+# which the host lets snippets read without asking.
+#
+# What a snippet prints is sent as it is written, as {"output": "stdout" or
+# "stderr", "text": ...} lines ahead of the response, so the host keeps it even
+# when the snippet takes the interpreter down.
+#
+# File system, network and process access is checked through an audit hook:
+# the hook writes {"check": {...}} on the response stream and waits for the
+# {"reply": id, "error": ...} line the host sends back on stdin.
+#
+# Snippets must not be able to talk the hook out of a check, so everything it
+# relies on lives in the closure of main(), bound before any snippet runs, and
+# it only calls functions implemented in C. A frame of the hook or of the REPL
+# threads would hand that closure out, so getting one is refused, as are the
+# garbage collector's object lists and tracing; tracebacks end where the hook
+# starts.
+import _json
+import _queue
+import _socket
+import _thread
+import ast
+import contextlib
+import io
+import itertools
+import json
+import os
+import sys
+import traceback
+import types
+
+requests = os.fdopen(os.dup(0), "r", encoding="utf-8")
+responses = os.fdopen(os.dup(1), "w", encoding="utf-8")
+# Keep snippets, and anything they spawn, off the protocol streams.
+os.dup2(os.open(os.devnull, os.O_RDONLY), 0)
+os.dup2(2, 1)
+sys.stdin = io.StringIO()
+# Modules come from the venv, not from whatever the working directory holds.
+sys.path = [path for path in sys.path if path]
+sys.dont_write_bytecode = True
+
+write_lock = _thread.allocate_lock()
+
+WRITE_FLAGS = os.O_WRONLY | os.O_RDWR | os.O_APPEND | os.O_CREAT | os.O_TRUNC
+# event: (capability, follow links, indexes of the path arguments)
+PATH_EVENTS = {
+    "os.listdir": ("read", True, (0,)),
+    "os.scandir": ("read", True, (0,)),
+    "os.mkdir": ("write", True, (0,)),
+    "os.remove": ("write", False, (0,)),
+    "os.rmdir": ("write", False, (0,)),
+    "os.rename": ("write", False, (0, 1)),
+    "os.link": ("write", False, (1,)),
+    "os.symlink": ("write", False, (1,)),
+    "os.truncate": ("write", True, (0,)),
+    "os.chmod": ("write", True, (0,)),
+    "os.chown": ("write", True, (0,)),
+    "os.utime": ("write", True, (0,)),
+    "shutil.rmtree": ("write", False, (0,)),
+}
+NET_EVENTS = {"socket.connect", "socket.sendto", "socket.sendmsg", "socket.bind"}
+# Attributes handing out a frame, checked like sys._getframe().
+FRAME_ATTRIBUTES = {
+    "tb_frame": types.TracebackType,
+    "gi_frame": types.GeneratorType,
+    "cr_frame": types.CoroutineType,
+    "ag_frame": types.AsyncGeneratorType,
+}
+# Neither child processes nor native code could be checked, and the rest would
+# reach the hook's state.
+REFUSED = {
+    "subprocess.Popen",
+    "os.system",
+    "os.exec",
+    "os.posix_spawn",
+    "os.spawn",
+    "os.fork",
+    "os.forkpty",
+    "os.kill",
+    "os.killpg",
+    "pty.spawn",
+    "ctypes.dlopen",
+    "ctypes.dlsym",
+    "ctypes.call_function",
+    "ctypes.cdata",
+    "ctypes.PyObj_FromPtr",
+    "sys.addaudithook",
+    "sys._current_frames",
+    "sys.settrace",
+    "sys.setprofile",
+    "sys._settraceallthreads",
+    "sys._setprofileallthreads",
+    "sys.monitoring.register_callback",
+    "gc.get_objects",
+    "gc.get_referrers",
+    "gc.get_referents",
+}
+
+
+def send(message):
+    line = json.dumps(message) + "\n"
+    with write_lock:
+        responses.write(line)
+        responses.flush()
+
+
+class Output(io.TextIOBase):
+    def __init__(self, stream):
+        self.stream = stream
+
+    def writable(self):
+        return True
+
+    def write(self, text):
+        if text:
+            send({"output": self.stream, "text": text})
+        return len(text)
+
+
+def to_json(value):
+    try:
+        json.dumps(value, allow_nan=False)
+        return value
+    except (TypeError, ValueError):
+        return repr(value)
+
+
+def exit_code(error):
+    if error.code is None:
+        return 0
+    if isinstance(error.code, int):
+        return error.code
+    print(error.code, file=sys.stderr)
+    return 1
+
+
+def visible(walk):
+    # The hook's frames are not handed out, so tracebacks end where it starts.
+    def walk_visible(tb):
+        entries = walk(tb)
+        while True:
+            try:
+                yield next(entries)
+            except (StopIteration, PermissionError):
+                return
+
+    return walk_visible
+
+
+for name in ("walk_tb", "_walk_tb_with_full_positions"):
+    if hasattr(traceback, name):
+        setattr(traceback, name, visible(getattr(traceback, name)))
+
+
+def execute(code, namespace):
+    try:
+        tree = ast.parse(code, "<run_code>", "exec")
+    except SyntaxError as e:
+        return {"status": "syntax", "message": e.msg, "line": e.lineno}
+    last = None
+    if tree.body and isinstance(tree.body[-1], ast.Expr):
+        last = ast.Expression(tree.body.pop().value)
+    response = {"status": "success"}
+    with contextlib.redirect_stdout(Output("stdout")), contextlib.redirect_stderr(Output("stderr")):
+        try:
+            exec(compile(tree, "<run_code>", "exec"), namespace)
+            if last is not None:
+                value = eval(compile(last, "<run_code>", "eval"), namespace)
+                response["value"] = None if value is None else to_json(value)
+        except SystemExit as e:
+            response = {"status": "exited", "code": exit_code(e)}
+        except BaseException as e:
+            response = {
+                "status": "error",
+                "message": "".join(traceback.format_exception_only(type(e), e)).strip(),
+                "traceback": "".join(traceback.format_tb(e.__traceback__.tb_next)),
+            }
+    return response
+
+
+def run(code, namespace, done):
+    # The first frame of its thread, so a snippet walking up its callers finds nothing else.
+    try:
+        response = json.dumps(execute(code, namespace))
+    except BaseException as e:
+        response = json.dumps({"status": "error", "message": "Invalid result: %s" % type(e).__name__})
+    done.put(response)
+
+
+def main():
+    # Bound once: snippets may patch modules and builtins, but not these names.
+    getcwd, urandom, getaddrinfo = os.getcwd, os.urandom, _socket.getaddrinfo
+    family_of, internet = _socket.socket.family.__get__, (_socket.AF_INET, _socket.AF_INET6)
+    SimpleQueue, start_thread, local = _queue.SimpleQueue, _thread.start_new_thread, _thread._local()
+    scan = _json.make_scanner(json.JSONDecoder())
+    encode_string, make_encoder = _json.encode_basestring_ascii, _json.make_encoder
+    join, str_eq, str_contains, encode = "".join, str.__eq__, str.__contains__, str.encode
+    decode, int_of, int_and, int_repr, item = bytes.decode, int.__pos__, int.__and__, int.__repr__, tuple.__getitem__
+    str_type, bytes_type, int_type, tuple_type, FrameType = str, bytes, int, tuple, types.FrameType
+    type_of, subclass, getattr_, iter_, len_, list_, deny = type, issubclass, getattr, iter, len, list, PermissionError
+    lookup_errors, read_only = (OSError, UnicodeError), types.MappingProxyType
+    # Copies, since the module-level tables are within a snippet's reach.
+    write_flags, path_events, net_events = WRITE_FLAGS, read_only(dict(PATH_EVENTS)), frozenset(NET_EVENTS)
+    frame_attributes, refused = read_only(dict(FRAME_ATTRIBUTES)), frozenset(REFUSED)
+    lines, stream, lock, runner = requests, responses, write_lock, run
+
+    codes = SimpleQueue()
+    replies = {}
+    resolved = set()
+
+    def write(message):
+        line = join(make_encoder(None, None, encode_string, None, ":", ",", False, False, False)(message, 0))
+        with lock:
+            stream.write(line + "\n")
+            stream.flush()
+
+    def read_requests():
+        for line in lines:
+            message = scan(line, 0)[0]
+            if "reply" in message:
+                reply = replies.pop(message["reply"], None)
+                if reply is not None:
+                    reply.put(message.get("error"))
+            else:
+                codes.put(message["code"])
+        codes.put(None)
+        for reply in list_(replies.values()):
+            reply.put("The host is gone")
+
+    def ask(event, check):
+        # Random ids, so lines a snippet forges cannot answer a real check.
+        check["id"] = urandom(16).hex()
+        check["op"] = event
+        reply = replies[check["id"]] = SimpleQueue()
+        write({"check": check})
+        error = reply.get()
+        if error is not None:
+            raise deny(error)
+
+    def text(value):
+        # An exact str for str and bytes values, running no code of theirs.
+        if subclass(type_of(value), str_type):
+            return join((value,))
+        if subclass(type_of(value), bytes_type):
+            return decode(value, "utf-8", "surrogateescape")
+        if subclass(type_of(value), int_type):
+            return int_repr(value)
+        return None
+
+    def check_path(event, capability, follow, path):
+        if path is None and event in ("os.listdir", "os.scandir"):
+            path = "."
+        if path is None or subclass(type_of(path), int_type):
+            return
+        name = text(path)
+        if name is None:
+            if str_eq(event, "shutil.rmtree"):
+                # Each removal it makes is checked on its own.
+                return
+            raise deny("%s: unsupported path" % event)
+        ask(event, {"capability": capability, "path": name, "cwd": getcwd(), "follow": follow})
+
+    def host(name, port):
+        if str_contains(name, ":"):
+            name = "[" + name + "]"
+        return name if port is None else name + ":" + port
+
+    def check_address(event, sock, address):
+        if address is None and str_eq(event, "socket.sendmsg"):
+            # Sent on the connection, which was checked when it was made.
+            return
+        if subclass(type_of(address), (str_type, bytes_type)):
+            # A Unix socket: connecting writes to the file.
+            if len_(address):
+                check_path(event, "write", True, address)
+            return
+        if not subclass(type_of(address), tuple_type) or family_of(sock) not in internet:
+            raise deny("%s: unsupported address family" % event)
+        name, port = text(item(address, 0)), int_of(item(address, 1))
+        if name is None or subclass(type_of(item(address, 0)), int_type):
+            raise deny("%s: unsupported address" % event)
+        if (name, port) not in resolved:
+            ask(event, {"capability": "net", "host": host(name, int_repr(port))})
+
+    def check_lookup(name, port):
+        name, port = text(name), text(port)
+        if name is None:
+            return
+        ask("socket.getaddrinfo", {"capability": "net", "host": host(name, port)})
+        # Connecting to what the allowed name resolves to needs no second check.
+        local.inside = True
+        try:
+            infos = getaddrinfo(encode(name, "ascii"), None if port is None else encode(port, "ascii"))
+        except lookup_errors:
+            infos = ()
+        finally:
+            local.inside = False
+        for info in infos:
+            resolved.add((text(item(item(info, 4), 0)), int_of(item(item(info, 4), 1))))
+
+    def reveals(frame):
+        # Whether a frame is one of the REPL's, or called from one.
+        local.inside = True
+        try:
+            while frame is not None:
+                if frame.f_code in hidden:
+                    return True
+                frame = frame.f_back
+            return False
+        finally:
+            local.inside = False
+
+    def writes(mode, flags):
+        if int_and(flags, write_flags):
+            return True
+        if not subclass(type_of(mode), str_type):
+            return False
+        for c in "wax+":
+            if str_contains(mode, c):
+                return True
+        return False
+
+    def audit(event, args):
+        if getattr_(local, "inside", False):
+            return
+        if str_eq(event, "open"):
+            path, mode, flags = args
+            check_path(event, "write" if writes(mode, flags) else "read", True, path)
+        elif event in path_events:
+            capability, follow, indexes = path_events[event]
+            for index in indexes:
+                check_path(event, capability, follow, item(args, index))
+        elif event in net_events:
+            check_address(event, item(args, 0), item(args, 1))
+        elif str_eq(event, "socket.getaddrinfo"):
+            check_lookup(item(args, 0), item(args, 1))
+        elif str_eq(event, "sys._getframe"):
+            if type_of(item(args, 0)) is FrameType and reveals(item(args, 0)):
+                raise deny("Frames of the REPL are not available")
+        elif str_eq(event, "object.__getattr__"):
+            owner, name = item(args, 0), text(item(args, 1))
+            if name in frame_attributes and type_of(owner) is frame_attributes[name]:
+                local.inside = True
+                try:
+                    frame = getattr_(owner, name)
+                finally:
+                    local.inside = False
+                if type_of(frame) is FrameType and reveals(frame):
+                    raise deny("Frames of the REPL are not available")
+        elif str_eq(event, "import") and str_eq(text(item(args, 0)) or "", "_posixsubprocess"):
+            raise deny("_posixsubprocess is not allowed")
+        elif event in refused:
+            raise deny("%s is not allowed" % event)
+
+    def fork_exec(*args):
+        raise deny("_posixsubprocess.fork_exec is not allowed")
+
+    # fork_exec raises no audit event, so it is replaced wherever it is bound,
+    # and loading the module again is refused by the hook.
+    import _posixsubprocess
+    import subprocess
+
+    _posixsubprocess.fork_exec = fork_exec
+    subprocess._fork_exec = fork_exec
+
+    def nested(code):
+        yield code
+        for constant in code.co_consts:
+            if isinstance(constant, types.CodeType):
+                yield from nested(constant)
+
+    hidden = frozenset(itertools.chain(nested(sys._getframe(0).f_code), (sys._getframe(1).f_code,)))
+
+    # Snippets get a __main__ of their own, not this script's module.
+    snippets = types.ModuleType("__main__")
+    snippets.__builtins__ = __builtins__
+    sys.modules["__main__"] = snippets
+
+    prefixes = {os.path.realpath(prefix) for prefix in (sys.prefix, sys.base_prefix, sys.exec_prefix)}
+    write({"trusted": sorted(prefixes)})
+    start_thread(read_requests, ())
+    sys.addaudithook(audit)
+    for code in iter_(codes.get, None):
+        done = SimpleQueue()
+        start_thread(runner, (code, snippets.__dict__, done))
+        response = done.get()
+        if type_of(response) is not str_type:
+            response = '{"status": "error", "message": "Invalid result"}'
+        with lock:
+            stream.write(response + "\n")
+            stream.flush()
+
+
+main()